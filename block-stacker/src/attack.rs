@@ -0,0 +1,199 @@
+//////////////////////////////////////////////////////////////////////////////////////////
+// Attack accounting
+
+/// The kind of T-spin performed by a placement.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Spin {
+    #[default]
+    None,
+    Mini,
+    Full,
+}
+
+/// Result of locking a piece with `Stacker::hard_drop`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Lock {
+    /// Number of lines cleared.
+    pub lines: usize,
+    /// Number of garbage lines among the lines cleared.
+    pub garbage: usize,
+    /// T-spin performed by the placement.
+    pub spin: Spin,
+    /// `Some(n)` if lines were cleared, where `n` is the number of consecutive line
+    /// clears before this one.
+    pub combo: Option<usize>,
+    /// `true` if the line clear got the back-to-back bonus.
+    pub b2b: bool,
+    /// `true` if the line clear emptied the matrix.
+    pub perfect_clear: bool,
+    /// Number of garbage lines sent, according to the guideline attack table.
+    pub attack: usize,
+}
+
+/// Combo and back-to-back state, carried between placements.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Chain {
+    /// `Some(n)` if the last placement cleared lines, where `n` is the number of
+    /// consecutive line clears before it.
+    pub combo: Option<usize>,
+    /// `true` if the last line clear was a tetris or T-spin, so that the next one may get
+    /// the back-to-back bonus.
+    pub b2b: bool,
+}
+
+/// Attack added by each combo, indexed by the number of consecutive line clears before
+/// the current one. Longer combos use the last entry.
+const COMBO_ATTACK: [usize; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+/// Attack added by a perfect clear.
+const PERFECT_CLEAR_ATTACK: usize = 10;
+
+impl Chain {
+    /// Updates the combo and back-to-back state after a placement that cleared `lines`
+    /// lines (`garbage` of which were garbage), returning the resulting `Lock`.
+    pub fn lock(&mut self, lines: usize, garbage: usize, spin: Spin, perfect_clear: bool) -> Lock {
+        if lines == 0 {
+            // back-to-back is only broken by line clears
+            self.combo = None;
+            return Lock {
+                spin,
+                ..Lock::default()
+            };
+        }
+        let combo = self.combo.map_or(0, |n| n + 1);
+        let difficult = lines >= 4 || spin != Spin::None;
+        let b2b = difficult && self.b2b;
+        self.combo = Some(combo);
+        self.b2b = difficult;
+
+        let mut attack = line_clear_attack(lines, spin);
+        attack += COMBO_ATTACK[std::cmp::min(combo, COMBO_ATTACK.len() - 1)];
+        if b2b {
+            attack += 1;
+        }
+        if perfect_clear {
+            attack += PERFECT_CLEAR_ATTACK;
+        }
+        Lock {
+            lines,
+            garbage,
+            spin,
+            combo: Some(combo),
+            b2b,
+            perfect_clear,
+            attack,
+        }
+    }
+}
+
+/// Returns the attack of clearing `lines` lines with `spin`, before any bonuses.
+fn line_clear_attack(lines: usize, spin: Spin) -> usize {
+    match (spin, lines) {
+        (_, 0) => 0,
+        (Spin::None, 1) => 0,
+        (Spin::None, 2) => 1,
+        (Spin::None, 3) => 2,
+        (Spin::None, _) => 4,
+        (Spin::Mini, n) if n < 3 => n - 1,
+        // a T-spin triple can only be reached with the last kick, so it is always full
+        (_, n) => 2 * n,
+    }
+}
+
+/// Classifies a T-spin by the 3-corner rule, given the `cells` of a T piece and
+/// `filled(di, dj)`, which tells if the cell at offset `(di, dj)` from the center of the
+/// T is filled. The spin is a mini unless both corners next to the pointed side of the
+/// T are filled. Pieces that are not shaped like a T never spin.
+pub fn corner_spin(cells: &[(i16, i16)], filled: impl Fn(i16, i16) -> bool) -> Spin {
+    if cells.len() != 4 {
+        // e.g. the T pentomino
+        return Spin::None;
+    }
+    let adjacent =
+        |(i0, j0): (i16, i16), (i1, j1): (i16, i16)| (i0 - i1).abs() + (j0 - j1).abs() == 1;
+    // the center is adjacent to the other three cells, and the point is the only one of
+    // those without a cell across from it
+    let center = match cells
+        .iter()
+        .find(|&&c| cells.iter().filter(|&&d| adjacent(c, d)).count() == 3)
+    {
+        Some(&c) => c,
+        None => return Spin::None,
+    };
+    let point = cells.iter().find(|&&(i, j)| {
+        adjacent(center, (i, j)) && !cells.contains(&(2 * center.0 - i, 2 * center.1 - j))
+    });
+    let (di, dj) = match point {
+        Some(&(i, j)) => (i - center.0, j - center.1),
+        None => return Spin::None,
+    };
+    let filled = |i: i16, j: i16| filled(center.0 + i, center.1 + j);
+    // (dj, di) is perpendicular to (di, dj), since one of them is zero
+    let front = [filled(di + dj, dj + di), filled(di - dj, dj - di)];
+    let back = [filled(-di + dj, -dj + di), filled(-di - dj, -dj - di)];
+    let corners = front.iter().chain(back.iter()).filter(|&&c| c).count();
+    if corners < 3 {
+        Spin::None
+    } else if front[0] && front[1] {
+        Spin::Full
+    } else {
+        Spin::Mini
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_clear_attack() {
+        assert_eq!(line_clear_attack(1, Spin::None), 0);
+        assert_eq!(line_clear_attack(4, Spin::None), 4);
+        assert_eq!(line_clear_attack(1, Spin::Mini), 0);
+        assert_eq!(line_clear_attack(2, Spin::Mini), 1);
+        assert_eq!(line_clear_attack(2, Spin::Full), 4);
+        assert_eq!(line_clear_attack(3, Spin::Full), 6);
+        assert_eq!(line_clear_attack(3, Spin::Mini), 6);
+    }
+
+    #[test]
+    fn test_corner_spin() {
+        // T pointing up, centered at (1, 1)
+        let cells = [(1, 0), (1, 1), (1, 2), (2, 1)];
+        let corners = |filled: &[(i16, i16)]| corner_spin(&cells, |i, j| filled.contains(&(i, j)));
+        assert_eq!(corners(&[(0, 0), (0, 2)]), Spin::None);
+        assert_eq!(corners(&[(0, 0), (0, 2), (2, 0)]), Spin::Mini);
+        assert_eq!(corners(&[(0, 0), (2, 0), (2, 2)]), Spin::Full);
+        assert_eq!(corners(&[(0, 0), (0, 2), (2, 0), (2, 2)]), Spin::Full);
+        // not a T
+        let cells = [(0, 0), (0, 1), (0, 2), (0, 3)];
+        assert_eq!(corner_spin(&cells, |_, _| true), Spin::None);
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut chain = Chain::default();
+        // tetris, then back-to-back TSD
+        assert_eq!(chain.lock(4, 0, Spin::None, false).attack, 4);
+        let lock = chain.lock(2, 0, Spin::Full, false);
+        assert_eq!(lock.combo, Some(1));
+        assert!(lock.b2b);
+        assert_eq!(lock.attack, 5);
+        // placing without clearing keeps back-to-back but breaks the combo
+        assert_eq!(chain.lock(0, 0, Spin::None, false).attack, 0);
+        assert_eq!(chain.combo, None);
+        assert!(chain.b2b);
+        // a single breaks back-to-back
+        let lock = chain.lock(1, 1, Spin::None, false);
+        assert_eq!((lock.combo, lock.b2b, lock.attack), (Some(0), false, 0));
+        assert!(!chain.b2b);
+        // long combo
+        for _ in 0..20 {
+            chain.lock(1, 0, Spin::None, false);
+        }
+        assert_eq!(chain.lock(1, 0, Spin::None, false).attack, 5);
+        // perfect clear
+        let mut chain = Chain::default();
+        assert_eq!(chain.lock(2, 0, Spin::None, true).attack, 11);
+    }
+}
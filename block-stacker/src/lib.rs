@@ -1,3 +1,4 @@
+mod attack;
 mod ruleset;
 mod stacker;
 
@@ -9,5 +10,6 @@ pub type PieceType = char;
 /// One of `"ILJSZTOGH "`
 pub type CellColor = char;
 
-pub use ruleset::Ruleset;
-pub use stacker::{Config, Stacker};
+pub use attack::{corner_spin, Chain, Lock, Spin};
+pub use ruleset::{Ruleset, RulesetSource};
+pub use stacker::{Config, GarbageConfig, Stacker};
@@ -46,6 +46,8 @@ pub struct CheeseCols {
     prng: PRNG,
     cols: u16,
     prev: Option<u16>,
+    // predetermined columns to take before using `prng`, in reverse order
+    pending: Vec<u16>,
 }
 
 impl CheeseCols {
@@ -54,6 +56,16 @@ impl CheeseCols {
             prng,
             cols,
             prev: None,
+            pending: vec![],
+        }
+    }
+
+    pub fn with_pending(prng: PRNG, cols: u16, pending: impl IntoIterator<Item = u16>) -> Self {
+        let mut pending = pending.into_iter().collect::<Vec<_>>();
+        pending.reverse();
+        Self {
+            pending,
+            ..Self::new(prng, cols)
         }
     }
 
@@ -62,6 +74,10 @@ impl CheeseCols {
     }
 
     pub fn next_col(&mut self) -> u16 {
+        if let Some(col) = self.pending.pop() {
+            self.prev = Some(col);
+            return col;
+        }
         let col = match self.prev {
             None => self.prng.gen_range(0, self.cols),
             Some(prev) => {
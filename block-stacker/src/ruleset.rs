@@ -1,6 +1,7 @@
 use super::PieceType;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 
 /// Represents a specification of the game rules, such as the matrix size, the number of
 /// previews, and the shape information for every mino.
@@ -38,6 +39,8 @@ enum Kick {
     CW(u8),
     /// `CCW(r)` indicates a counter-clockwise rotation ending at rotation `r`.
     CCW(u8),
+    /// `Flip(r)` indicates a 180 degree rotation ending at rotation `r`.
+    Flip(u8),
 }
 
 impl<'de> Deserialize<'de> for Kick {
@@ -50,12 +53,18 @@ impl<'de> Deserialize<'de> for Kick {
         match s.chars().nth(0) {
             Some('>') => s[1..].parse().map(Kick::CW).map_err(D::Error::custom),
             Some('<') => s[1..].parse().map(Kick::CCW).map_err(D::Error::custom),
+            Some('^') => s[1..].parse().map(Kick::Flip).map_err(D::Error::custom),
             _ => Err(D::Error::custom("bad kick specification")),
         }
     }
 }
 
 static GUIDELINE_BYTES: &[u8] = include_bytes!("../../support/guideline.json");
+static ARS_BYTES: &[u8] = include_bytes!("../../support/ars.json");
+static CLASSIC_BYTES: &[u8] = include_bytes!("../../support/classic.json");
+static SRS_PLUS_BYTES: &[u8] = include_bytes!("../../support/srs-plus.json");
+static SRS_X_BYTES: &[u8] = include_bytes!("../../support/srs-x.json");
+static PENTOMINO_BYTES: &[u8] = include_bytes!("../../support/pentomino.json");
 
 impl Ruleset {
     /// Returns a copy of the guideline rules.
@@ -63,6 +72,98 @@ impl Ruleset {
         serde_json::from_slice(GUIDELINE_BYTES).expect("BUG: guideline data is malformed!")
     }
 
+    /// Returns a copy of the guideline rules using the Arika rotation system (ARS): pieces
+    /// spawn flat side up and rest on the bottom of their bounding box, and kicks only try
+    /// one column to the right and then to the left. The I piece never kicks.
+    pub fn ars() -> Ruleset {
+        serde_json::from_slice(ARS_BYTES).expect("BUG: ARS data is malformed!")
+    }
+
+    /// Returns a copy of the guideline rules without any kicks, so pieces can only rotate
+    /// in place.
+    pub fn classic() -> Ruleset {
+        serde_json::from_slice(CLASSIC_BYTES).expect("BUG: classic data is malformed!")
+    }
+
+    /// Returns a copy of the guideline rules using SRS+, which has left/right symmetric I
+    /// kicks and 180 degree rotations.
+    pub fn srs_plus() -> Ruleset {
+        serde_json::from_slice(SRS_PLUS_BYTES).expect("BUG: SRS+ data is malformed!")
+    }
+
+    /// Returns a copy of the guideline rules using SRS-X, which is plain SRS extended with
+    /// 180 degree rotations that may kick up to three columns sideways.
+    pub fn srs_x() -> Ruleset {
+        serde_json::from_slice(SRS_X_BYTES).expect("BUG: SRS-X data is malformed!")
+    }
+
+    /// Returns a copy of the pentomino rules: the twelve five-cell pieces on a guideline
+    /// sized matrix, with simple kicks that try one column to each side and then one row
+    /// up.
+    pub fn pentomino() -> Ruleset {
+        serde_json::from_slice(PENTOMINO_BYTES).expect("BUG: pentomino data is malformed!")
+    }
+
+    /// Names of the bundled rulesets, see `builtin`.
+    pub const BUILTIN_NAMES: &[&str] =
+        &["guideline", "ars", "classic", "srs+", "srs-x", "pentomino"];
+
+    /// Returns the bundled ruleset called `name`, which is one of `BUILTIN_NAMES`.
+    pub fn builtin(name: &str) -> Option<Ruleset> {
+        match name {
+            "guideline" | "srs" => Some(Ruleset::guideline()),
+            "ars" => Some(Ruleset::ars()),
+            "classic" => Some(Ruleset::classic()),
+            "srs+" | "srs-plus" => Some(Ruleset::srs_plus()),
+            "srs-x" => Some(Ruleset::srs_x()),
+            "pentomino" => Some(Ruleset::pentomino()),
+            _ => None,
+        }
+    }
+
+    /// Parses a ruleset from JSON, in the same format as `support/guideline.json`. Fails
+    /// if the JSON is malformed or describes an inconsistent ruleset.
+    pub fn from_json(json: &str) -> serde_json::Result<Ruleset> {
+        use serde::de::Error;
+        let rules: Ruleset = serde_json::from_str(json)?;
+        rules.validate().map_err(serde_json::Error::custom)?;
+        Ok(rules)
+    }
+
+    /// Checks that the shapes in this ruleset fit in the matrix and can be told apart
+    /// from garbage.
+    fn validate(&self) -> Result<(), String> {
+        if self.cols == 0 || self.visible_rows > self.rows {
+            return Err("invalid matrix dimensions".to_string());
+        }
+        if self.minos.is_empty() {
+            return Err("ruleset has no pieces".to_string());
+        }
+        for (&typ, mino) in self.minos.iter() {
+            if !typ.is_ascii_alphabetic() || typ == 'G' || typ == 'H' {
+                return Err(format!("invalid piece type {:?}", typ));
+            }
+            let in_box = |&(i, j): &(u16, u16)| i < mino.width && j < mino.width;
+            if mino.coords.is_empty() || !mino.coords.iter().all(in_box) {
+                return Err(format!("invalid coords for piece {}", typ));
+            }
+            let in_matrix = self.coords(typ, 0).all(|(i, j)| {
+                let (i, j) = (i as i16 + mino.spawn.0, j as i16 + mino.spawn.1);
+                i >= 0 && j >= 0 && (i as usize) < self.rows && (j as usize) < self.cols
+            });
+            if !in_matrix {
+                return Err(format!("piece {} spawns outside of the matrix", typ));
+            }
+            let valid_kick = |kick: &Kick| match kick {
+                Kick::CW(r) | Kick::CCW(r) | Kick::Flip(r) => *r < 4,
+            };
+            if !mino.kicks.keys().all(valid_kick) {
+                return Err(format!("invalid kick table for piece {}", typ));
+            }
+        }
+        Ok(())
+    }
+
     fn mino(&self, typ: PieceType) -> &PolyMino {
         self.minos.get(&typ).expect("BUG: no such mino")
     }
@@ -82,22 +183,26 @@ impl Ruleset {
     }
 
     /// Returns the list of kick offsets for shape specified by `color`, when rotating
-    /// from orientation `rot0` to `rot`.
+    /// from orientation `rot0` to `rot`. The list is empty if the ruleset does not allow
+    /// that rotation.
     pub fn kicks<'a>(
         &'a self,
         typ: PieceType,
         rot0: i32,
         rot: i32,
     ) -> impl Iterator<Item = (i16, i16)> + 'a {
-        let kick = if rot0 < rot {
-            Kick::CW(normalize_rot(rot))
-        } else {
-            Kick::CCW(normalize_rot(rot))
+        let r = normalize_rot(rot);
+        let kick = match normalize_rot(rot - rot0) {
+            2 => Kick::Flip(r),
+            3 => Kick::CCW(r),
+            _ => Kick::CW(r),
         };
-        match self.mino(typ).kicks.get(&kick) {
-            Some(kicks) => kicks.as_slice(),
+        match (self.mino(typ).kicks.get(&kick), kick) {
+            (Some(kicks), _) => kicks.as_slice(),
+            // 180 degree rotations are only allowed if the ruleset has kicks for them
+            (None, Kick::Flip(_)) => &[],
             // in SRS the O kick table is empty, so this fallback case is used.
-            None => &[(0, 0)],
+            (None, _) => &[(0, 0)],
         }
         .iter()
         .cloned()
@@ -109,6 +214,40 @@ impl Ruleset {
     }
 }
 
+/// Describes where a ruleset came from, as stored in recorded games: either the name of a
+/// bundled ruleset, or the JSON of a custom ruleset, so that a recording loads the same
+/// rules no matter where the original file was.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RulesetSource {
+    /// One of `Ruleset::BUILTIN_NAMES`.
+    Builtin(String),
+    /// A ruleset in the same format as `support/guideline.json`.
+    Json(serde_json::Value),
+}
+
+impl RulesetSource {
+    /// Returns the bundled ruleset named `arg`, or else reads the JSON file at `arg`.
+    pub fn open(arg: &Path) -> serde_json::Result<Self> {
+        if let Some(name) = arg.to_str().filter(|name| Ruleset::builtin(name).is_some()) {
+            return Ok(RulesetSource::Builtin(name.to_string()));
+        }
+        let json = std::fs::read_to_string(arg).map_err(serde_json::Error::io)?;
+        serde_json::from_str(&json).map(RulesetSource::Json)
+    }
+
+    /// Loads the ruleset. Fails if the name is not a bundled ruleset, or if the JSON
+    /// describes an inconsistent ruleset.
+    pub fn load(&self) -> serde_json::Result<Ruleset> {
+        use serde::de::Error;
+        match self {
+            RulesetSource::Builtin(name) => Ruleset::builtin(name)
+                .ok_or_else(|| serde_json::Error::custom(format!("unknown ruleset {:?}", name))),
+            RulesetSource::Json(json) => Ruleset::from_json(&json.to_string()),
+        }
+    }
+}
+
 /// Rotates `coord`, `r` times, such that it is contained in a box with width/height `w`.
 ///
 /// For example: `rotate_coord((1, 3), 4, 3)` does the following:
@@ -158,11 +297,96 @@ mod test {
         assert_eq!(normalize_rot(-6), 2);
     }
 
+    #[test]
+    fn test_ruleset_from_json() {
+        let json = std::str::from_utf8(GUIDELINE_BYTES).unwrap();
+        let rules = Ruleset::from_json(json).unwrap();
+        assert_eq!((rules.cols, rules.previews), (10, 5));
+        assert_eq!(rules.types().count(), 7);
+
+        let narrow = json.replacen("\"cols\": 10", "\"cols\": 4", 1);
+        assert!(Ruleset::from_json(&narrow).is_err());
+        let garbage = json.replacen("\"J\"", "\"G\"", 1);
+        assert!(Ruleset::from_json(&garbage).is_err());
+        assert!(Ruleset::from_json("{}").is_err());
+    }
+
     #[test]
     fn test_guideline_o_kicks() {
         let rules = Ruleset::guideline();
-        for &r in &[-2, -1, 0, 1, 2, 3] {
+        // quarter rotations only, 180 degree rotations are not allowed
+        for &r in &[-2, 0, 2, 4] {
             assert_eq!(rules.kicks('O', 1, r).collect::<Vec<_>>(), [(0, 0)]);
         }
     }
+
+    #[test]
+    fn test_builtin_rulesets() {
+        for &name in &["guideline", "ars", "classic", "srs+", "srs-x"] {
+            let rules = Ruleset::builtin(name).unwrap();
+            assert_eq!(rules.validate(), Ok(()), "{}", name);
+            assert_eq!(rules.types().count(), 7, "{}", name);
+        }
+        assert!(Ruleset::builtin("nes").is_none());
+        for &name in Ruleset::BUILTIN_NAMES {
+            assert!(Ruleset::builtin(name).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_pentomino_ruleset() {
+        let rules = Ruleset::builtin("pentomino").unwrap();
+        assert_eq!(rules.validate(), Ok(()));
+        let mut types = rules.types().collect::<Vec<_>>();
+        types.sort_unstable();
+        assert_eq!(types.into_iter().collect::<String>(), "FILNPTUVWXYZ");
+        for typ in rules.types() {
+            for r in 0..4 {
+                assert_eq!(rules.coords(typ, r).count(), 5, "{}", typ);
+            }
+        }
+        // the I piece rotates inside a 5x5 box
+        let mut coords = rules.coords('I', 1).collect::<Vec<_>>();
+        coords.sort_unstable();
+        assert_eq!(coords, [(0, 2), (1, 2), (2, 2), (3, 2), (4, 2)]);
+    }
+
+    #[test]
+    fn test_flip_kicks() {
+        let guideline = Ruleset::guideline();
+        assert_eq!(guideline.kicks('T', 0, 2).count(), 0);
+        let srs_plus = Ruleset::srs_plus();
+        assert_eq!(srs_plus.kicks('T', 0, 2).count(), 6);
+        assert_eq!(srs_plus.kicks('T', 1, -1).next(), Some((0, 0)));
+        assert_eq!(srs_plus.kicks('O', 3, 5).collect::<Vec<_>>(), [(0, 0)]);
+        // quarter rotations still use the regular kick tables
+        assert_eq!(
+            srs_plus.kicks('T', 0, 1).collect::<Vec<_>>(),
+            guideline.kicks('T', 0, 1).collect::<Vec<_>>()
+        );
+        let classic = Ruleset::classic();
+        assert_eq!(classic.kicks('I', 0, 1).collect::<Vec<_>>(), [(0, 0)]);
+        assert_eq!(classic.kicks('I', 0, 2).count(), 0);
+    }
+
+    #[test]
+    fn test_ruleset_source() {
+        let src = RulesetSource::open(Path::new("srs+")).unwrap();
+        assert_eq!(src, RulesetSource::Builtin("srs+".to_string()));
+        assert_eq!(src.load().unwrap().kicks('T', 0, 2).count(), 6);
+
+        // custom rulesets are stored as JSON, not as the path they were read from
+        let path = std::env::temp_dir().join("block-stacker-test-ruleset.json");
+        let json = std::str::from_utf8(CLASSIC_BYTES).unwrap();
+        std::fs::write(&path, json).unwrap();
+        let src = RulesetSource::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(src, RulesetSource::Json(_)));
+        let stored = serde_json::to_string(&src).unwrap();
+        let src: RulesetSource = serde_json::from_str(&stored).unwrap();
+        assert_eq!(src.load().unwrap().kicks('I', 0, 2).count(), 0);
+
+        assert!(RulesetSource::Builtin("nes".to_string()).load().is_err());
+        assert!(RulesetSource::open(Path::new("no/such/ruleset.json")).is_err());
+    }
 }
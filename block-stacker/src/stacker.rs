@@ -1,4 +1,9 @@
-use crate::{random, ruleset::Ruleset, CellColor, PieceType};
+use crate::{
+    attack::{corner_spin, Chain, Lock, Spin},
+    random,
+    ruleset::Ruleset,
+    CellColor, PieceType,
+};
 use std::rc::Rc;
 
 //////////////////////////////////////////////////////////////////////////////////////////
@@ -13,6 +18,9 @@ pub struct Stacker {
     current: Option<Piece>,
     next: Next,
     held: Option<PieceType>,
+    // the current piece came out of hold, so it cannot be held again until it locks
+    has_held: bool,
+    chain: Chain,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -48,6 +56,8 @@ impl Stacker {
             current: None,
             next: Next::new(&rules, prng),
             held: None,
+            has_held: false,
+            chain: Chain::default(),
             rules,
             rng_seed,
         };
@@ -56,6 +66,70 @@ impl Stacker {
         stacker
     }
 
+    /// Constructs a new stacker game state with ruleset `rules`, like `new`, except that
+    /// pieces are taken from `pieces` and cheese hole columns are taken from `holes`. Once
+    /// either list runs out, the randomizer is used as usual.
+    pub fn with_sequence(
+        rules: Rc<Ruleset>,
+        cfg: Config,
+        pieces: &[PieceType],
+        holes: &[u16],
+    ) -> Self {
+        let (rng_seed, prng) = random::prng(cfg.prng_seed);
+        let mut stacker = Stacker {
+            matrix: Matrix::new(rules.cols as u16),
+            cheese: Cheese::with_holes(&rules, cfg.garbage, prng.clone(), holes),
+            current: None,
+            next: Next::with_pending(&rules, prng, pieces.iter().cloned()),
+            held: None,
+            has_held: false,
+            chain: Chain::default(),
+            rules,
+            rng_seed,
+        };
+        stacker.cheese(false);
+        stacker.spawn_from_queue();
+        stacker
+    }
+
+    /// Constructs a stacker game state with ruleset `rules`, starting from a predetermined
+    /// position rather than a fresh cheese matrix. `cells` lists the occupied cells of the
+    /// matrix, `hold` is the held piece, and `queue` begins with the current piece and
+    /// continues with the upcoming pieces. Once `queue` runs out, pieces are drawn from
+    /// the randomizer as usual. No cheese rows are generated.
+    pub fn with_position(
+        rules: Rc<Ruleset>,
+        cfg: Config,
+        cells: impl IntoIterator<Item = ((u16, u16), CellColor)>,
+        hold: Option<PieceType>,
+        queue: &[PieceType],
+    ) -> Self {
+        let (rng_seed, prng) = random::prng(cfg.prng_seed);
+        let mut matrix = Matrix::new(rules.cols as u16);
+        for ((i, j), cc) in cells {
+            matrix.set((i as i16, j as i16), cc);
+        }
+        let mut cheese = Cheese::new(&rules, cfg.garbage, prng.clone());
+        cheese.set_total(0);
+        let next = Next::with_pending(&rules, prng, queue.iter().skip(1).cloned());
+        let mut stacker = Stacker {
+            matrix,
+            cheese,
+            current: None,
+            next,
+            held: hold,
+            has_held: false,
+            chain: Chain::default(),
+            rules,
+            rng_seed,
+        };
+        match queue.first() {
+            Some(&typ) => stacker.spawn(typ),
+            None => stacker.spawn_from_queue(),
+        }
+        stacker
+    }
+
     /// Returns the ruleset used by this stacker.
     pub fn ruleset(&self) -> &Rc<Ruleset> {
         &self.rules
@@ -74,6 +148,23 @@ impl Stacker {
         }
     }
 
+    /// Returns the number of garbage rows at the bottom of the matrix.
+    pub fn garbage_rows(&self) -> usize {
+        self.matrix.garbage_rows()
+    }
+
+    /// Returns the number of garbage lines left to be added to the matrix, or `None` if
+    /// there is no limit.
+    pub fn garbage_remaining(&self) -> Option<usize> {
+        let total = self.cheese.cfg.total_lines?;
+        Some(total.saturating_sub(self.cheese.count))
+    }
+
+    /// Returns the current combo and back-to-back state.
+    pub fn chain(&self) -> Chain {
+        self.chain
+    }
+
     /// Returns a list of all occupied cells in the matrix.
     pub fn matrix<'a>(&'a self) -> impl Iterator<Item = ((u16, u16), PieceType)> + 'a {
         self.matrix.iter()
@@ -142,6 +233,16 @@ impl Stacker {
         }
     }
 
+    /// Move the current piece down by one square. Returns `true` if the piece moved
+    /// without being obstructed.
+    pub fn move_down(&mut self) -> bool {
+        if let Some(pc) = self.current.as_mut() {
+            pc.try_move_by(&self.matrix, &self.rules, -1, 0)
+        } else {
+            false
+        }
+    }
+
     /// Drop the current piece all the way to the floor. Returns `true` if the piece moved
     /// at all as a result.
     pub fn sonic_drop(&mut self) -> bool {
@@ -152,34 +253,41 @@ impl Stacker {
         }
     }
 
-    /// Hard drop the current piece. Returns `(lines_cleared, garbage_cleared)`.
-    pub fn hard_drop(&mut self) -> (usize, usize) {
-        let (n, ds) = match self.current.take() {
+    /// Hard drop the current piece. Returns the lines cleared and attack sent as a result.
+    pub fn hard_drop(&mut self) -> Lock {
+        let lock = match self.current.take() {
             Some(mut pc) => {
                 pc.sonic_drop(&self.matrix, &self.rules);
+                let spin = pc.t_spin(&self.matrix, &self.rules);
                 for coord in pc.coords(&self.rules) {
                     self.matrix.set(coord, pc.typ);
                 }
-                self.matrix.sift()
+                let (n, ds) = self.matrix.sift();
+                let perfect_clear = n > 0 && self.matrix.rows() == 0;
+                self.chain.lock(n, ds, spin, perfect_clear)
             }
-            None => (0, 0),
+            None => Lock::default(),
         };
-        self.cheese(n > 0);
+        self.cheese(lock.lines > 0);
         self.spawn_from_queue();
-        (n, ds)
+        self.has_held = false;
+        lock
     }
 
     /// Hold the current piece. Returns `true` if the piece was held, or `false` if the
     /// hold queue was just used and cannot be swapped again.
     pub fn hold(&mut self) -> bool {
+        if self.has_held {
+            return false;
+        }
         let prev = self.current_piece_type();
         if let Some(held) = std::mem::replace(&mut self.held, prev) {
-            // TODO: prevent repeat holds
             self.spawn(held);
         } else {
             // no hold piece, so grab one from the queue
             self.spawn_from_queue();
         }
+        self.has_held = true;
         true
     }
 
@@ -420,6 +528,9 @@ struct Piece {
     origin: (i16, i16),
     /// The orientation of the piece. Negative indicates counter-clockwise rotations.
     rot: i32,
+    /// `Some(last)` if the piece has not moved since it was last rotated, where `last` is
+    /// `true` if the rotation used the last of several offsets in the kick table.
+    kick: Option<bool>,
 }
 
 impl Piece {
@@ -427,7 +538,13 @@ impl Piece {
     fn new(rules: &Ruleset, typ: PieceType) -> Self {
         let origin = rules.spawn(typ);
         let rot = 0;
-        Piece { typ, origin, rot }
+        let kick = None;
+        Piece {
+            typ,
+            origin,
+            rot,
+            kick,
+        }
     }
 
     /// Returns the coordinates of squares occupied by the piece. `rules` should be the
@@ -447,6 +564,7 @@ impl Piece {
         let mut tmp = *self;
         tmp.origin.0 += i_off;
         tmp.origin.1 += j_off;
+        tmp.kick = None;
         if tmp.coords(rules).any(|coord| matrix.get(coord).is_some()) {
             false
         } else {
@@ -460,11 +578,14 @@ impl Piece {
     /// to the kick table. `rules` should be the same ruleset used to initialize this
     /// piece.
     fn try_rot_by(&mut self, matrix: &Matrix, rules: &Ruleset, r_off: i32) -> bool {
-        for (i_off, j_off) in rules.kicks(self.typ, self.rot, self.rot + r_off) {
+        let kicks = rules.kicks(self.typ, self.rot, self.rot + r_off);
+        let num_kicks = rules.kicks(self.typ, self.rot, self.rot + r_off).count();
+        for (k, (i_off, j_off)) in kicks.enumerate() {
             let mut tmp = *self;
             tmp.origin.0 += i_off;
             tmp.origin.1 += j_off;
             tmp.rot += r_off;
+            tmp.kick = Some(k > 0 && k + 1 == num_kicks);
             if !tmp.coords(rules).any(|coord| matrix.get(coord).is_some()) {
                 *self = tmp;
                 return true;
@@ -485,6 +606,22 @@ impl Piece {
         }
         dy
     }
+
+    /// Returns the kind of T-spin that locking the piece in its current position would
+    /// perform, using the 3-corner rule. The spin is a mini unless both corners next to
+    /// the pointed side of the T are filled, or the piece was rotated using the last
+    /// offset of the kick table (e.g. the T-spin triple kick in SRS).
+    fn t_spin(self, matrix: &Matrix, rules: &Ruleset) -> Spin {
+        let last_kick = match self.kick {
+            Some(last) if self.typ == 'T' => last,
+            _ => return Spin::None,
+        };
+        let cells = self.coords(rules).collect::<Vec<_>>();
+        match corner_spin(&cells, |i, j| matrix.get((i, j)).is_some()) {
+            Spin::Mini if last_kick => Spin::Full,
+            spin => spin,
+        }
+    }
 }
 
 //////////////////////////////////////////////////////////////////////////////////////////
@@ -493,13 +630,26 @@ impl Piece {
 #[derive(Clone)]
 struct Next {
     previews: Vec<PieceType>,
+    // predetermined pieces to take before using `source`, in reverse order
+    pending: Vec<PieceType>,
     source: random::Pieces,
 }
 
 impl Next {
     fn new(rules: &Ruleset, prng: random::PRNG) -> Self {
+        Self::with_pending(rules, prng, std::iter::empty())
+    }
+
+    fn with_pending(
+        rules: &Ruleset,
+        prng: random::PRNG,
+        pending: impl IntoIterator<Item = PieceType>,
+    ) -> Self {
+        let mut pending = pending.into_iter().collect::<Vec<_>>();
+        pending.reverse();
         let mut next = Self {
             previews: Vec::with_capacity(rules.previews),
+            pending,
             source: random::Pieces::with_random_bag(prng, rules.types()),
         };
         next.refill(rules.previews);
@@ -508,7 +658,11 @@ impl Next {
 
     fn refill(&mut self, n: usize) {
         while self.previews.len() < n {
-            self.previews.push(self.source.next());
+            let typ = match self.pending.pop() {
+                Some(typ) => typ,
+                None => self.source.next(),
+            };
+            self.previews.push(typ);
         }
     }
 
@@ -547,6 +701,14 @@ impl Cheese {
         }
     }
 
+    fn with_holes(rules: &Ruleset, cfg: GarbageConfig, prng: random::PRNG, holes: &[u16]) -> Self {
+        Self {
+            cfg,
+            count: 0,
+            source: random::CheeseCols::with_pending(prng, rules.cols as _, holes.iter().cloned()),
+        }
+    }
+
     fn set_total(&mut self, n: usize) {
         self.cfg.total_lines = Some(n);
     }
@@ -581,6 +743,18 @@ impl Cheese {
 mod test {
     use super::*;
 
+    fn stacker(
+        rules: Ruleset,
+        cells: Vec<((u16, u16), CellColor)>,
+        queue: &[PieceType],
+    ) -> Stacker {
+        let cfg = Config {
+            prng_seed: Some(17549539402897300681),
+            garbage: GarbageConfig::default(),
+        };
+        Stacker::with_position(rules.into(), cfg, cells, None, queue)
+    }
+
     #[test]
     fn test_prng() {
         let cfg = Config {
@@ -596,4 +770,156 @@ mod test {
             st2.matrix().collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn test_with_sequence() {
+        let cfg = Config {
+            prng_seed: Some(17549539402897300681),
+            garbage: GarbageConfig {
+                min_height: 2,
+                max_height: 3,
+                total_lines: None,
+            },
+        };
+        let st = Stacker::with_sequence(
+            Ruleset::guideline().into(),
+            cfg,
+            &['T', 'I', 'O'],
+            &[4, 0, 9],
+        );
+        assert_eq!(st.current_piece_type(), Some('T'));
+        assert_eq!(&st.next()[..2], &['I', 'O']);
+        // cheese rows are inserted below, so the first hole is in the top row
+        let holes = (0..3)
+            .map(|i| (0..10).find(|&j| !st.matrix().any(|(c, _)| c == (i, j))))
+            .collect::<Vec<_>>();
+        assert_eq!(holes, [Some(9), Some(0), Some(4)]);
+    }
+
+    #[test]
+    fn test_with_position() {
+        let cfg = Config {
+            prng_seed: Some(17549539402897300681),
+            garbage: GarbageConfig::default(),
+        };
+        let cells = vec![((0, 0), 'G'), ((0, 1), 'G'), ((1, 0), 'T')];
+        let mut st = Stacker::with_position(
+            Ruleset::guideline().into(),
+            cfg,
+            cells.clone(),
+            Some('I'),
+            &['L', 'J', 'S', 'Z', 'O', 'T', 'I'],
+        );
+        assert_eq!(st.matrix().collect::<Vec<_>>(), cells);
+        assert_eq!(st.current_piece_type(), Some('L'));
+        assert_eq!(st.held(), Some('I'));
+        assert_eq!(st.next(), &['J', 'S', 'Z', 'O', 'T']);
+        st.hard_drop();
+        assert_eq!(st.current_piece_type(), Some('J'));
+        assert_eq!(st.next(), &['S', 'Z', 'O', 'T', 'I']);
+        // no cheese was added
+        assert!(st.matrix().all(|((i, _), _)| i < 4));
+    }
+
+    #[test]
+    fn test_repeat_hold() {
+        let mut st = stacker(Ruleset::guideline(), vec![], &['L', 'J', 'S', 'Z']);
+        assert!(st.hold());
+        assert_eq!((st.current_piece_type(), st.held()), (Some('J'), Some('L')));
+        // the piece that came out of hold cannot be held again until it locks
+        assert!(!st.hold());
+        assert_eq!((st.current_piece_type(), st.held()), (Some('J'), Some('L')));
+        st.hard_drop();
+        assert!(st.hold());
+        assert_eq!((st.current_piece_type(), st.held()), (Some('L'), Some('S')));
+    }
+
+    #[test]
+    fn test_hard_drop_t_spin() {
+        // T-spin double slot in columns 3-5, with an overhang at (2, 3)
+        let mut cells = vec![((2, 3), 'G')];
+        cells.extend((0..10).filter(|&j| j != 4).map(|j| ((0, j), 'G')));
+        cells.extend(
+            (0..10)
+                .filter(|j| !(3..=5).contains(j))
+                .map(|j| ((1, j), 'G')),
+        );
+        let st = stacker(Ruleset::guideline(), cells, &['T']);
+
+        // dropped in without rotating: no T-spin, and it does not reach the slot
+        let mut st1 = st.clone();
+        let lock = st1.hard_drop();
+        assert_eq!((lock.lines, lock.spin, lock.attack), (0, Spin::None, 0));
+
+        // rotated into the slot
+        let mut st2 = st.clone();
+        assert!(st2.rotate(1));
+        st2.sonic_drop();
+        assert!(st2.rotate(1));
+        let lock = st2.hard_drop();
+        assert_eq!((lock.lines, lock.spin, lock.attack), (2, Spin::Full, 4));
+        assert_eq!(st2.chain().combo, Some(0));
+        assert!(st2.chain().b2b);
+
+        // an obstructed move after the rotation does not cancel the T-spin
+        let mut st3 = st;
+        st3.rotate(1);
+        st3.sonic_drop();
+        st3.rotate(1);
+        assert!(!st3.move_down());
+        let lock = st3.hard_drop();
+        assert_eq!((lock.lines, lock.spin), (2, Spin::Full));
+    }
+
+    #[test]
+    fn test_hard_drop_t_spin_last_kick() {
+        // . . . . x .
+        // . . . . . .
+        // x . x x . x
+        // x x x . . x
+        // x . x . . x
+        let holes: &[&[u16]] = &[&[1, 3, 4], &[3, 4], &[1, 4]];
+        let mut cells = vec![((4, 4), 'G')];
+        for (i, row) in holes.iter().enumerate() {
+            cells.extend(
+                (0..10)
+                    .filter(|j| !row.contains(j))
+                    .map(|j| ((i as u16, j), 'G')),
+            );
+        }
+        let mut st = stacker(Ruleset::guideline(), cells, &['T']);
+        st.move_horizontal(-2);
+        st.sonic_drop();
+        st.move_horizontal(1);
+        // the overhang blocks every kick offset but the last one, which makes it a full
+        // T-spin even though only one corner next to the point is filled
+        assert!(st.rotate(-1));
+        let lock = st.hard_drop();
+        assert_eq!((lock.lines, lock.spin), (1, Spin::Full));
+    }
+
+    #[test]
+    fn test_rotate_180() {
+        // no kicks for 180 degree rotations in the guideline rules
+        let mut st = stacker(Ruleset::guideline(), vec![], &['T']);
+        assert!(!st.rotate(2));
+        // flipping the T on the floor kicks it up by one row in SRS+
+        let mut st = stacker(Ruleset::srs_plus(), vec![], &['T']);
+        st.sonic_drop();
+        let (_, i, j, _, _) = st.current_piece().unwrap();
+        assert!(st.rotate(2));
+        assert_eq!(st.current_piece(), Some(('T', i + 1, j, 2, i + 1)));
+    }
+
+    #[test]
+    fn test_pentominoes() {
+        let mut st = stacker(Ruleset::pentomino(), vec![], &['I', 'I', 'T']);
+        // two I pentominoes fill a row
+        while st.move_horizontal(-1) {}
+        assert_eq!(st.hard_drop().lines, 0);
+        while st.move_horizontal(1) {}
+        assert_eq!(st.hard_drop().lines, 1);
+        assert_eq!(st.matrix().count(), 0);
+        assert_eq!(st.current_piece_type(), Some('T'));
+    }
 }
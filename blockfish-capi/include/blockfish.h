@@ -0,0 +1,225 @@
+#ifndef BLOCKFISH_H
+#define BLOCKFISH_H
+
+/* Generated by cbindgen from blockfish-capi/src/lib.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * An input in a suggested sequence.
+ */
+typedef enum BlockfishInput {
+  BLOCKFISH_INPUT_LEFT = 0,
+  BLOCKFISH_INPUT_RIGHT = 1,
+  BLOCKFISH_INPUT_CW = 2,
+  BLOCKFISH_INPUT_CCW = 3,
+  BLOCKFISH_INPUT_HOLD = 4,
+  BLOCKFISH_INPUT_SD = 5,
+  BLOCKFISH_INPUT_HD = 6,
+  /**
+   * 180 degree rotation.
+   */
+  BLOCKFISH_INPUT_FLIP = 7,
+} BlockfishInput;
+
+/**
+ * Result of a call that can fail.
+ */
+typedef enum BlockfishStatus {
+  BLOCKFISH_STATUS_OK = 0,
+  /**
+   * A string argument is malformed, or an index is out of range.
+   */
+  BLOCKFISH_STATUS_INVALID_ARGUMENT = 1,
+  /**
+   * A piece letter isn't a valid piece.
+   */
+  BLOCKFISH_STATUS_INVALID_PIECE = 2,
+  /**
+   * The ruleset's matrix is too wide for the engine.
+   */
+  BLOCKFISH_STATUS_UNSUPPORTED_RULESET = 3,
+} BlockfishStatus;
+
+/**
+ * An instance of the engine, holding its configuration and ruleset.
+ */
+typedef struct BlockfishAI BlockfishAI;
+
+/**
+ * A running or finished analysis.
+ */
+typedef struct BlockfishAnalysis BlockfishAnalysis;
+
+/**
+ * A game state to analyze: the matrix, the held piece and the queue.
+ */
+typedef struct BlockfishSnapshot BlockfishSnapshot;
+
+/**
+ * Static evaluation of a matrix, see `blockfish_ai_static_eval`.
+ */
+typedef struct BlockfishEval {
+  uint32_t rows;
+  uint32_t piece_estimate;
+  uint32_t i_dependencies;
+  uint32_t attack;
+  /**
+   * Score using the AI's scoring parameters. Lower is better.
+   */
+  int64_t score;
+} BlockfishEval;
+
+/**
+ * Statistics about an analysis.
+ */
+typedef struct BlockfishStats {
+  uint64_t nodes;
+  uint64_t iterations;
+  uint64_t time_taken_millis;
+} BlockfishStats;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an AI with the default configuration and SRS rules.
+ */
+struct BlockfishAI *blockfish_ai_new(void);
+
+/**
+ * Destroys `ai`. Does nothing if `ai` is null. Analyses started by `ai` keep running.
+ */
+void blockfish_ai_free(struct BlockfishAI *ai);
+
+/**
+ * Configures `ai` from `config`, which is written as
+ * `<search-limit>[/<parameters>[/<goal>]]` with the search limit in thousands of nodes,
+ * e.g. `"50/5,10,10,10,0/cheese"`.
+ */
+enum BlockfishStatus blockfish_ai_set_config(struct BlockfishAI *ai, const char *config);
+
+/**
+ * Sets the maximum number of nodes that analyses by `ai` search.
+ */
+void blockfish_ai_set_search_limit(struct BlockfishAI *ai, size_t nodes);
+
+/**
+ * Switches `ai` to the bundled ruleset called `name`, e.g. `"guideline"` or `"srs-x"`.
+ */
+enum BlockfishStatus blockfish_ai_set_ruleset(struct BlockfishAI *ai, const char *name);
+
+/**
+ * Returns the number of columns in the matrix of the ruleset of `ai`.
+ */
+uint16_t blockfish_ai_cols(const struct BlockfishAI *ai);
+
+/**
+ * Begins an analysis of `snapshot` using the current configuration of `ai`. The
+ * snapshot is copied, so it may be modified or destroyed afterwards. Returns null if the
+ * matrix of `snapshot` is not as wide as the ruleset's, see `blockfish_ai_cols`.
+ */
+struct BlockfishAnalysis *blockfish_ai_analyze(struct BlockfishAI *ai,
+                                               const struct BlockfishSnapshot *snapshot);
+
+/**
+ * Evaluates the matrix of `snapshot` without searching.
+ */
+struct BlockfishEval blockfish_ai_static_eval(const struct BlockfishAI *ai,
+                                              const struct BlockfishSnapshot *snapshot);
+
+/**
+ * Creates a snapshot with an empty matrix that is `cols` wide, and nothing in hold or
+ * the queue. Returns null unless `cols` is between 1 and 16.
+ */
+struct BlockfishSnapshot *blockfish_snapshot_new(uint16_t cols);
+
+/**
+ * Parses a snapshot written in Blockfish's text notation, e.g. `"4x.5x/x9. - TIOLJ"`.
+ * Returns null if `notation` is malformed, or if the matrix is not between 1 and 16
+ * columns wide.
+ */
+struct BlockfishSnapshot *blockfish_snapshot_parse(const char *notation);
+
+/**
+ * Destroys `snapshot`. Does nothing if `snapshot` is null.
+ */
+void blockfish_snapshot_free(struct BlockfishSnapshot *snapshot);
+
+/**
+ * Sets the held piece to the piece letter `hold`, or empties hold if `hold` is 0.
+ */
+enum BlockfishStatus blockfish_snapshot_set_hold(struct BlockfishSnapshot *snapshot, char hold);
+
+/**
+ * Sets the queue to the piece letters of `queue`, starting with the current piece.
+ */
+enum BlockfishStatus blockfish_snapshot_set_queue(struct BlockfishSnapshot *snapshot,
+                                                  const char *queue);
+
+/**
+ * Fills the cell at `row` and `col` of the matrix, where row 0 is the bottom row. Fails
+ * if `col` is outside of the matrix.
+ */
+enum BlockfishStatus blockfish_snapshot_fill_cell(struct BlockfishSnapshot *snapshot,
+                                                  uint16_t row,
+                                                  uint16_t col);
+
+/**
+ * Stops `analysis` and destroys it. Does nothing if `analysis` is null.
+ */
+void blockfish_analysis_free(struct BlockfishAnalysis *analysis);
+
+/**
+ * Updates the suggestions of `analysis` with its progress, without blocking. Returns 1
+ * once the analysis has finished, otherwise 0.
+ */
+int blockfish_analysis_poll(struct BlockfishAnalysis *analysis);
+
+/**
+ * Blocks until `analysis` finishes, then updates its suggestions.
+ */
+void blockfish_analysis_wait(struct BlockfishAnalysis *analysis);
+
+/**
+ * Stops `analysis` early. It finishes with the suggestions found so far once the search
+ * notices, which `blockfish_analysis_poll` or `blockfish_analysis_wait` can be used to
+ * wait for.
+ */
+void blockfish_analysis_stop(const struct BlockfishAnalysis *analysis);
+
+/**
+ * Returns the statistics of `analysis` so far, or the final statistics once it has
+ * finished.
+ */
+struct BlockfishStats blockfish_analysis_stats(const struct BlockfishAnalysis *analysis);
+
+/**
+ * Returns the number of suggestions of `analysis` as of the last call to
+ * `blockfish_analysis_poll` or `blockfish_analysis_wait`.
+ */
+size_t blockfish_analysis_suggestion_count(const struct BlockfishAnalysis *analysis);
+
+/**
+ * Gets suggestion `idx` of `analysis`, where suggestion 0 is the best. Writes the
+ * suggestion's rating (lower is better) to `rating`, and its inputs to `inputs`, which
+ * has room for `*len` inputs. Sets `*len` to the number of inputs in the suggestion,
+ * which may be more than were written. `rating` and `inputs` may be null, in which case
+ * they are not written to.
+ */
+enum BlockfishStatus blockfish_analysis_suggestion(const struct BlockfishAnalysis *analysis,
+                                                   size_t idx,
+                                                   int64_t *rating,
+                                                   enum BlockfishInput *inputs,
+                                                   size_t *len);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* BLOCKFISH_H */
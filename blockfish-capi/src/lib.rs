@@ -0,0 +1,551 @@
+//! C API for embedding Blockfish in other programs. The header is generated into Cargo's
+//! `OUT_DIR` when the crate is built; the copy checked in at `include/blockfish.h` is kept
+//! up to date by `test_header_up_to_date`.
+//!
+//! The engine is used through opaque handles (`BlockfishAI`, `BlockfishSnapshot` and
+//! `BlockfishAnalysis`), which are created by the `*_new` functions and must be released
+//! with the matching `*_free` function. Unless noted otherwise, every pointer argument
+//! must be non-null and point to a live handle, and strings must be null-terminated.
+//! Functions that can fail return a `BlockfishStatus`.
+
+// every function is an FFI entry point with the same safety requirements, given above
+#![allow(clippy::missing_safety_doc)]
+
+use blockfish::{ai, Config, Input, MAX_COLS};
+use std::{
+    convert::TryFrom,
+    ffi::CStr,
+    os::raw::{c_char, c_int},
+    ptr,
+};
+
+/// Result of a call that can fail.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BlockfishStatus {
+    Ok = 0,
+    /// A string argument is malformed, or an index is out of range.
+    InvalidArgument = 1,
+    /// A piece letter isn't a valid piece.
+    InvalidPiece = 2,
+    /// The ruleset's matrix is too wide for the engine.
+    UnsupportedRuleset = 3,
+}
+
+/// An input in a suggested sequence.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BlockfishInput {
+    Left = 0,
+    Right = 1,
+    Cw = 2,
+    Ccw = 3,
+    Hold = 4,
+    Sd = 5,
+    Hd = 6,
+    /// 180 degree rotation.
+    Flip = 7,
+}
+
+impl From<Input> for BlockfishInput {
+    fn from(input: Input) -> Self {
+        match input {
+            Input::Left => BlockfishInput::Left,
+            Input::Right => BlockfishInput::Right,
+            Input::CW => BlockfishInput::Cw,
+            Input::CCW => BlockfishInput::Ccw,
+            Input::Hold => BlockfishInput::Hold,
+            Input::SD => BlockfishInput::Sd,
+            Input::HD => BlockfishInput::Hd,
+            Input::Flip => BlockfishInput::Flip,
+        }
+    }
+}
+
+/// Statistics about an analysis.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BlockfishStats {
+    pub nodes: u64,
+    pub iterations: u64,
+    pub time_taken_millis: u64,
+}
+
+impl From<ai::Stats> for BlockfishStats {
+    fn from(stats: ai::Stats) -> Self {
+        Self {
+            nodes: stats.nodes as u64,
+            iterations: stats.iterations as u64,
+            time_taken_millis: stats.time_taken.as_millis() as u64,
+        }
+    }
+}
+
+/// Static evaluation of a matrix, see `blockfish_ai_static_eval`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct BlockfishEval {
+    pub rows: u32,
+    pub piece_estimate: u32,
+    pub i_dependencies: u32,
+    pub attack: u32,
+    /// Score using the AI's scoring parameters. Lower is better.
+    pub score: i64,
+}
+
+/// Parses the null-terminated string `s`.
+unsafe fn parse_str<'a>(s: *const c_char) -> Result<&'a str, BlockfishStatus> {
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| BlockfishStatus::InvalidArgument)
+}
+
+fn piece(ch: c_char) -> Result<blockfish::Color, BlockfishStatus> {
+    blockfish::Color::try_from(ch as u8 as char).map_err(|_| BlockfishStatus::InvalidPiece)
+}
+
+macro_rules! tri {
+    ($e:expr) => {
+        match $e {
+            Ok(x) => x,
+            Err(status) => return status,
+        }
+    };
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// AI
+
+/// An instance of the engine, holding its configuration and ruleset.
+pub struct BlockfishAI(ai::AI);
+
+/// Creates an AI with the default configuration and SRS rules.
+#[no_mangle]
+pub extern "C" fn blockfish_ai_new() -> *mut BlockfishAI {
+    Box::into_raw(Box::new(BlockfishAI(ai::AI::new(Config::default()))))
+}
+
+/// Destroys `ai`. Does nothing if `ai` is null. Analyses started by `ai` keep running.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_ai_free(ai: *mut BlockfishAI) {
+    if !ai.is_null() {
+        drop(Box::from_raw(ai));
+    }
+}
+
+/// Configures `ai` from `config`, which is written as
+/// `<search-limit>[/<parameters>[/<goal>]]` with the search limit in thousands of nodes,
+/// e.g. `"50/5,10,10,10,0/cheese"`.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_ai_set_config(
+    ai: *mut BlockfishAI,
+    config: *const c_char,
+) -> BlockfishStatus {
+    let config = tri!(parse_str(config));
+    let config = tri!(config
+        .parse::<Config>()
+        .map_err(|_| BlockfishStatus::InvalidArgument));
+    *(*ai).0.config_mut() = config;
+    BlockfishStatus::Ok
+}
+
+/// Sets the maximum number of nodes that analyses by `ai` search.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_ai_set_search_limit(ai: *mut BlockfishAI, nodes: usize) {
+    (*ai).0.config_mut().search_limit = nodes;
+}
+
+/// Switches `ai` to the bundled ruleset called `name`, e.g. `"guideline"` or `"srs-x"`.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_ai_set_ruleset(
+    ai: *mut BlockfishAI,
+    name: *const c_char,
+) -> BlockfishStatus {
+    let name = tri!(parse_str(name));
+    let rules = tri!(block_stacker::Ruleset::builtin(name).ok_or(BlockfishStatus::InvalidArgument));
+    tri!((*ai)
+        .0
+        .set_ruleset(&rules)
+        .map_err(|_| BlockfishStatus::UnsupportedRuleset));
+    BlockfishStatus::Ok
+}
+
+/// Returns the number of columns in the matrix of the ruleset of `ai`.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_ai_cols(ai: *const BlockfishAI) -> u16 {
+    (*ai).0.cols()
+}
+
+/// Begins an analysis of `snapshot` using the current configuration of `ai`. The
+/// snapshot is copied, so it may be modified or destroyed afterwards. Returns null if the
+/// matrix of `snapshot` is not as wide as the ruleset's, see `blockfish_ai_cols`.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_ai_analyze(
+    ai: *mut BlockfishAI,
+    snapshot: *const BlockfishSnapshot,
+) -> *mut BlockfishAnalysis {
+    if (*snapshot).0.matrix.cols() != (*ai).0.cols() {
+        return ptr::null_mut();
+    }
+    let analysis = (*ai).0.analyze((*snapshot).0.clone());
+    Box::into_raw(Box::new(BlockfishAnalysis {
+        inner: analysis,
+        moves: vec![],
+    }))
+}
+
+/// Evaluates the matrix of `snapshot` without searching.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_ai_static_eval(
+    ai: *const BlockfishAI,
+    snapshot: *const BlockfishSnapshot,
+) -> BlockfishEval {
+    let ai = &(*ai).0;
+    let eval = ai.static_eval(&(*snapshot).0);
+    BlockfishEval {
+        rows: eval.rows as u32,
+        piece_estimate: eval.piece_estimate as u32,
+        i_dependencies: eval.i_dependencies as u32,
+        attack: eval.attack as u32,
+        score: eval.score(&ai.config().parameters),
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Snapshots
+
+/// A game state to analyze: the matrix, the held piece and the queue.
+pub struct BlockfishSnapshot(ai::Snapshot);
+
+/// Creates a snapshot with an empty matrix that is `cols` wide, and nothing in hold or
+/// the queue. Returns null unless `cols` is between 1 and 16.
+#[no_mangle]
+pub extern "C" fn blockfish_snapshot_new(cols: u16) -> *mut BlockfishSnapshot {
+    if cols == 0 || cols > MAX_COLS {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(BlockfishSnapshot(ai::Snapshot {
+        hold: None,
+        queue: vec![],
+        matrix: blockfish::BasicMatrix::with_cols(cols),
+        bag: None,
+        refill: None,
+        combo: None,
+        b2b: false,
+    })))
+}
+
+/// Parses a snapshot written in Blockfish's text notation, e.g. `"4x.5x/x9. - TIOLJ"`.
+/// Returns null if `notation` is malformed, or if the matrix is not between 1 and 16
+/// columns wide.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_snapshot_parse(
+    notation: *const c_char,
+) -> *mut BlockfishSnapshot {
+    match parse_str(notation).map(str::parse) {
+        Ok(Ok(ss)) => Box::into_raw(Box::new(BlockfishSnapshot(ss))),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Destroys `snapshot`. Does nothing if `snapshot` is null.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_snapshot_free(snapshot: *mut BlockfishSnapshot) {
+    if !snapshot.is_null() {
+        drop(Box::from_raw(snapshot));
+    }
+}
+
+/// Sets the held piece to the piece letter `hold`, or empties hold if `hold` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_snapshot_set_hold(
+    snapshot: *mut BlockfishSnapshot,
+    hold: c_char,
+) -> BlockfishStatus {
+    (*snapshot).0.hold = match hold {
+        0 => None,
+        _ => Some(tri!(piece(hold))),
+    };
+    BlockfishStatus::Ok
+}
+
+/// Sets the queue to the piece letters of `queue`, starting with the current piece.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_snapshot_set_queue(
+    snapshot: *mut BlockfishSnapshot,
+    queue: *const c_char,
+) -> BlockfishStatus {
+    let queue = CStr::from_ptr(queue).to_bytes();
+    let queue = queue.iter().map(|&ch| piece(ch as c_char));
+    (*snapshot).0.queue = tri!(queue.collect());
+    BlockfishStatus::Ok
+}
+
+/// Fills the cell at `row` and `col` of the matrix, where row 0 is the bottom row. Fails
+/// if `col` is outside of the matrix.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_snapshot_fill_cell(
+    snapshot: *mut BlockfishSnapshot,
+    row: u16,
+    col: u16,
+) -> BlockfishStatus {
+    let matrix = &mut (*snapshot).0.matrix;
+    if col >= matrix.cols() {
+        return BlockfishStatus::InvalidArgument;
+    }
+    matrix.set((row, col));
+    BlockfishStatus::Ok
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Analyses
+
+/// A running or finished analysis.
+pub struct BlockfishAnalysis {
+    inner: ai::Analysis,
+    // every move found so far, best first
+    moves: Vec<ai::MoveId>,
+}
+
+impl BlockfishAnalysis {
+    fn sort_moves(&mut self) {
+        let inner = &self.inner;
+        self.moves = inner.all_moves().collect();
+        self.moves.sort_by(|&a, &b| inner.cmp(a, b));
+    }
+}
+
+/// Stops `analysis` and destroys it. Does nothing if `analysis` is null.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_analysis_free(analysis: *mut BlockfishAnalysis) {
+    if !analysis.is_null() {
+        let analysis = Box::from_raw(analysis);
+        analysis.inner.stop();
+    }
+}
+
+/// Updates the suggestions of `analysis` with its progress, without blocking. Returns 1
+/// once the analysis has finished, otherwise 0.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_analysis_poll(analysis: *mut BlockfishAnalysis) -> c_int {
+    let analysis = &mut *analysis;
+    let done = loop {
+        match analysis.inner.poll() {
+            Ok(Some(_)) => continue,
+            Ok(None) => break false,
+            Err(ai::AnalysisDone) => break true,
+        }
+    };
+    analysis.sort_moves();
+    done as c_int
+}
+
+/// Blocks until `analysis` finishes, then updates its suggestions.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_analysis_wait(analysis: *mut BlockfishAnalysis) {
+    let analysis = &mut *analysis;
+    analysis.inner.wait();
+    analysis.sort_moves();
+}
+
+/// Stops `analysis` early. It finishes with the suggestions found so far once the search
+/// notices, which `blockfish_analysis_poll` or `blockfish_analysis_wait` can be used to
+/// wait for.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_analysis_stop(analysis: *const BlockfishAnalysis) {
+    (*analysis).inner.stop();
+}
+
+/// Returns the statistics of `analysis` so far, or the final statistics once it has
+/// finished.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_analysis_stats(
+    analysis: *const BlockfishAnalysis,
+) -> BlockfishStats {
+    (*analysis).inner.progress().into()
+}
+
+/// Returns the number of suggestions of `analysis` as of the last call to
+/// `blockfish_analysis_poll` or `blockfish_analysis_wait`.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_analysis_suggestion_count(
+    analysis: *const BlockfishAnalysis,
+) -> usize {
+    (*analysis).moves.len()
+}
+
+/// Gets suggestion `idx` of `analysis`, where suggestion 0 is the best. Writes the
+/// suggestion's rating (lower is better) to `rating`, and its inputs to `inputs`, which
+/// has room for `*len` inputs. Sets `*len` to the number of inputs in the suggestion,
+/// which may be more than were written. `rating` and `inputs` may be null, in which case
+/// they are not written to.
+#[no_mangle]
+pub unsafe extern "C" fn blockfish_analysis_suggestion(
+    analysis: *const BlockfishAnalysis,
+    idx: usize,
+    rating: *mut i64,
+    inputs: *mut BlockfishInput,
+    len: *mut usize,
+) -> BlockfishStatus {
+    let analysis = &*analysis;
+    let m_id = tri!(analysis
+        .moves
+        .get(idx)
+        .ok_or(BlockfishStatus::InvalidArgument));
+    let sugg = analysis.inner.suggestion(*m_id, std::usize::MAX);
+    if !rating.is_null() {
+        *rating = sugg.rating;
+    }
+    if !inputs.is_null() {
+        for (i, &input) in sugg.inputs.iter().take(*len).enumerate() {
+            *inputs.add(i) = input.into();
+        }
+    }
+    *len = sugg.inputs.len();
+    BlockfishStatus::Ok
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_header_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/blockfish.h"));
+        let checked_in = include_str!("../include/blockfish.h");
+        assert!(
+            generated == checked_in,
+            "include/blockfish.h is out of date, replace it with {}/blockfish.h",
+            env!("OUT_DIR"),
+        );
+    }
+
+    #[test]
+    fn test_snapshot() {
+        unsafe {
+            let ss = blockfish_snapshot_new(10);
+            let queue = CString::new("LTX").unwrap();
+            assert_eq!(
+                blockfish_snapshot_set_hold(ss, b'I' as c_char),
+                BlockfishStatus::Ok
+            );
+            assert_eq!(
+                blockfish_snapshot_set_queue(ss, queue.as_ptr()),
+                BlockfishStatus::Ok
+            );
+            assert_eq!(blockfish_snapshot_fill_cell(ss, 1, 9), BlockfishStatus::Ok);
+            assert_eq!(
+                blockfish_snapshot_fill_cell(ss, 0, 10),
+                BlockfishStatus::InvalidArgument
+            );
+            assert_eq!((*ss).0.to_string(), "9.x/10. I LTX");
+            let queue = CString::new("L?").unwrap();
+            assert_eq!(
+                blockfish_snapshot_set_queue(ss, queue.as_ptr()),
+                BlockfishStatus::InvalidPiece
+            );
+            blockfish_snapshot_free(ss);
+
+            assert!(blockfish_snapshot_new(17).is_null());
+            let bad = CString::new("10. -").unwrap();
+            assert!(blockfish_snapshot_parse(bad.as_ptr()).is_null());
+        }
+    }
+
+    #[test]
+    fn test_analyze() {
+        unsafe {
+            let ai = blockfish_ai_new();
+            let config = CString::new("5").unwrap();
+            assert_eq!(
+                blockfish_ai_set_config(ai, config.as_ptr()),
+                BlockfishStatus::Ok
+            );
+            let notation = CString::new("x9./x9. - LIJ").unwrap();
+            let ss = blockfish_snapshot_parse(notation.as_ptr());
+            assert!(!ss.is_null());
+            let analysis = blockfish_ai_analyze(ai, ss);
+            blockfish_snapshot_free(ss);
+            blockfish_analysis_wait(analysis);
+            assert_eq!(blockfish_analysis_poll(analysis), 1);
+            assert!(blockfish_analysis_stats(analysis).nodes > 0);
+
+            let count = blockfish_analysis_suggestion_count(analysis);
+            assert!(count > 0);
+            let mut ratings = vec![];
+            for idx in 0..count {
+                let (mut rating, mut len) = (0, 0);
+                let status = blockfish_analysis_suggestion(
+                    analysis,
+                    idx,
+                    &mut rating,
+                    ptr::null_mut(),
+                    &mut len,
+                );
+                assert_eq!(status, BlockfishStatus::Ok);
+                ratings.push(rating);
+            }
+            assert!(ratings.windows(2).all(|w| w[0] <= w[1]));
+
+            let mut inputs = [BlockfishInput::Left; 4];
+            let mut len = inputs.len();
+            let status = blockfish_analysis_suggestion(
+                analysis,
+                0,
+                ptr::null_mut(),
+                inputs.as_mut_ptr(),
+                &mut len,
+            );
+            assert_eq!(status, BlockfishStatus::Ok);
+            assert!(len > inputs.len());
+            let status = blockfish_analysis_suggestion(
+                analysis,
+                count,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut len,
+            );
+            assert_eq!(status, BlockfishStatus::InvalidArgument);
+
+            blockfish_analysis_free(analysis);
+            blockfish_ai_free(ai);
+        }
+    }
+
+    #[test]
+    fn test_analyze_wrong_width() {
+        unsafe {
+            let ai = blockfish_ai_new();
+            assert_eq!(blockfish_ai_cols(ai), 10);
+            for notation in &["x. - TIO", "x11. - TIO"] {
+                let notation = CString::new(*notation).unwrap();
+                let ss = blockfish_snapshot_parse(notation.as_ptr());
+                assert!(!ss.is_null());
+                assert!(blockfish_ai_analyze(ai, ss).is_null());
+                blockfish_snapshot_free(ss);
+            }
+            let ss = blockfish_snapshot_new(4);
+            assert!(blockfish_ai_analyze(ai, ss).is_null());
+            blockfish_snapshot_free(ss);
+
+            let notation = CString::new("17. - I").unwrap();
+            assert!(blockfish_snapshot_parse(notation.as_ptr()).is_null());
+            blockfish_ai_free(ai);
+        }
+    }
+
+    #[test]
+    fn test_static_eval() {
+        unsafe {
+            let ai = blockfish_ai_new();
+            let notation = CString::new("x9./x9. - -").unwrap();
+            let ss = blockfish_snapshot_parse(notation.as_ptr());
+            let eval = blockfish_ai_static_eval(ai, ss);
+            assert_eq!(eval.rows, 2);
+            assert_eq!(eval.attack, 0);
+            blockfish_snapshot_free(ss);
+            blockfish_ai_free(ai);
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use crate::{
     controls::{Action, EngineOp, GameOp, TreeOp},
+    replay::{RecordedPiece, Recording},
     view::View,
 };
 use bitflags::bitflags;
-use block_stacker::Stacker;
-use blockfish::{ai, Parameters, StackerExt as _};
-use std::sync::mpsc;
+use block_stacker::{Ruleset, Stacker};
+use blockfish::{ai, Input, Parameters, StackerExt as _};
+use std::{rc::Rc, sync::mpsc};
 
 // limit on number of times to poll per frame -- prevents lagging out the UI thread.
 const MAX_POLLS_PER_FRAME: u32 = 64;
@@ -20,6 +21,11 @@ pub struct Controller<'v> {
     undo_list: Vec<(Stacker, Progress)>,
     analysis: Option<Analysis>,
     trie: Option<Trie>,
+    // inputs for each piece placed so far, and for the current piece
+    record: Vec<Vec<Input>>,
+    inputs: Vec<Input>,
+    // recorded game being stepped through, if in replay mode
+    replay: Option<Replay>,
 }
 
 bitflags! {
@@ -47,6 +53,9 @@ impl<'v> Controller<'v> {
             undo_list: Vec::with_capacity(100),
             progress: Progress::new(),
             analysis: None,
+            record: Vec::with_capacity(100),
+            inputs: vec![],
+            replay: None,
         };
 
         ctl.consult_engine();
@@ -55,10 +64,45 @@ impl<'v> Controller<'v> {
         ctl
     }
 
+    /// Constructs a new `Controller` in replay mode, stepping through the game recorded in
+    /// `rec`. The engine starts out disabled.
+    pub fn with_replay(ai: ai::AI, view: View<'v>, rules: Rc<Ruleset>, rec: &Recording) -> Self {
+        let replay = Replay::new(rules, rec);
+        let (stacker, progress) = replay.current().clone();
+        let mut ctl = Self {
+            ai,
+            view,
+            stacker,
+            trie: None,
+            undo_list: vec![],
+            progress,
+            analysis: None,
+            record: vec![],
+            inputs: vec![],
+            replay: Some(replay),
+        };
+
+        ctl.update_view(Update::all());
+        ctl
+    }
+
     pub fn view(&self) -> &View<'v> {
         &self.view
     }
 
+    /// Returns a recording of the pieces placed so far.
+    pub fn recording(&self) -> Recording {
+        let mut rec = Recording::new(&self.stacker.config(), self.stacker.prng_seed());
+        rec.pieces = self
+            .record
+            .iter()
+            .map(|inputs| RecordedPiece {
+                inputs: inputs.clone(),
+            })
+            .collect();
+        rec
+    }
+
     fn update_view(&mut self, upd: Update) {
         if upd.contains(Update::QUEUE) {
             let next = self.stacker.next().iter().cloned();
@@ -139,11 +183,13 @@ impl<'v> Controller<'v> {
         self.progress = progress;
     }
 
-    /// Hard drops the current piece and updates `progress` as a result.
+    /// Hard drops the current piece and updates `progress` and the record as a result.
     fn hard_drop(&mut self) {
-        let (lc, ds) = self.stacker.hard_drop();
+        self.inputs.push(Input::HD);
+        self.record.push(std::mem::take(&mut self.inputs));
+        let lock = self.stacker.hard_drop();
         let cc = self.stacker.is_matrix_colorless();
-        self.progress.incr(lc, ds, cc);
+        self.progress.incr(lock.lines, lock.garbage, cc);
     }
 
     /// Handles the user action `action`.
@@ -157,21 +203,40 @@ impl<'v> Controller<'v> {
 
     /// Handles a game input, updating both the stacker state and view accordingly.
     fn handle_game_op(&mut self, op: GameOp) {
+        if self.replay.is_some() {
+            return self.handle_replay_op(op);
+        }
         let mut upd = Update::empty();
         match op {
             GameOp::MoveLeft | GameOp::MoveRight => {
-                let dx = if op == GameOp::MoveLeft { -1 } else { 1 };
+                let (dx, inp) = if op == GameOp::MoveLeft {
+                    (-1, Input::Left)
+                } else {
+                    (1, Input::Right)
+                };
                 upd.set(Update::PIECE, self.stacker.move_horizontal(dx));
+                self.record_input(upd, inp);
             }
             GameOp::RotateCCW | GameOp::RotateCW => {
-                let dr = if op == GameOp::RotateCCW { -1 } else { 1 };
+                let (dr, inp) = if op == GameOp::RotateCCW {
+                    (-1, Input::CCW)
+                } else {
+                    (1, Input::CW)
+                };
                 upd.set(Update::PIECE, self.stacker.rotate(dr));
+                self.record_input(upd, inp);
+            }
+            GameOp::Rotate180 => {
+                upd.set(Update::PIECE, self.stacker.rotate(2));
+                self.record_input(upd, Input::Flip);
             }
             GameOp::SonicDrop => {
                 upd.set(Update::PIECE, self.stacker.sonic_drop());
+                self.record_input(upd, Input::SD);
             }
             GameOp::Hold => {
                 upd.set(Update::PIECE | Update::QUEUE, self.stacker.hold());
+                self.record_input(upd, Input::Hold);
             }
             GameOp::HardDrop => {
                 self.undo_save();
@@ -186,6 +251,8 @@ impl<'v> Controller<'v> {
                 self.progress = Progress::new();
                 self.undo_list.clear();
                 self.undo_save();
+                self.record.clear();
+                self.inputs.clear();
                 upd.set(Update::STACKER, true);
             }
             GameOp::Undo => {
@@ -193,6 +260,9 @@ impl<'v> Controller<'v> {
                 if self.undo_list.is_empty() {
                     self.undo_save();
                 }
+                // the restored state is right before the hard drop
+                self.inputs = self.record.pop().unwrap_or_default();
+                self.inputs.pop();
                 upd.set(Update::STACKER, true);
             }
         }
@@ -206,6 +276,39 @@ impl<'v> Controller<'v> {
         self.update_view(upd);
     }
 
+    /// Adds `inp` to the inputs for the current piece, if it had an effect according to
+    /// `upd`.
+    fn record_input(&mut self, upd: Update, inp: Input) {
+        if !upd.is_empty() {
+            self.inputs.push(inp);
+        }
+    }
+
+    /// Handles a game input in replay mode, where hard drop and undo instead step forward
+    /// and backward through the recorded game, and reset goes back to the beginning.
+    fn handle_replay_op(&mut self, op: GameOp) {
+        let replay = self.replay.as_mut().expect("not in replay mode");
+        let changed = match op {
+            GameOp::HardDrop => replay.step(1),
+            GameOp::Undo => replay.step(-1),
+            GameOp::Reset => replay.step(-(replay.pos as isize)),
+            _ => false,
+        };
+        if !changed {
+            return;
+        }
+        let (stacker, progress) = replay.current().clone();
+        self.stacker = stacker;
+        self.progress = progress;
+
+        let mut upd = Update::STACKER;
+        if self.analysis.is_some() {
+            self.consult_engine();
+            upd.set(Update::ENGINE, true);
+        }
+        self.update_view(upd);
+    }
+
     /// Handles an engine related user action.
     fn handle_engine_op(&mut self, op: EngineOp) {
         if let Some(an) = self.analysis.take() {
@@ -240,12 +343,20 @@ impl<'v> Controller<'v> {
                 upd.set(Update::AI, an.nav(0, step));
                 self.analysis = Some(an);
             }
+            EngineOp::Goto | EngineOp::AutoPlay if self.replay.is_some() => {
+                // the recorded game cannot be changed
+                self.analysis = Some(an);
+            }
             EngineOp::Goto => {
-                upd.set(Update::STACKER, an.go_to(&mut self.stacker));
+                if an.go_to(&mut self.stacker) {
+                    self.inputs = an.selected_inputs();
+                    upd.set(Update::STACKER, true);
+                }
                 self.analysis = Some(an);
             }
             EngineOp::AutoPlay => {
                 if an.go_to(&mut self.stacker) {
+                    self.inputs = an.selected_inputs();
                     self.undo_save();
                     self.hard_drop();
                     self.consult_engine();
@@ -342,6 +453,58 @@ impl Progress {
     }
 }
 
+/// Holds the game state before each piece of a recorded game, and which one is being
+/// shown.
+struct Replay {
+    steps: Vec<(Stacker, Progress)>,
+    pos: usize,
+}
+
+impl Replay {
+    /// Plays back recording `rec` using ruleset `rules`. If the recording is invalid then
+    /// only the steps before the invalid placement are kept.
+    fn new(rules: Rc<Ruleset>, rec: &Recording) -> Self {
+        let mut stacker = Stacker::new(rules, rec.game_config());
+        let mut progress = Progress::new();
+        let mut steps = Vec::with_capacity(rec.pieces.len() + 1);
+        steps.push((stacker.clone(), progress.clone()));
+        for (i, piece) in rec.pieces.iter().enumerate() {
+            let inputs = match piece.inputs.split_last() {
+                Some((Input::HD, inputs)) => inputs,
+                _ => {
+                    log::warn!("recorded piece #{} does not end in a hard drop", i + 1);
+                    break;
+                }
+            };
+            stacker.run(inputs.iter().cloned());
+            if stacker.current_piece_type().is_none() {
+                log::warn!("recorded game topped out before piece #{}", i + 1);
+                break;
+            }
+            let lock = stacker.hard_drop();
+            let cc = stacker.is_matrix_colorless();
+            progress.incr(lock.lines, lock.garbage, cc);
+            steps.push((stacker.clone(), progress.clone()));
+        }
+        log::info!("loaded replay with {} pieces", steps.len() - 1);
+        Self { steps, pos: 0 }
+    }
+
+    fn current(&self) -> &(Stacker, Progress) {
+        &self.steps[self.pos]
+    }
+
+    /// Moves `delta` steps forward (or backward, if negative). Returns `true` if the
+    /// current step changed.
+    fn step(&mut self, delta: isize) -> bool {
+        let max = self.steps.len() as isize - 1;
+        let pos = std::cmp::min(std::cmp::max(self.pos as isize + delta, 0), max) as usize;
+        let changed = pos != self.pos;
+        self.pos = pos;
+        changed
+    }
+}
+
 /// Holds the current state of a background analysis, the suggested moves, and which move
 /// is selected.
 struct Analysis {
@@ -483,6 +646,11 @@ impl Analysis {
         true
     }
 
+    /// Returns the inputs that `go_to` plays for the selected move.
+    fn selected_inputs(&self) -> Vec<Input> {
+        self.moves[self.sel_idx].inputs_prefix(0).collect()
+    }
+
     /// Updates the engine elements of `view`.
     fn update_view(&mut self, view: &mut View) {
         // update status text
@@ -553,6 +721,7 @@ impl Move {
 /// tree sidebar. Implements UI actions such as hovering, scrolling, and collapsing nodes.
 struct Trie {
     parameters: Parameters,
+    piece_size: usize,
     nodes: Vec<TrieNode>,
     linear: Vec<TrieNodeId>,
     hover: Option<usize>,
@@ -578,6 +747,7 @@ impl Trie {
     fn new() -> Self {
         Self {
             parameters: Parameters::default(),
+            piece_size: 4,
             nodes: vec![],
             linear: vec![],
             hover: None,
@@ -601,6 +771,7 @@ impl Trie {
         });
         self.linearize();
         self.parameters.clone_from(&ai.config().parameters);
+        self.piece_size = ai.piece_size();
         self.rx = Some(ai.listen_all());
     }
 
@@ -651,7 +822,7 @@ impl Trie {
                 Some(&id) => id,
                 None => {
                     let mut child = TrieNode::new(prefix.to_vec(), stacker.clone(), depth);
-                    child.update_eval(&self.parameters);
+                    child.update_eval(&self.parameters, self.piece_size);
                     let id = self.nodes.len();
                     self.nodes.push(child);
                     children.push(id);
@@ -755,11 +926,11 @@ impl TrieNode {
         }
     }
 
-    fn update_eval(&mut self, params: &Parameters) {
+    fn update_eval(&mut self, params: &Parameters, piece_size: usize) {
         let mut stacker = self.stacker.clone();
         stacker.hard_drop();
         if let Some(ss) = stacker.snapshot() {
-            self.eval = ai::static_eval(&ss);
+            self.eval = ai::static_eval(&ss, piece_size);
             self.eval_score = self.eval.score(params);
         }
     }
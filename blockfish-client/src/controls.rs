@@ -20,6 +20,7 @@ pub enum GameOp {
     MoveRight,
     RotateCCW,
     RotateCW,
+    Rotate180,
     Hold,
     SonicDrop,
     HardDrop,
@@ -215,6 +216,8 @@ struct GameControlsSpec {
     right: KeyStroke,
     ccw: KeyStroke,
     cw: KeyStroke,
+    #[serde(default)]
+    flip: Option<KeyStroke>,
     hold: KeyStroke,
     sd: KeyStroke,
     hd: KeyStroke,
@@ -252,7 +255,14 @@ impl ControlsSpec {
             (Action::Engine(EngineOp::Goto), self.engine.goto),
             (Action::Tree(TreeOp::Toggle), BINDING_TOGGLE_TREE),
         ];
-        Controls::new(key_bindings.iter().cloned(), self.handling.clone())
+        let flip = self
+            .game
+            .flip
+            .map(|ks| (Action::Game(GameOp::Rotate180), ks));
+        Controls::new(
+            key_bindings.iter().cloned().chain(flip),
+            self.handling.clone(),
+        )
     }
 }
 
@@ -2,13 +2,14 @@
 
 mod controller;
 mod controls;
+mod replay;
 mod resources;
 mod theme;
 mod timer;
 mod util;
 mod view;
 
-use block_stacker::{Config as BSConfig, Ruleset};
+use block_stacker::{Config as BSConfig, Ruleset, RulesetSource};
 use blockfish::Config as BFConfig;
 
 use argh::FromArgs;
@@ -25,6 +26,16 @@ enum Error {
     Resources(#[from] resources::ResourceLoadError),
     #[error("failed to parse {0} config")]
     ParseConfig(&'static str, #[source] serde_json::Error),
+    #[error("failed to load replay")]
+    LoadReplay(#[source] replay::RecordingError),
+    #[error("failed to save recording")]
+    SaveRecording(#[source] replay::RecordingError),
+    #[error("--record cannot be used together with --replay")]
+    RecordReplay,
+    #[error("failed to load ruleset")]
+    LoadRuleset(#[source] Box<dyn std::error::Error>),
+    #[error("unsupported ruleset")]
+    UnsupportedRuleset(#[from] blockfish::ai::UnsupportedRuleset),
 }
 
 fn sdl_error(e: impl std::fmt::Display) -> Error {
@@ -53,6 +64,16 @@ struct Args {
     /// integer used to seed the random number generator
     #[argh(option, short = 'A')]
     ai_params: Option<BFConfig>,
+    /// step through a recorded game or race trace instead of playing
+    #[argh(option)]
+    replay: Option<std::path::PathBuf>,
+    /// record the game to the given file on exit; not allowed with --replay
+    #[argh(option)]
+    record: Option<std::path::PathBuf>,
+    /// ruleset name (guideline, ars, classic, srs+, srs-x, pentomino) or JSON file,
+    /// defaults to the guideline rules, or the ruleset recorded in the replay
+    #[argh(option)]
+    ruleset: Option<std::path::PathBuf>,
 }
 
 impl Args {
@@ -152,6 +173,11 @@ fn error_trace_msgbox(err: &(dyn std::error::Error + 'static)) {
 // Entry point
 
 fn entry(mut args: Args) -> Result<()> {
+    // a replay has nothing new to record, and saving it would overwrite the replayed file
+    if args.replay.is_some() && args.record.is_some() {
+        return Err(Error::RecordReplay);
+    }
+
     // init subsystems
     let sdl = sdl2::init().map_err(sdl_error)?;
     let sdl_video = sdl.video().map_err(sdl_error)?;
@@ -184,11 +210,28 @@ fn entry(mut args: Args) -> Result<()> {
     let theme = theme::Theme::from_config_file(&config_dir)?;
 
     // build ai, game state, view and controller
-    let rules = std::rc::Rc::new(Ruleset::guideline());
-    let ai = blockfish::ai::AI::new(args.ai_config());
-    let stacker = block_stacker::Stacker::new(rules.clone(), args.game_config());
-    let view = view::View::new(resources, rules, controls, &theme);
-    let mut ctl = controller::Controller::new(ai, view, stacker);
+    let replay = match args.replay.as_ref() {
+        Some(path) => Some(replay::Recording::from_file(path).map_err(Error::LoadReplay)?),
+        None => None,
+    };
+    let ruleset = match args.ruleset.as_ref() {
+        Some(path) => Some(RulesetSource::open(path).map_err(|e| Error::LoadRuleset(e.into()))?),
+        None => replay.as_ref().and_then(|rec| rec.ruleset.clone()),
+    };
+    let rules = std::rc::Rc::new(match ruleset.as_ref() {
+        Some(src) => src.load().map_err(|e| Error::LoadRuleset(e.into()))?,
+        None => Ruleset::guideline(),
+    });
+    let mut ai = blockfish::ai::AI::new(args.ai_config());
+    ai.set_ruleset(&rules)?;
+    let view = view::View::new(resources, rules.clone(), controls, &theme);
+    let mut ctl = match replay.as_ref() {
+        Some(rec) => controller::Controller::with_replay(ai, view, rules, rec),
+        None => {
+            let stacker = block_stacker::Stacker::new(rules, args.game_config());
+            controller::Controller::new(ai, view, stacker)
+        }
+    };
     let mut tmr = ctl.view().make_timer();
 
     loop {
@@ -196,7 +239,14 @@ fn entry(mut args: Args) -> Result<()> {
         tmr.update();
         for evt in events.poll_iter() {
             match ctl.view().handle(evt, &mut tmr) {
-                Err(view::Quit) => return Ok(()),
+                Err(view::Quit) => {
+                    if let Some(path) = args.record.as_ref() {
+                        let mut rec = ctl.recording();
+                        rec.ruleset = ruleset.clone();
+                        rec.to_file(path).map_err(Error::SaveRecording)?;
+                    }
+                    return Ok(());
+                }
                 Ok(Some(action)) => ctl.handle(action),
                 Ok(None) => {}
             }
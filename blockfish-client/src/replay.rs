@@ -0,0 +1,73 @@
+use block_stacker::{Config as BSConfig, RulesetSource};
+use blockfish::Input;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+
+// Errors
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("invalid recording")]
+    Json(#[from] serde_json::Error),
+}
+
+// Recording
+
+/// A recorded game: the settings needed to regenerate the same pieces and garbage, and
+/// the inputs played for each piece. Race traces written by `blockfish-race` can be read
+/// as recordings, since their extra fields are ignored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub seed: u64,
+    pub garbage: usize,
+    pub min_garbage: usize,
+    pub goal: Option<usize>,
+    /// Ruleset the game was played with, if not the guideline rules.
+    #[serde(default)]
+    pub ruleset: Option<RulesetSource>,
+    pub pieces: Vec<RecordedPiece>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedPiece {
+    /// Inputs played for this piece, ending in a hard drop.
+    pub inputs: Vec<Input>,
+}
+
+impl Recording {
+    /// Constructs an empty recording of a game with the given config and seed, using the
+    /// guideline rules.
+    pub fn new(cfg: &BSConfig, seed: u64) -> Self {
+        Self {
+            seed,
+            garbage: cfg.garbage.max_height,
+            min_garbage: cfg.garbage.min_height,
+            goal: cfg.garbage.total_lines,
+            ruleset: None,
+            pieces: vec![],
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, RecordingError> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    pub fn to_file(&self, path: &Path) -> Result<(), RecordingError> {
+        let file = std::fs::File::create(path)?;
+        Ok(serde_json::to_writer(file, self)?)
+    }
+
+    /// Returns the game config needed to reproduce this recording.
+    pub fn game_config(&self) -> BSConfig {
+        let mut cfg = BSConfig::default();
+        cfg.prng_seed = Some(self.seed);
+        cfg.garbage.max_height = self.garbage;
+        cfg.garbage.min_height = self.min_garbage;
+        cfg.garbage.total_lines = self.goal;
+        cfg
+    }
+}
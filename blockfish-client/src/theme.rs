@@ -139,6 +139,24 @@ pub struct Colors {
     pub cell: HashMap<CellColor, Color>,
 }
 
+impl Colors {
+    /// Returns the color for cells of type `cc`. Piece types that the theme has no color
+    /// for (e.g. pentominoes) reuse one of the mino colors, chosen by their letter.
+    pub fn cell_color(&self, cc: CellColor) -> Option<Color> {
+        if let Some(&color) = self.cell.get(&cc) {
+            return Some(color);
+        }
+        if !cc.is_ascii_alphabetic() {
+            return None;
+        }
+        let fallback = MINO_TYPES[(cc as usize) % MINO_TYPES.len()];
+        self.cell.get(&fallback).cloned()
+    }
+}
+
+/// Piece types that every theme has a color for.
+const MINO_TYPES: [CellColor; 7] = ['L', 'O', 'J', 'I', 'S', 'Z', 'T'];
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -151,4 +169,13 @@ mod test {
         assert!("!1abf3e".parse::<HexColor>().is_err());
         assert!("1abf3e3".parse::<HexColor>().is_err());
     }
+
+    #[test]
+    fn test_cell_color_fallback() {
+        let colors = Theme::default().to_colors();
+        assert_eq!(colors.cell_color('T'), colors.cell.get(&'T').cloned());
+        assert!(colors.cell_color('X').is_some());
+        assert!(colors.cell_color('u').is_some());
+        assert_eq!(colors.cell_color('.'), None);
+    }
 }
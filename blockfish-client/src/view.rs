@@ -69,6 +69,7 @@ impl<'r> View<'r> {
     ) -> Self {
         let rows = ruleset.visible_rows as u16;
         let cols = ruleset.cols as u16;
+        let geom = Geometry::new(rows, cols, piece_box(&ruleset), DEFAULT_SIZE);
 
         let mut motd = Label::new();
         let mut help = [vec![], vec![]];
@@ -82,7 +83,7 @@ impl<'r> View<'r> {
             motd,
             help,
             colors: theme.to_colors(),
-            geom: Geometry::new(rows, cols, DEFAULT_SIZE),
+            geom,
             queue: Cells::new(None),
             matrix: Cells::new(Some(rows)),
             piece: Cells::new(Some(rows)),
@@ -125,7 +126,11 @@ impl<'r> View<'r> {
         };
 
         let left_right = &[Action::Game(MoveLeft), Action::Game(MoveRight)];
-        let ccw_cw = &[Action::Game(RotateCCW), Action::Game(RotateCW)];
+        let ccw_cw = &[
+            Action::Game(RotateCCW),
+            Action::Game(RotateCW),
+            Action::Game(Rotate180),
+        ];
         let sd_hd = &[Action::Game(SonicDrop), Action::Game(HardDrop)];
         let hold = &[Action::Game(Hold)];
         let undo = &[Action::Game(Undo)];
@@ -134,7 +139,7 @@ impl<'r> View<'r> {
         game_ctrls.resize_with(7, Label::new);
         game_ctrls[0].set("game controls");
         game_ctrls[1].set(&label_text("\u{2190}, \u{2192}:         ", left_right));
-        game_ctrls[2].set(&label_text("ccw, cw:      ", ccw_cw));
+        game_ctrls[2].set(&label_text("ccw, cw, 180: ", ccw_cw));
         game_ctrls[3].set(&label_text("sd, hd:       ", sd_hd));
         game_ctrls[4].set(&label_text("hold:         ", hold));
         game_ctrls[5].set(&label_text("undo:         ", undo));
@@ -662,7 +667,7 @@ impl Cells {
             if rects.is_empty() {
                 continue;
             }
-            if let Some(&color) = colors.cell.get(&cc) {
+            if let Some(color) = colors.cell_color(cc) {
                 cv.set_draw_color(color);
                 for &rect in rects.iter() {
                     match style {
@@ -870,10 +875,10 @@ impl<'r> TreeSidebar<'r> {
         // draw node icons & labels
         for (idx, node) in self.nodes.iter().enumerate() {
             let loc = (idx, node.depth);
-            let color = colors.cell.get(&node.piece).unwrap();
-            let node_rect = geom.tree_node_icon(loc);
-            cv.set_draw_color(*color);
-            cv.fill_rect(node_rect).unwrap();
+            if let Some(color) = colors.cell_color(node.piece) {
+                cv.set_draw_color(color);
+                cv.fill_rect(geom.tree_node_icon(loc)).unwrap();
+            }
             self.labels[idx].paint(cv, geom, loc);
         }
     }
@@ -930,6 +935,10 @@ struct Geometry {
     tree_node_pad: i32,
     // x offset between nodes at adjacent depths
     tree_node_dx: i32,
+    // number of cells between the tops of adjacent next previews
+    preview_rows: u32,
+    // number of cells between the left of the hold piece and the main matrix
+    hold_cols: u32,
     // position of the bottom of the window
     bottom: i32,
     // extent of the main matrix
@@ -946,12 +955,30 @@ struct Geometry {
     tree_scroll: i32,
 }
 
+/// Returns the number of rows and columns needed to draw every piece of `ruleset` in the
+/// hold and next previews. The rows include a gap of at least one row between pieces.
+fn piece_box(ruleset: &Ruleset) -> (u32, u32) {
+    let (mut rows, mut cols) = (0, 0);
+    for typ in ruleset.types() {
+        let coords = ruleset.coords(typ, 0).collect::<Vec<_>>();
+        let top = coords.iter().map(|&(i, _)| i + 1).max().unwrap_or(0);
+        let bottom = coords.iter().map(|&(i, _)| i).min().unwrap_or(0);
+        let right = coords.iter().map(|&(_, j)| j + 1).max().unwrap_or(0);
+        // pieces resting on the bottom of their bounding box need an extra row
+        rows = std::cmp::max(rows, std::cmp::max(top, top - bottom + 1));
+        cols = std::cmp::max(cols, right);
+    }
+    (rows as u32, cols as u32)
+}
+
 impl Geometry {
-    fn new(rows: u16, cols: u16, win: (u32, u32)) -> Self {
+    fn new(rows: u16, cols: u16, (box_rows, box_cols): (u32, u32), win: (u32, u32)) -> Self {
         let mut geom = Self {
             // constant
             rows: rows as u32,
             cols: cols as u32,
+            preview_rows: box_rows,
+            hold_cols: box_cols + 1,
             // TODO: derive these from Resources
             line_height: 19,
             line_height_small: 15,
@@ -1010,15 +1037,15 @@ impl Geometry {
     }
 
     fn hold_cell(&self, i: u16, j: u16) -> Rect {
-        let x0 = self.matrix.left() - (self.cell as i32) * 5;
-        let y0 = self.matrix.top() + (self.cell as i32) * 3;
+        let x0 = self.matrix.left() - (self.cell as i32) * (self.hold_cols as i32);
+        let y0 = self.matrix.top() + (self.cell as i32) * (self.preview_rows as i32);
         self.cell(x0, y0, self.cell, i, j)
     }
 
     fn next_cell(&self, idx: usize, i: u16, j: u16) -> Rect {
         let x0 = self.matrix.right() + (self.cell as i32);
         let mut y0 = self.matrix.top();
-        y0 += (idx as i32 + 1) * (self.cell as i32) * 3;
+        y0 += (idx as i32 + 1) * (self.cell as i32) * (self.preview_rows as i32);
         self.cell(x0, y0, self.cell, i, j)
     }
 
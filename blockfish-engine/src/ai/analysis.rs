@@ -1,8 +1,15 @@
-use super::{state::State, Stats, Suggestion};
-use crate::{finesse::FinesseFinder, place::PlaceFinder, shape::ShapeTable, Config, Input};
+use super::{
+    endgame::{NodeLimitReached, Solver},
+    state::State,
+    Stats, Suggestion, WorkerPool,
+};
+use crate::{finesse::FinesseFinder, place::PlaceFinder, shape::ShapeTable, Color, Config, Input};
 use std::{
-    collections::HashMap,
-    sync::{mpsc, Arc, RwLock},
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, RwLock,
+    },
 };
 
 use super::b_star::{Search, Step};
@@ -17,10 +24,30 @@ pub struct Analysis {
     moves: HashMap<MoveId, Move>,
     trace_inputs: Box<TraceInputsFn>,
     stats: Arc<RwLock<Option<Stats>>>,
+    stop: StopHandle,
+    // iterations and nodes as of the last message received
+    progress: Stats,
+    start_time: std::time::Instant,
     rx: mpsc::Receiver<Msg>,
     all_tx: Option<mpsc::Sender<Suggestion>>,
 }
 
+/// Can be used to stop an analysis early, from any thread. See `Analysis::stop_handle`.
+#[derive(Clone, Debug, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Asks the analysis to stop searching. The analysis finishes as usual, keeping the
+    /// moves found so far.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(super) fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 /// Indicates that the analysis has finished and no new updates to any moves will happen.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AnalysisDone;
@@ -38,11 +65,14 @@ struct Move {
 struct Msg {
     changed_move_id: Option<MoveId>,
     mov: Move,
+    // number of nodes generated so far
+    nodes: usize,
 }
 
 /// Used by the worker thread to send information to the `Analysis` handle.
 struct AnalysisSink {
     stats: Arc<RwLock<Option<Stats>>>,
+    stop: StopHandle,
     tx: mpsc::SyncSender<Msg>,
 }
 
@@ -52,9 +82,11 @@ impl Analysis {
     fn new(trace_inputs: impl Fn(&[usize]) -> Vec<Input> + Send + 'static) -> (AnalysisSink, Self) {
         let (tx, rx) = mpsc::sync_channel(256);
         let stats = Arc::new(RwLock::new(None));
+        let stop = StopHandle::default();
         (
             AnalysisSink {
                 stats: stats.clone(),
+                stop: stop.clone(),
                 tx,
             },
             Analysis {
@@ -62,6 +94,9 @@ impl Analysis {
                 trace_inputs: Box::new(trace_inputs),
                 all_tx: None,
                 stats,
+                stop,
+                progress: Stats::default(),
+                start_time: std::time::Instant::now(),
                 rx,
             },
         )
@@ -79,6 +114,9 @@ impl Analysis {
             }
         }
 
+        self.progress.iterations = msg.mov.iteration;
+        self.progress.nodes = msg.nodes;
+
         // update moves
         if let Some(move_id) = msg.changed_move_id {
             self.moves.insert(move_id, msg.mov);
@@ -127,6 +165,22 @@ impl Analysis {
         }
     }
 
+    /// Blocks until the analysis thread finishes or `timeout` elapses, whichever comes
+    /// first. Returns `true` if the analysis finished.
+    pub fn wait_timeout(&mut self, timeout: std::time::Duration) -> bool {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(msg) => {
+                    self.recv(msg);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => return false,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return true,
+            }
+        }
+    }
+
     /// Returns the `Suggestion` for the given move, containing at most `len`
     /// placements. `len` should be `std::usize::MAX` in order to get the inputs for the
     /// entire sequence. `len` may be `0` to just get the move's rating.
@@ -144,6 +198,27 @@ impl Analysis {
     pub fn stats(&self) -> Option<Stats> {
         self.stats.read().map_or(None, |s| s.clone())
     }
+
+    /// Stops the analysis early. Equivalent to `self.stop_handle().stop()`.
+    pub fn stop(&self) {
+        self.stop.stop();
+    }
+
+    /// Returns a handle that can stop this analysis, e.g. from another thread while this
+    /// handle is blocked in `wait`.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Returns the statistics gathered so far, while the analysis is still running. The
+    /// iteration and node counts are as of the last update received by `poll` or `wait`.
+    /// Returns the final statistics once the analysis has finished.
+    pub fn progress(&self) -> Stats {
+        self.stats().unwrap_or_else(|| Stats {
+            time_taken: self.start_time.elapsed(),
+            ..self.progress.clone()
+        })
+    }
 }
 
 impl AnalysisSink {
@@ -163,17 +238,67 @@ impl AnalysisSink {
 
 // Analysis thread
 
-fn analysis(shtb: Arc<ShapeTable>, cfg: Config, root: State, sink: AnalysisSink) {
+fn analysis(
+    shtb: Arc<ShapeTable>,
+    cfg: Config,
+    root: State,
+    bag: Option<Vec<Color>>,
+    sink: AnalysisSink,
+) {
     let start_time = std::time::Instant::now();
     let mut iteration = 0;
     let mut global_min = std::i64::MAX;
 
+    // near the end of the race, first try to find the exact minimum number of pieces to
+    // reach the goal. moves that are solved this way are not updated by the search. the
+    // solver only gets half of the node budget, so the search still has nodes left over
+    // to rate moves if the solver gives up.
+    let mut solved = HashSet::new();
+    let mut solver_nodes = 0;
+    if Solver::applies_to(&root) {
+        let mut solver = Solver::new(&shtb, cfg.search_limit / 2, bag, &sink.stop);
+        let result = solver.solve(&root);
+        solver_nodes = solver.node_count();
+        match result {
+            Ok(solutions) => {
+                for (idx, sol) in solutions {
+                    iteration += 1;
+                    let move_id = MoveId::new(idx);
+                    let rating = sol.pieces as i64;
+                    log::debug!("{:<2?} --> {:>3?} (exact)", move_id, rating);
+                    global_min = std::cmp::min(rating, global_min);
+                    solved.insert(move_id);
+                    let msg = Msg {
+                        changed_move_id: Some(move_id),
+                        mov: Move {
+                            iteration,
+                            rating,
+                            trace: sol.trace,
+                        },
+                        nodes: solver_nodes,
+                    };
+                    if !sink.send(msg) {
+                        log::warn!("handle disconnected mid-analysis");
+                        return;
+                    }
+                }
+            }
+            Err(NodeLimitReached) => {
+                log::debug!("endgame solver gave up after {} nodes", solver_nodes);
+            }
+        }
+    }
+
     let mut search = Search::new(&shtb, cfg.parameters);
     search.start(root);
 
-    while search.node_count() < cfg.search_limit {
+    while search.node_count() + solver_nodes < cfg.search_limit && !sink.stop.is_stopped() {
         let msg;
         match search.step() {
+            Ok(Step::RatingChanged { move_id, .. }) if solved.contains(&move_id) => {
+                msg = None;
+            }
+
             Ok(Step::RatingChanged {
                 move_id,
                 rating,
@@ -195,6 +320,7 @@ fn analysis(shtb: Arc<ShapeTable>, cfg: Config, root: State, sink: AnalysisSink)
                         rating,
                         trace,
                     },
+                    nodes: search.node_count() + solver_nodes,
                 });
             }
 
@@ -207,6 +333,7 @@ fn analysis(shtb: Arc<ShapeTable>, cfg: Config, root: State, sink: AnalysisSink)
                         rating,
                         trace,
                     },
+                    nodes: search.node_count() + solver_nodes,
                 });
             }
 
@@ -227,7 +354,7 @@ fn analysis(shtb: Arc<ShapeTable>, cfg: Config, root: State, sink: AnalysisSink)
 
     sink.finish(Stats {
         iterations: iteration,
-        nodes: search.node_count(),
+        nodes: search.node_count() + solver_nodes,
         time_taken: std::time::Instant::now() - start_time,
     });
 }
@@ -236,7 +363,7 @@ fn analysis(shtb: Arc<ShapeTable>, cfg: Config, root: State, sink: AnalysisSink)
 
 type TraceInputsFn = dyn Fn(&[usize]) -> Vec<Input> + Send;
 
-fn reconstruct_inputs(shtb: &ShapeTable, state0: State, trace: &[usize]) -> Vec<Input> {
+pub(super) fn reconstruct_inputs(shtb: &ShapeTable, state0: State, trace: &[usize]) -> Vec<Input> {
     let mut pfind = PlaceFinder::new(&shtb);
     let mut ffind = FinesseFinder::new();
     let mut state = state0;
@@ -262,21 +389,44 @@ fn reconstruct_inputs(shtb: &ShapeTable, state0: State, trace: &[usize]) -> Vec<
 
 // Putting it all together
 
-/// Spawns a new analysis, returning a handle to it.
+/// Spawns a new analysis that can be stopped by `stop`, returning a handle to it.
 pub fn spawn(
     shtb: Arc<ShapeTable>,
     cfg: Config,
     root: State,
+    bag: Option<Vec<Color>>,
     all_suggestions_tx: Option<mpsc::Sender<Suggestion>>,
+    pool: Option<&WorkerPool>,
+    stop: StopHandle,
 ) -> Analysis {
     let trace_inputs = {
         let shtb = shtb.clone();
         let state0 = root.clone();
         move |t: &[usize]| reconstruct_inputs(&shtb, state0.clone(), t)
     };
-    let (sink, mut handle) = Analysis::new(trace_inputs);
+    let (mut sink, mut handle) = Analysis::new(trace_inputs);
     handle.all_tx = all_suggestions_tx;
-    std::thread::spawn(move || analysis(shtb, cfg, root, sink));
+    sink.stop = stop.clone();
+    handle.stop = stop;
+    let job = move || analysis(shtb, cfg, root, bag, sink);
+    match pool {
+        Some(pool) => {
+            // the analysis finishes right away if it could not be started
+            if let Err(e) = pool.execute(job) {
+                log::error!("failed to start analysis: {}", e);
+            }
+        }
+        None => {
+            std::thread::spawn(job);
+        }
+    }
+    handle
+}
+
+/// Returns the handle of an analysis that has already finished without finding any moves.
+pub(super) fn finished() -> Analysis {
+    let (sink, handle) = Analysis::new(|_: &[usize]| vec![]);
+    sink.finish(Stats::default());
     handle
 }
 
@@ -299,7 +449,8 @@ mod test {
         };
         assert!(sink.send(Msg {
             changed_move_id: Some(MoveId::n(6)),
-            mov
+            mov,
+            nodes: 10,
         }));
         assert_eq!(handle.poll(), Ok(Some(MoveId::n(6))));
         assert_eq!(handle.suggestion(MoveId::n(6), 0).rating, 1234);
@@ -315,7 +466,8 @@ mod test {
                 iteration: 1,
                 rating: 1234,
                 trace: vec![6, 7, 8],
-            }
+            },
+            nodes: 10,
         }));
         assert!(sink.send(Msg {
             changed_move_id: Some(MoveId::n(7)),
@@ -323,7 +475,8 @@ mod test {
                 iteration: 2,
                 rating: 1233,
                 trace: vec![7, 8, 9, 10],
-            }
+            },
+            nodes: 20,
         }));
         assert!(sink.send(Msg {
             changed_move_id: Some(MoveId::n(6)),
@@ -331,7 +484,8 @@ mod test {
                 iteration: 3,
                 rating: 1233,
                 trace: vec![6, 7, 9],
-            }
+            },
+            nodes: 30,
         }));
         assert!(sink.send(Msg {
             changed_move_id: None,
@@ -339,7 +493,8 @@ mod test {
                 iteration: 3,
                 rating: 1239,
                 trace: vec![6, 7],
-            }
+            },
+            nodes: 40,
         }));
     }
 
@@ -352,6 +507,26 @@ mod test {
         assert_eq!(handle.poll(), Err(AnalysisDone));
     }
 
+    #[test]
+    fn test_analysis_wait_timeout() {
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        let timeout = std::time::Duration::from_millis(10);
+        assert!(sink.send(Msg {
+            changed_move_id: Some(MoveId::n(6)),
+            mov: Move {
+                iteration: 1,
+                rating: 1234,
+                trace: vec![6, 7, 8],
+            },
+            nodes: 10,
+        }));
+        assert!(!handle.wait_timeout(timeout));
+        assert_eq!(handle.all_moves().count(), 1);
+        sink.finish(Stats::default());
+        assert!(handle.wait_timeout(timeout));
+        assert_eq!(handle.poll(), Err(AnalysisDone));
+    }
+
     #[test]
     fn test_analysis_suggestion() {
         let (sink, mut handle) = Analysis::new(spam_hd_traces);
@@ -405,6 +580,23 @@ mod test {
         assert_eq!(handle.stats(), Some(s));
     }
 
+    #[test]
+    fn test_analysis_progress() {
+        let (sink, mut handle) = Analysis::new(spam_hd_traces);
+        assert_eq!(handle.progress().nodes, 0);
+        example_analysis(sink);
+        assert_eq!(handle.poll(), Ok(Some(MoveId::n(6))));
+        assert_eq!(
+            (handle.progress().iterations, handle.progress().nodes),
+            (1, 10)
+        );
+        handle.wait();
+        assert_eq!(
+            (handle.progress().iterations, handle.progress().nodes),
+            (3, 40)
+        );
+    }
+
     #[test]
     fn test_analysis_all_suggestions() {
         let (sink, mut handle) = Analysis::new(spam_hd_traces);
@@ -436,6 +628,80 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_analysis_stop() {
+        use crate::{ai::Snapshot, shape::srs, BasicMatrix};
+        let snapshot = Snapshot {
+            hold: None,
+            queue: "LJSZOTILJSZOTI".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        let cfg = Config {
+            search_limit: std::usize::MAX,
+            ..Config::default()
+        };
+        // not a cheese race, so the search runs right away instead of the endgame solver
+        let mut root: State = snapshot.into();
+        root.set_goal(crate::ai::Goal::Survival);
+        let mut handle = spawn(
+            Arc::new(srs()),
+            cfg,
+            root,
+            None,
+            None,
+            None,
+            StopHandle::default(),
+        );
+        let stop = handle.stop_handle();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            stop.stop();
+        });
+        // would never finish without stopping
+        handle.wait();
+        assert!(handle.stats().is_some());
+        assert!(handle.all_moves().count() > 0);
+    }
+
+    #[test]
+    fn test_analysis_worker_pool() {
+        use crate::{ai::Snapshot, shape::srs, BasicMatrix};
+        let snapshot = Snapshot {
+            hold: None,
+            queue: "LJSZ".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        let shtb = Arc::new(srs());
+        let pool = WorkerPool::new(1);
+        // the second analysis waits for the first one to free up the worker
+        let mut handles = (0..2)
+            .map(|_| {
+                let (cfg, root) = (Config::default(), snapshot.clone().into());
+                spawn(
+                    shtb.clone(),
+                    cfg,
+                    root,
+                    None,
+                    None,
+                    Some(&pool),
+                    StopHandle::default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for handle in handles.iter_mut() {
+            handle.wait();
+            assert!(handle.all_moves().count() > 0);
+        }
+    }
+
     #[test]
     fn test_analysis_is_send() {
         let (_, handle) = Analysis::new(spam_hd_traces);
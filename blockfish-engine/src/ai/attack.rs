@@ -0,0 +1,75 @@
+use crate::{matrix::BasicMatrix, place::Place};
+
+pub use block_stacker::{Chain, Spin};
+
+/// Returns the kind of T-spin performed by placement `pl` into `matrix` (before the
+/// piece is added to it).
+///
+/// The inputs used to reach the placement are not known here, so a T piece counts as
+/// spun if it cannot move up, left or right, and the 3-corner rule is used from there.
+/// The spin is a mini unless both corners next to the pointed side of the T are filled.
+pub fn t_spin(matrix: &BasicMatrix, pl: &Place) -> Spin {
+    if pl.shape.color().as_char() != 'T' {
+        return Spin::None;
+    }
+    let (i, j, r) = pl.tf;
+    let immobile = [(i + 1, j), (i, j - 1), (i, j + 1)]
+        .iter()
+        .all(|&(i, j)| pl.shape.intersects(matrix, (i, j, r)));
+    if !immobile {
+        return Spin::None;
+    }
+
+    let cells = pl.shape.cells(pl.tf).collect::<Vec<_>>();
+    block_stacker::corner_spin(&cells, |i, j| {
+        i < 0 || j < 0 || matrix.get((i as u16, j as u16))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{basic_matrix, place::PlaceFinder, shape::srs, Color};
+
+    #[test]
+    fn test_t_spin() {
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs);
+        let spins = |pfind: &mut PlaceFinder, matrix: &BasicMatrix| {
+            pfind.reset_matrix(matrix);
+            pfind.push_shape(Color::n('T'), false);
+            pfind
+                .map(|pl| {
+                    let mut after = matrix.clone();
+                    pl.shape.blit_to(&mut after, pl.tf);
+                    let lines = (0..after.rows())
+                        .filter(|&i| after.gaps(i).next().is_none())
+                        .count();
+                    (lines, t_spin(matrix, &pl))
+                })
+                .collect::<Vec<_>>()
+        };
+        // T-spin double slot in columns 3-5
+        let matrix = basic_matrix![
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, __, __, __, xx, xx, xx, xx],
+            [__, __, __, xx, __, __, __, __, __, __],
+        ];
+        let spins1 = spins(&mut pfind, &matrix);
+        assert!(spins1.contains(&(2, Spin::Full)));
+        // flat side down under the overhang, only one corner on the pointed side
+        assert!(spins1.contains(&(1, Spin::Mini)));
+        assert!(spins1
+            .iter()
+            .all(|&(lines, spin)| lines > 0 || spin == Spin::None));
+        // without the overhang, the T can move up out of the slot
+        let matrix = basic_matrix![
+            [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+            [xx, xx, xx, __, __, __, xx, xx, xx, xx],
+        ];
+        let spins2 = spins(&mut pfind, &matrix);
+        assert!(spins2.contains(&(2, Spin::None)));
+        assert!(spins2.iter().all(|&(_, spin)| spin == Spin::None));
+    }
+}
@@ -1,7 +1,4 @@
-use super::{
-    eval::{eval, penalty},
-    state::State,
-};
+use super::{eval::penalty, state::State};
 use crate::{
     config::Parameters,
     place::{Place, PlaceFinder},
@@ -202,7 +199,7 @@ impl<'s> Search<'s> {
         let trace = node.trace().collect();
         let move_id = match node.trace.get(0) {
             Some(&idx) => {
-                let m_id = MoveId(idx as _);
+                let m_id = MoveId::new(idx as _);
                 let best = self.move_best.entry(m_id).or_insert(std::i64::MAX);
                 if rating < *best {
                     *best = rating;
@@ -222,6 +219,11 @@ fn default_level() -> BinaryHeap<Node> {
 }
 
 impl MoveId {
+    /// Returns the id of the move that begins with placement index `idx`.
+    pub(super) fn new(idx: usize) -> Self {
+        Self(idx as u8)
+    }
+
     #[cfg(test)]
     pub fn n(x: i32) -> Self {
         Self(x as u8)
@@ -239,7 +241,7 @@ struct Node {
 
 impl Node {
     fn root(params: &Parameters, state: State) -> Self {
-        let h = eval(state.matrix()).score(params);
+        let h = state.eval().score(params);
         Self {
             state,
             trace: vec![],
@@ -256,7 +258,7 @@ impl Node {
         let mut trace = self.trace.clone();
         trace.push(pl.idx as u8);
         let g = penalty(params, trace.len());
-        let h = eval(state.matrix()).score(params);
+        let h = state.eval().score(params);
         Self {
             state,
             trace,
@@ -273,8 +275,7 @@ impl Node {
     /// Returns the rating value for this node. Lower is always better.
     fn rating(&self) -> i64 {
         if self.state.reached_goal() {
-            // just use number-of-pieces as rating
-            self.trace.len() as i64
+            self.state.goal().rating(self.trace.len())
         } else {
             self.f.saturating_add(self.parent_f)
         }
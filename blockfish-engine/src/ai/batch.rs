@@ -0,0 +1,152 @@
+use super::{Analysis, Snapshot, Stats, StopHandle};
+use std::time::{Duration, Instant};
+
+type StartFn = dyn FnMut(Snapshot, StopHandle) -> Analysis + Send;
+
+/// How long to wait on one running analysis before checking on the next.
+const WAIT_SLICE: Duration = Duration::from_millis(10);
+
+/// A handle to the analyses of a list of snapshots, see `AI::analyze_batch`. No analysis
+/// is started until the batch is iterated. Iterating over the batch blocks until the next
+/// analysis finishes, yielding it along with the index of its snapshot. Analyses are
+/// yielded in the order they finish.
+pub struct Batch {
+    start: Box<StartFn>,
+    pending: std::iter::Enumerate<std::vec::IntoIter<Snapshot>>,
+    running: Vec<(usize, Analysis)>,
+    max_running: usize,
+    stop: StopHandle,
+    // totals of the analyses yielded so far
+    stats: Stats,
+    start_time: Instant,
+}
+
+impl Batch {
+    /// Constructs a batch that analyzes each of `snapshots` using `start`, running at
+    /// most `max_running` analyses at once. Every analysis is given the same stop handle.
+    pub(super) fn new(
+        snapshots: Vec<Snapshot>,
+        max_running: usize,
+        start: impl FnMut(Snapshot, StopHandle) -> Analysis + Send + 'static,
+    ) -> Self {
+        Self {
+            start: Box::new(start),
+            pending: snapshots.into_iter().enumerate(),
+            running: Vec::new(),
+            max_running: std::cmp::max(max_running, 1),
+            stop: StopHandle::default(),
+            stats: Stats::default(),
+            start_time: Instant::now(),
+        }
+    }
+
+    /// Starts pending analyses until `max_running` are running.
+    fn fill(&mut self) {
+        while self.running.len() < self.max_running {
+            match self.pending.next() {
+                Some((idx, snapshot)) => {
+                    let analysis = (self.start)(snapshot, self.stop.clone());
+                    self.running.push((idx, analysis));
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Returns a handle that stops the whole batch: the running analyses finish early,
+    /// and the pending ones finish as soon as they start.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Returns the total number of iterations and nodes of the analyses yielded so far,
+    /// and the time since the batch began.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            time_taken: self.start_time.elapsed(),
+            ..self.stats.clone()
+        }
+    }
+}
+
+impl Iterator for Batch {
+    type Item = (usize, Analysis);
+
+    fn next(&mut self) -> Option<(usize, Analysis)> {
+        self.fill();
+        // take turns waiting on each analysis, so that every one of them keeps being
+        // drained until the first finishes
+        let pos = loop {
+            if self.running.is_empty() {
+                return None;
+            }
+            let done = self
+                .running
+                .iter_mut()
+                .position(|(_, analysis)| analysis.wait_timeout(WAIT_SLICE));
+            if let Some(pos) = done {
+                break pos;
+            }
+        };
+        let (idx, analysis) = self.running.swap_remove(pos);
+        // keep the others busy while the caller deals with this one
+        self.fill();
+        let stats = analysis.progress();
+        self.stats.iterations += stats.iterations;
+        self.stats.nodes += stats.nodes;
+        Some((idx, analysis))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::AI;
+    use crate::{BasicMatrix, Color, Config};
+
+    fn snapshot(queue: &str) -> super::Snapshot {
+        super::Snapshot {
+            hold: None,
+            queue: queue.chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        let ai = AI::new(Config::default());
+        let snapshots = vec![snapshot("LJSZ"), snapshot("OTI"), snapshot("SZ")];
+        let mut batch = ai.analyze_batch(snapshots, Config::default(), 2);
+        let mut indices = vec![];
+        let mut nodes = 0;
+        for (idx, analysis) in &mut batch {
+            assert!(analysis.all_moves().count() > 0);
+            indices.push(idx);
+            nodes += analysis.stats().unwrap().nodes;
+        }
+        indices.sort();
+        assert_eq!(indices, [0, 1, 2]);
+        assert_eq!(batch.stats().nodes, nodes);
+    }
+
+    #[test]
+    fn test_batch_stop() {
+        let cfg = Config {
+            search_limit: std::usize::MAX,
+            ..Config::default()
+        };
+        let ai = AI::new(cfg.clone());
+        let snapshots = vec![snapshot("LJSZOTI"), snapshot("OTILJSZ"), snapshot("SZOTI")];
+        let mut batch = ai.analyze_batch(snapshots, cfg, 2);
+        let stop = batch.stop_handle();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            stop.stop();
+        });
+        // would never finish if the pending analysis ignored the stop
+        assert_eq!(batch.by_ref().count(), 3);
+    }
+}
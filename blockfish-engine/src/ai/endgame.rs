@@ -0,0 +1,370 @@
+use super::{state::State, Goal, StopHandle};
+use crate::{
+    common::Color,
+    matrix::BasicMatrix,
+    place::{Place, PlaceFinder},
+    shape::ShapeTable,
+};
+use std::collections::HashSet;
+
+/// Boards with at most this many garbage rows left are small enough to be solved
+/// exhaustively.
+pub const MAX_GARBAGE_ROWS: usize = 4;
+
+/// Longest sequence of pieces to search for a solution.
+pub const MAX_DEPTH: usize = 6;
+
+/// Exhaustive solver for the minimum number of pieces needed to clear the bottom row of
+/// the matrix, i.e. the number of pieces for a sequence to reach the goal.
+///
+/// If the contents of the current bag are known, pieces beyond the queue are taken into
+/// account as well: a solution must then reach the goal no matter which of the remaining
+/// pieces in the bag comes next. Otherwise, only the pieces in the queue are used.
+pub struct Solver<'s> {
+    pfind: PlaceFinder<'s>,
+    bag: Option<Vec<Color>>,
+    // every piece in a full bag
+    full_bag: Vec<Color>,
+    // number of cells in the largest piece
+    max_piece_size: usize,
+    node_count: usize,
+    node_limit: usize,
+    stop: StopHandle,
+    // (state, depth) pairs that are known to not reach the goal from the current root
+    failed: HashSet<(Key, usize)>,
+}
+
+/// A move that was proven to reach the goal in the minimum number of pieces.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Solution {
+    /// Number of pieces needed to reach the goal.
+    pub pieces: usize,
+    /// Placement trace of a sequence reaching the goal, starting with the move. The trace
+    /// stops once the queue runs out, since later placements depend on the pieces drawn.
+    pub trace: Vec<usize>,
+}
+
+/// Indicates that the solver was stopped by the node limit, or by its stop handle, before
+/// finding a solution.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NodeLimitReached;
+
+#[derive(Clone, Eq, PartialEq, Hash)]
+struct Key {
+    matrix: BasicMatrix,
+    queue: Vec<Color>,
+    has_held: bool,
+    bag: Option<Vec<Color>>,
+}
+
+impl<'s> Solver<'s> {
+    /// Constructs a new solver that gives up after generating `node_limit` nodes, or once
+    /// `stop` is stopped. `bag` is the set of pieces remaining in the bag after the queue,
+    /// if known.
+    pub fn new(
+        shtb: &'s ShapeTable,
+        node_limit: usize,
+        bag: Option<Vec<Color>>,
+        stop: &StopHandle,
+    ) -> Self {
+        Self {
+            pfind: PlaceFinder::new(shtb),
+            bag,
+            full_bag: shtb.colors(),
+            max_piece_size: shtb.piece_sizes().last().cloned().unwrap_or(4),
+            node_count: 0,
+            node_limit,
+            stop: stop.clone(),
+            failed: HashSet::with_capacity(4096),
+        }
+    }
+
+    /// Returns the total number of generated nodes.
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    /// Returns `true` if `root` has few enough garbage rows left to be solved
+    /// exhaustively. Only applies to the cheese goal.
+    pub fn applies_to(root: &State) -> bool {
+        root.goal() == Goal::Cheese && root.garbage_rows() <= MAX_GARBAGE_ROWS
+    }
+
+    /// Finds every move from `root` that reaches the goal in the fewest number of pieces,
+    /// searching up to `MAX_DEPTH` pieces. Returns the moves' solutions, indexed by
+    /// placement index, or an empty list if no move reaches the goal within `MAX_DEPTH`
+    /// pieces.
+    pub fn solve(&mut self, root: &State) -> Result<Vec<(usize, Solution)>, NodeLimitReached> {
+        self.failed.clear();
+        let bag = self.bag.clone();
+        let moves = self.placements(root);
+        for depth in 1..=MAX_DEPTH {
+            let mut solutions = vec![];
+            for pl in moves.iter() {
+                let mut state = root.clone();
+                state.place(pl);
+                self.count_node()?;
+                let pv = if state.reached_goal() {
+                    Some(vec![])
+                } else {
+                    self.search(&state, &bag, depth - 1)?
+                };
+                if let Some(mut pv) = pv {
+                    pv.insert(0, pl.idx);
+                    let sol = Solution {
+                        pieces: depth,
+                        trace: pv,
+                    };
+                    solutions.push((pl.idx, sol));
+                }
+            }
+            if !solutions.is_empty() {
+                return Ok(solutions);
+            }
+        }
+        Ok(vec![])
+    }
+
+    /// Searches for a way to reach the goal from `state` within `depth` pieces. Returns the
+    /// principal variation if one was found.
+    fn search(
+        &mut self,
+        state: &State,
+        bag: &Option<Vec<Color>>,
+        depth: usize,
+    ) -> Result<Option<Vec<usize>>, NodeLimitReached> {
+        if depth == 0 || lower_bound(state.matrix(), self.max_piece_size) > depth {
+            return Ok(None);
+        }
+        if let (true, Some(bag)) = (state.queue_len() < 2, bag.as_ref()) {
+            // the next piece is unknown, so every possible piece must reach the goal
+            let mut colors = bag.clone();
+            colors.sort_unstable();
+            colors.dedup();
+            for color in colors {
+                let mut state = state.clone();
+                state.push_back(color);
+                let bag = Some(draw(bag, color, &self.full_bag));
+                if self.search(&state, &bag, depth)?.is_none() {
+                    return Ok(None);
+                }
+            }
+            return Ok(Some(vec![]));
+        }
+
+        let (queue, has_held) = state.queue();
+        let key = Key {
+            matrix: state.matrix().clone(),
+            queue: queue.to_vec(),
+            has_held,
+            bag: bag.clone(),
+        };
+        if self.failed.contains(&(key.clone(), depth)) {
+            return Ok(None);
+        }
+        for pl in self.placements(state) {
+            let mut succ = state.clone();
+            succ.place(&pl);
+            self.count_node()?;
+            let pv = if succ.reached_goal() {
+                Some(vec![])
+            } else {
+                self.search(&succ, bag, depth - 1)?
+            };
+            if let Some(mut pv) = pv {
+                // only keep the trace while it is made of known pieces
+                if state.queue_len() >= 2 || bag.is_none() {
+                    pv.insert(0, pl.idx);
+                } else {
+                    pv.clear();
+                }
+                return Ok(Some(pv));
+            }
+        }
+        self.failed.insert((key, depth));
+        Ok(None)
+    }
+
+    fn placements(&mut self, state: &State) -> Vec<Place<'s>> {
+        state.placements(&mut self.pfind).collect()
+    }
+
+    fn count_node(&mut self) -> Result<(), NodeLimitReached> {
+        self.node_count += 1;
+        if self.node_count > self.node_limit || self.stop.is_stopped() {
+            Err(NodeLimitReached)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns the minimum number of pieces needed to fill in the bottom row of `matrix`,
+/// using pieces of at most `piece_size` cells.
+fn lower_bound(matrix: &BasicMatrix, piece_size: usize) -> usize {
+    if matrix.rows() == 0 {
+        return 0;
+    }
+    let empty = (0..matrix.cols()).filter(|&j| !matrix.get((0, j))).count();
+    (empty + piece_size - 1) / piece_size
+}
+
+/// Removes `color` from `bag`, starting a new bag of `full_bag` if it becomes empty.
+fn draw(bag: &[Color], color: Color, full_bag: &[Color]) -> Vec<Color> {
+    let mut bag = bag.to_vec();
+    if let Some(i) = bag.iter().position(|&c| c == color) {
+        bag.remove(i);
+    }
+    if bag.is_empty() {
+        bag.extend_from_slice(full_bag);
+    }
+    bag
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ai::Snapshot, basic_matrix, shape::srs};
+
+    fn state(matrix: BasicMatrix, queue: &str) -> State {
+        Snapshot {
+            hold: None,
+            queue: queue.chars().map(Color::n).collect(),
+            matrix,
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_lower_bound() {
+        let (xx, __) = (true, false);
+        assert_eq!(lower_bound(&BasicMatrix::with_cols(10), 4), 0);
+        assert_eq!(
+            lower_bound(&basic_matrix![[xx, xx, xx, xx, __, xx, xx, xx, xx, xx]], 4),
+            1
+        );
+        let matrix = basic_matrix![[xx, __, __, __, __, __, xx, xx, xx, xx]];
+        assert_eq!(lower_bound(&matrix, 4), 2);
+        // a single pentomino may fill all five cells
+        assert_eq!(lower_bound(&matrix, 5), 1);
+    }
+
+    #[test]
+    fn test_draw() {
+        let full_bag = srs().colors();
+        let bag = "TIZ".chars().map(Color::n).collect::<Vec<_>>();
+        assert_eq!(
+            draw(&bag, Color::n('I'), &full_bag),
+            [Color::n('T'), Color::n('Z')]
+        );
+        assert_eq!(draw(&[Color::n('O')], Color::n('O'), &full_bag).len(), 7);
+    }
+
+    #[test]
+    fn test_solve_known_queue() {
+        let (xx, __) = (true, false);
+        let srs = srs();
+        // only the I (placed second, using hold) can fill the bottom row in one piece
+        let root = state(
+            basic_matrix![[xx, xx, xx, __, __, __, __, xx, xx, xx]],
+            "OIT",
+        );
+        let mut solver = Solver::new(&srs, 100_000, None, &StopHandle::default());
+        let sols = solver.solve(&root).unwrap();
+        assert!(!sols.is_empty());
+        for (idx, sol) in sols.iter() {
+            assert_eq!(sol.pieces, 1);
+            assert_eq!(sol.trace, [*idx]);
+        }
+
+        // two pieces needed
+        let root = state(
+            basic_matrix![[xx, __, __, __, __, __, __, xx, xx, xx]],
+            "IIO",
+        );
+        let sols = solver.solve(&root).unwrap();
+        assert!(sols.iter().all(|(_, sol)| sol.pieces == 2));
+        assert!(sols.iter().all(|(_, sol)| sol.trace.len() == 2));
+    }
+
+    #[test]
+    fn test_solve_same_next_pieces() {
+        let (xx, __) = (true, false);
+        let srs = srs();
+        // only a vertical I can reach the bottom of the well
+        let matrix = basic_matrix![
+            [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+            [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+            [xx, xx, xx, __, xx, xx, xx, xx, xx, xx]
+        ];
+        let mut solver = Solver::new(&srs, 100_000, None, &StopHandle::default());
+        assert_eq!(solver.solve(&state(matrix.clone(), "OOOO")), Ok(vec![]));
+        // the queues only differ in the last piece, which must not share failures
+        let sols = solver.solve(&state(matrix, "OOOI")).unwrap();
+        assert!(!sols.is_empty());
+        assert!(sols.iter().all(|(_, sol)| sol.pieces == 3));
+    }
+
+    #[test]
+    fn test_applies_to() {
+        let (xx, __) = (true, false);
+        let garbage = [xx, xx, xx, xx, __, xx, xx, xx, xx, xx];
+        let stack = [xx, __, __, xx, __, xx, xx, xx, xx, xx];
+        assert!(Solver::applies_to(&state(
+            basic_matrix![garbage, garbage, stack, stack, stack, stack],
+            "T"
+        )));
+        assert!(!Solver::applies_to(&state(
+            basic_matrix![garbage, garbage, garbage, garbage, garbage],
+            "T"
+        )));
+    }
+
+    #[test]
+    fn test_solve_unknown_pieces() {
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let matrix = basic_matrix![[xx, xx, xx, __, __, __, __, xx, xx, xx]];
+        // the queue cannot fill the row, but the only piece left in the bag can
+        let root = state(matrix.clone(), "O");
+        let mut solver = Solver::new(&srs, 100_000, None, &StopHandle::default());
+        assert_eq!(solver.solve(&root), Ok(vec![]));
+        let mut solver = Solver::new(
+            &srs,
+            100_000,
+            Some(vec![Color::n('I')]),
+            &StopHandle::default(),
+        );
+        let sols = solver.solve(&root).unwrap();
+        assert!(sols.iter().all(|(_, sol)| sol.pieces == 2));
+        // the trace stops before the unknown piece
+        assert!(sols.iter().all(|(_, sol)| sol.trace.len() == 1));
+    }
+
+    #[test]
+    fn test_solve_node_limit() {
+        let srs = srs();
+        let root = state(BasicMatrix::with_cols(10), "IOTLJSZ");
+        let mut solver = Solver::new(&srs, 100, None, &StopHandle::default());
+        assert_eq!(solver.solve(&root), Err(NodeLimitReached));
+    }
+
+    #[test]
+    fn test_solve_stopped() {
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let root = state(
+            basic_matrix![[xx, xx, xx, __, __, __, __, xx, xx, xx]],
+            "OIT",
+        );
+        let stop = StopHandle::default();
+        stop.stop();
+        let mut solver = Solver::new(&srs, 100_000, None, &stop);
+        assert_eq!(solver.solve(&root), Err(NodeLimitReached));
+        assert_eq!(solver.node_count(), 1);
+    }
+}
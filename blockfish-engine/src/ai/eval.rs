@@ -9,6 +9,8 @@ pub struct Eval {
     pub rows: u16,
     pub piece_estimate: u16,
     pub i_dependencies: u16,
+    /// Number of garbage lines sent to reach this state.
+    pub attack: u16,
 }
 
 impl Eval {
@@ -19,6 +21,7 @@ impl Eval {
         params.row_factor * (self.rows as i64)
             + params.piece_estimate_factor * (self.piece_estimate as i64)
             + params.i_dependency_factor * (self.i_dependencies as i64)
+            - params.attack_factor * (self.attack as i64)
     }
 }
 
@@ -29,17 +32,20 @@ pub fn penalty(params: &Parameters, depth: usize) -> i64 {
     (depth as i64) * params.piece_penalty
 }
 
-/// Evaluates a matrix, returning the different heuristic values.
-pub fn eval(matrix: &BasicMatrix) -> Eval {
+/// Evaluates a matrix, returning the different heuristic values. `piece_size` is the
+/// number of cells in the largest piece, which is used to estimate the fewest pieces
+/// needed to fill an area.
+pub fn eval(matrix: &BasicMatrix, piece_size: usize) -> Eval {
     Eval {
         rows: matrix.rows(),
-        piece_estimate: piece_estimate(matrix.clone()),
+        piece_estimate: piece_estimate(matrix.clone(), piece_size as u16),
         i_dependencies: i_dependencies(&matrix, 0..matrix.rows()).count() as _,
+        attack: 0,
     }
 }
 
 /// Mystery's residue-based minimum piece estimate algorithm.
-fn piece_estimate(mut matrix: BasicMatrix) -> u16 {
+fn piece_estimate(mut matrix: BasicMatrix, piece_size: u16) -> u16 {
     let mut pieces = 0;
     let mut depth = 0;
 
@@ -47,7 +53,7 @@ fn piece_estimate(mut matrix: BasicMatrix) -> u16 {
     while let Some((i, res)) = covered_hole(&matrix, &mut residue_buf) {
         let rows = (i + 1)..res.end;
         let pieces_to_fill: u16 = negative_spaces(&matrix, rows.clone())
-            .map(|area| (area + 3) / 4)
+            .map(|area| (area + piece_size - 1) / piece_size)
             .sum();
 
         matrix.remove_rows(rows);
@@ -234,6 +240,18 @@ mod test {
         assert_eq!(irs(&xs, &[11..15]), [(2, 0)]);
     }
 
+    #[test]
+    fn test_piece_estimate_sizes() {
+        let (xx, __) = (true, false);
+        // the 9 cells above the covered hole take 3 tetrominoes or 2 pentominoes
+        let matrix = basic_matrix![
+            [__, xx, xx, xx, xx, xx, xx, xx, xx, xx],
+            [xx, __, __, __, __, __, __, __, __, __],
+        ];
+        assert_eq!(piece_estimate(matrix.clone(), 4), 3);
+        assert_eq!(piece_estimate(matrix, 5), 2);
+    }
+
     fn neg_space(mat: BasicMatrix) -> Vec<u16> {
         let mut nss = negative_spaces(&mat, 0..mat.rows()).collect::<Vec<_>>();
         nss.sort();
@@ -0,0 +1,180 @@
+use crate::BasicMatrix;
+use thiserror::Error;
+
+/// The objective of the engine, which decides when a sequence of placements is finished
+/// and how finished sequences are rated.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Goal {
+    /// Clear the bottom row of the matrix, as in a cheese race.
+    Cheese,
+    /// Clear the given number of lines.
+    Sprint(usize),
+    /// Clear every cell in the matrix.
+    PerfectClear,
+    /// No finishing condition, just keep the stack as clean as possible.
+    Survival,
+}
+
+impl Default for Goal {
+    fn default() -> Self {
+        Goal::Cheese
+    }
+}
+
+/// Progress made by a sequence of placements, used to check if it reached the goal.
+pub(super) struct Progress<'a> {
+    /// The matrix after the last placement.
+    pub matrix: &'a BasicMatrix,
+    /// `true` if the original bottom row of the matrix has been cleared.
+    pub bottom_cleared: bool,
+    /// Total number of lines cleared.
+    pub lines_cleared: usize,
+}
+
+impl Goal {
+    /// Returns `true` if `progress` satisfies this goal.
+    pub(super) fn is_reached(&self, progress: &Progress) -> bool {
+        match *self {
+            Goal::Cheese => progress.bottom_cleared,
+            Goal::Sprint(lines) => progress.lines_cleared >= lines,
+            Goal::PerfectClear => progress.lines_cleared > 0 && progress.matrix.rows() == 0,
+            Goal::Survival => false,
+        }
+    }
+
+    /// Returns this goal for a game where `lines_cleared` lines have already been
+    /// cleared, since the engine only counts lines cleared during its search. Only sprint
+    /// goals are affected.
+    pub fn remaining(&self, lines_cleared: usize) -> Goal {
+        match *self {
+            Goal::Sprint(lines) => Goal::Sprint(lines.saturating_sub(lines_cleared)),
+            goal => goal,
+        }
+    }
+
+    /// Returns the rating of a sequence of `pieces` placements that reached this goal.
+    /// These ratings are better than the rating of any unfinished sequence, unless the
+    /// evaluation rewards attack sent.
+    pub(super) fn rating(&self, pieces: usize) -> i64 {
+        match *self {
+            // each of these is finished faster with fewer pieces
+            Goal::Cheese | Goal::Sprint(_) | Goal::PerfectClear => pieces as i64,
+            // never reached, so no sequence is finished
+            Goal::Survival => std::i64::MAX,
+        }
+    }
+}
+
+// Parsing / printing
+
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+#[error("expected 'cheese', 'sprint:<lines>', 'pc' or 'survival'")]
+pub struct ParseGoalError;
+
+impl std::str::FromStr for Goal {
+    type Err = ParseGoalError;
+    fn from_str(s: &str) -> Result<Self, ParseGoalError> {
+        match s {
+            "cheese" => Ok(Goal::Cheese),
+            "pc" => Ok(Goal::PerfectClear),
+            "survival" => Ok(Goal::Survival),
+            _ => {
+                let lines = s.strip_prefix("sprint:").ok_or(ParseGoalError)?;
+                let lines = lines.parse().map_err(|_| ParseGoalError)?;
+                Ok(Goal::Sprint(lines))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Goal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Goal::Cheese => f.write_str("cheese"),
+            Goal::Sprint(lines) => write!(f, "sprint:{}", lines),
+            Goal::PerfectClear => f.write_str("pc"),
+            Goal::Survival => f.write_str("survival"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::basic_matrix;
+
+    #[test]
+    fn test_parse_goal() {
+        for &goal in &[
+            Goal::Cheese,
+            Goal::Sprint(40),
+            Goal::PerfectClear,
+            Goal::Survival,
+        ] {
+            assert_eq!(goal.to_string().parse::<Goal>(), Ok(goal));
+        }
+        assert_eq!("sprint".parse::<Goal>(), Err(ParseGoalError));
+        assert_eq!("sprint:x".parse::<Goal>(), Err(ParseGoalError));
+        assert_eq!("".parse::<Goal>(), Err(ParseGoalError));
+    }
+
+    #[test]
+    fn test_goal_is_reached() {
+        let (xx, __) = (true, false);
+        let empty = BasicMatrix::with_cols(10);
+        let garbage = basic_matrix![[xx, xx, __, xx, xx, xx, xx, xx, xx, xx]];
+        let progress = |matrix, bottom_cleared, lines_cleared| Progress {
+            matrix,
+            bottom_cleared,
+            lines_cleared,
+        };
+        assert!(Goal::Cheese.is_reached(&progress(&garbage, true, 1)));
+        assert!(!Goal::Cheese.is_reached(&progress(&garbage, false, 1)));
+        assert!(Goal::Sprint(4).is_reached(&progress(&garbage, false, 4)));
+        assert!(!Goal::Sprint(4).is_reached(&progress(&empty, true, 3)));
+        assert!(Goal::PerfectClear.is_reached(&progress(&empty, true, 2)));
+        assert!(!Goal::PerfectClear.is_reached(&progress(&empty, false, 0)));
+        assert!(!Goal::PerfectClear.is_reached(&progress(&garbage, true, 2)));
+        assert!(!Goal::Survival.is_reached(&progress(&empty, true, 2)));
+    }
+
+    #[test]
+    fn test_goal_remaining() {
+        assert_eq!(Goal::Sprint(40).remaining(38), Goal::Sprint(2));
+        assert_eq!(Goal::Sprint(40).remaining(41), Goal::Sprint(0));
+        assert_eq!(Goal::Cheese.remaining(38), Goal::Cheese);
+    }
+
+    #[test]
+    fn test_sprint_reachable_from_root() {
+        use crate::{
+            ai::{Snapshot, AI},
+            Color, Config,
+        };
+        let (xx, __) = (true, false);
+        let row = [xx, xx, xx, xx, xx, xx, __, __, __, __];
+        let best_rating = |goal| {
+            let snapshot = Snapshot {
+                hold: None,
+                queue: vec![Color::n('I')],
+                matrix: basic_matrix![row],
+                bag: None,
+                refill: None,
+                combo: None,
+                b2b: false,
+            };
+            let mut ai = AI::new(Config {
+                goal,
+                ..Config::default()
+            });
+            let mut analysis = ai.analyze(snapshot);
+            analysis.wait();
+            let best = analysis.all_moves().min_by(|&m, &n| analysis.cmp(m, n));
+            analysis.suggestion(best.unwrap(), 1).rating
+        };
+        // 39 of 40 lines were cleared before, so the I finishes the sprint
+        let goal = Goal::Sprint(40).remaining(39);
+        assert_eq!(best_rating(goal), goal.rating(1));
+        assert_ne!(best_rating(Goal::Sprint(40)), Goal::Sprint(40).rating(1));
+    }
+}
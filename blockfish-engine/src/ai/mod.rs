@@ -1,12 +1,21 @@
 use crate::{
     config::Config,
+    matrix::MAX_COLS,
     shape::{srs, ShapeTable},
     BasicMatrix, Color, Input,
 };
+use thiserror::Error;
 
 mod analysis;
+mod attack;
 mod b_star;
+mod batch;
+mod endgame;
 mod eval;
+mod goal;
+mod notation;
+mod pc;
+mod pool;
 mod state;
 
 // Input / output types
@@ -17,8 +26,34 @@ pub struct Snapshot {
     pub hold: Option<Color>,
     pub queue: Vec<Color>,
     pub matrix: BasicMatrix,
+    /// Pieces remaining in the current bag after the last piece in `queue`, if known.
+    pub bag: Option<Vec<Color>>,
+    /// Rules for adding garbage to the matrix after each placement, if any.
+    pub refill: Option<Refill>,
+    /// `Some(n)` if the last placement cleared lines, where `n` is the number of
+    /// consecutive line clears before it.
+    pub combo: Option<usize>,
+    /// `true` if the last line clear was a tetris or T-spin.
+    pub b2b: bool,
 }
 
+/// Garbage refill rules of a cheese race. After each placement, garbage rows with unknown
+/// holes are inserted at the bottom of the matrix until there are `max_height` rows of
+/// garbage, or `min_height` rows if the placement cleared lines.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Refill {
+    pub min_height: u16,
+    pub max_height: u16,
+    /// Number of garbage lines left to be added, or `None` if unlimited.
+    pub lines_remaining: Option<usize>,
+    /// Number of rows at the bottom of the matrix that are currently garbage.
+    pub garbage_rows: u16,
+}
+
+pub use goal::{Goal, ParseGoalError};
+pub use notation::ParseSnapshotError;
+pub use pc::{PcPlacement, PcSolution, PcSolutions};
+
 /// A suggested sequence and its rating.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Suggestion {
@@ -43,15 +78,24 @@ pub struct Stats {
 
 pub use eval::Eval;
 
-/// Performs the static analysis function on a snapshot.
-pub fn static_eval(snapshot: &Snapshot) -> Eval {
-    eval::eval(&snapshot.matrix)
+/// Performs the static analysis function on a snapshot. `piece_size` is the number of
+/// cells in the largest piece (see `AI::piece_size`), or see `AI::static_eval` to use the
+/// AI's ruleset.
+pub fn static_eval(snapshot: &Snapshot, piece_size: usize) -> Eval {
+    eval::eval(&snapshot.matrix, piece_size)
 }
 
 // AI interface
 
 // Re-export
-pub use analysis::{Analysis, AnalysisDone, MoveId};
+pub use analysis::{Analysis, AnalysisDone, MoveId, StopHandle};
+pub use batch::Batch;
+pub use pool::{NoWorkers, WorkerPool};
+
+/// Error returned by `AI::set_ruleset` for rulesets that the engine cannot play.
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+#[error("matrices wider than {} columns are not supported", MAX_COLS)]
+pub struct UnsupportedRuleset;
 
 /// An instance of the Blockfish AI. Holds engine configuration and can be used to spawn
 /// an analysis.
@@ -59,10 +103,13 @@ pub use analysis::{Analysis, AnalysisDone, MoveId};
 /// `AI` can currently be seen as just a sort of builder-pattern type for creating
 /// `Analysis`'s.  However, in the future it could be extended to handle more things such
 /// as a reusable thread pool and/or the place to call `static_eval`.
+#[derive(Clone)]
 pub struct AI {
     config: Config,
     shape_table: std::sync::Arc<ShapeTable>,
+    cols: u16,
     all_tx: Option<std::sync::mpsc::Sender<Suggestion>>,
+    pool: Option<WorkerPool>,
 }
 
 impl AI {
@@ -71,7 +118,9 @@ impl AI {
         Self {
             config,
             shape_table: std::sync::Arc::new(srs()),
+            cols: 10,
             all_tx: None,
+            pool: None,
         }
     }
 
@@ -83,13 +132,126 @@ impl AI {
         &mut self.config
     }
 
-    /// Begins a new analysis of `snapshot`, returning a handle to it.
+    /// Begins a new analysis of `snapshot`, returning a handle to it. If the matrix of
+    /// `snapshot` is not as wide as the current ruleset's (see `cols`), the analysis
+    /// finishes right away without finding any moves.
     pub fn analyze(&mut self, snapshot: Snapshot) -> Analysis {
-        analysis::spawn(
+        self.analyze_with_config(snapshot, self.config.clone())
+    }
+
+    /// Begins a new analysis of `snapshot` using `config` instead of the AI's
+    /// configuration, returning a handle to it.
+    pub fn analyze_with_config(&mut self, snapshot: Snapshot, config: Config) -> Analysis {
+        self.analyze_with_stop_handle(snapshot, config, StopHandle::default())
+    }
+
+    /// Like `analyze_with_config`, but the analysis is stopped by `stop`. The handle may be
+    /// created, and stopped, before the analysis begins.
+    pub fn analyze_with_stop_handle(
+        &mut self,
+        snapshot: Snapshot,
+        config: Config,
+        stop: StopHandle,
+    ) -> Analysis {
+        let piece_size = self.piece_size();
+        let all_tx = self.all_tx.take();
+        spawn_analysis(
             self.shape_table.clone(),
-            self.config.clone(),
-            snapshot.into(),
-            self.all_tx.take(),
+            self.pool.as_ref(),
+            self.cols,
+            snapshot,
+            config,
+            piece_size,
+            all_tx,
+            stop,
+        )
+    }
+
+    /// Prepares analyses of every snapshot in `snapshots` using `config`, running at most
+    /// `max_running` of them at once. Returns a handle that starts the analyses when
+    /// iterated, and yields each analysis once it finishes.
+    pub fn analyze_batch(
+        &self,
+        snapshots: Vec<Snapshot>,
+        config: Config,
+        max_running: usize,
+    ) -> Batch {
+        let shape_table = self.shape_table.clone();
+        let pool = self.pool.clone();
+        let (cols, piece_size) = (self.cols, self.piece_size());
+        Batch::new(snapshots, max_running, move |snapshot, stop| {
+            let (shtb, cfg) = (shape_table.clone(), config.clone());
+            spawn_analysis(
+                shtb,
+                pool.as_ref(),
+                cols,
+                snapshot,
+                cfg,
+                piece_size,
+                None,
+                stop,
+            )
+        })
+    }
+
+    /// Configures analyses to run on the workers of `pool`, rather than each on a new
+    /// thread. Analyses started while every worker is busy wait for one to become free.
+    pub fn set_worker_pool(&mut self, pool: WorkerPool) {
+        self.pool = Some(pool);
+    }
+
+    /// Configures the AI to use the shapes and kick tables of `rules` instead of SRS. Fails
+    /// if the matrix is too wide for the engine.
+    pub fn set_ruleset(
+        &mut self,
+        rules: &block_stacker::Ruleset,
+    ) -> Result<(), UnsupportedRuleset> {
+        if rules.cols > MAX_COLS as usize {
+            return Err(UnsupportedRuleset);
+        }
+        self.shape_table = std::sync::Arc::new(ShapeTable::from_ruleset(rules));
+        self.cols = rules.cols as u16;
+        Ok(())
+    }
+
+    /// Returns the number of columns in the matrix of the current ruleset. Snapshots
+    /// with a different number of columns cannot be analyzed meaningfully.
+    pub fn cols(&self) -> u16 {
+        self.cols
+    }
+
+    /// Returns the number of cells in the largest piece of the current ruleset, which is
+    /// what the evaluation's piece estimate is based on.
+    pub fn piece_size(&self) -> usize {
+        self.shape_table.piece_sizes().last().cloned().unwrap_or(4)
+    }
+
+    /// Performs the static analysis function on a snapshot, using the piece size of the
+    /// current ruleset.
+    pub fn static_eval(&self, snapshot: &Snapshot) -> Eval {
+        static_eval(snapshot, self.piece_size())
+    }
+
+    /// Finds every perfect clear of `snapshot` that uses at most `max_pieces` pieces,
+    /// blocking until the search finishes. The search is limited by the configured
+    /// search limit.
+    pub fn perfect_clears(&self, snapshot: Snapshot, max_pieces: usize) -> PcSolutions {
+        self.perfect_clears_with_stop_handle(snapshot, max_pieces, &StopHandle::default())
+    }
+
+    /// Like `perfect_clears`, but the search gives up early once `stop` is stopped.
+    pub fn perfect_clears_with_stop_handle(
+        &self,
+        snapshot: Snapshot,
+        max_pieces: usize,
+        stop: &StopHandle,
+    ) -> PcSolutions {
+        pc::find(
+            &self.shape_table,
+            snapshot,
+            max_pieces,
+            self.config.search_limit,
+            stop,
         )
     }
 
@@ -104,3 +266,134 @@ impl AI {
         rx
     }
 }
+
+/// Spawns an analysis of `snapshot`, see `AI::analyze_with_config`. `cols` is the width
+/// of the matrix in the ruleset of `shape_table`.
+#[allow(clippy::too_many_arguments)]
+fn spawn_analysis(
+    shape_table: std::sync::Arc<ShapeTable>,
+    pool: Option<&WorkerPool>,
+    cols: u16,
+    mut snapshot: Snapshot,
+    config: Config,
+    piece_size: usize,
+    all_tx: Option<std::sync::mpsc::Sender<Suggestion>>,
+    stop: StopHandle,
+) -> Analysis {
+    if snapshot.matrix.cols() != cols {
+        log::warn!("matrix is not {} columns wide", cols);
+        return analysis::finished();
+    }
+    let bag = snapshot.bag.take();
+    let mut root: state::State = snapshot.into();
+    root.set_goal(config.goal);
+    root.set_piece_size(piece_size);
+    analysis::spawn(shape_table, config, root, bag, all_tx, pool, stop)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::basic_matrix;
+
+    #[test]
+    fn test_piece_size_mixed() {
+        // tetrominoes plus the X pentomino
+        let mut json: serde_json::Value =
+            serde_json::from_str(include_str!("../../../support/guideline.json")).unwrap();
+        let pentomino: serde_json::Value =
+            serde_json::from_str(include_str!("../../../support/pentomino.json")).unwrap();
+        json["minos"]["X"] = pentomino["minos"]["X"].clone();
+        let rules = block_stacker::Ruleset::from_json(&json.to_string()).unwrap();
+        let mut ai = AI::new(Config::default());
+        assert_eq!(ai.piece_size(), 4);
+        ai.set_ruleset(&rules).unwrap();
+        assert_eq!(ai.piece_size(), 5);
+
+        let (xx, __) = (true, false);
+        let snapshot = Snapshot {
+            hold: None,
+            queue: vec![],
+            matrix: basic_matrix![
+                [__, xx, xx, xx, xx, xx, xx, xx, xx, xx],
+                [xx, __, __, __, __, __, __, __, __, __],
+            ],
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        // 9 cells above the hole take at least 2 pieces when some have 5 cells
+        assert_eq!(ai.static_eval(&snapshot).piece_estimate, 2);
+        assert_eq!(static_eval(&snapshot, 4).piece_estimate, 3);
+    }
+
+    #[test]
+    fn test_analyze_stopped_before_start() {
+        let mut ai = AI::new(Config::default());
+        let mut config = ai.config();
+        config.search_limit = 1_000_000;
+        let stop = StopHandle::default();
+        stop.stop();
+        let snapshot = Snapshot {
+            hold: None,
+            queue: "LTJIOSZ".chars().map(Color::n).collect(),
+            matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        let mut analysis = ai.analyze_with_stop_handle(snapshot, config, stop);
+        analysis.wait();
+        assert!(analysis.stats().unwrap().nodes < 1_000_000);
+    }
+
+    #[test]
+    fn test_analyze_endgame_stopped_before_start() {
+        let (xx, __) = (true, false);
+        let mut ai = AI::new(Config::default());
+        let mut config = ai.config();
+        config.search_limit = 1_000_000;
+        let stop = StopHandle::default();
+        stop.stop();
+        // few enough garbage rows for the endgame solver to run first
+        let snapshot = Snapshot {
+            hold: None,
+            queue: "TIOLJSZ".chars().map(Color::n).collect(),
+            matrix: basic_matrix![
+                [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+                [xx, xx, xx, __, xx, xx, xx, xx, xx, xx],
+                [xx, xx, __, xx, xx, xx, xx, xx, xx, xx],
+                [xx, xx, xx, xx, xx, xx, xx, xx, xx, __],
+            ],
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        let mut analysis = ai.analyze_with_stop_handle(snapshot, config, stop);
+        analysis.wait();
+        assert!(analysis.stats().unwrap().nodes < 1_000);
+    }
+
+    #[test]
+    fn test_analyze_narrow_matrix() {
+        let mut ai = AI::new(Config::default());
+        assert_eq!(ai.cols(), 10);
+        let rules = block_stacker::Ruleset::pentomino();
+        ai.set_ruleset(&rules).unwrap();
+        assert_eq!(ai.cols() as usize, rules.cols);
+
+        // matrices of the wrong width are not searched
+        let mut ai = AI::new(Config::default());
+        for notation in &["x. - TIO", "x11./x11. - TIO"] {
+            let mut analysis = ai.analyze(notation.parse().unwrap());
+            analysis.wait();
+            assert_eq!(analysis.all_moves().count(), 0);
+            assert_eq!(analysis.stats().unwrap().nodes, 0);
+        }
+        let mut batch = ai.analyze_batch(vec!["x. - TIO".parse().unwrap()], ai.config(), 1);
+        assert_eq!(batch.next().unwrap().1.all_moves().count(), 0);
+    }
+}
@@ -0,0 +1,308 @@
+//! Compact single-line text notation for `Snapshot`s.
+//!
+//! A position is written as space separated fields:
+//!
+//! ```text
+//! <matrix> <hold> <queue> [<bag>]
+//! ```
+//!
+//! - `<matrix>` lists the rows of the matrix from top to bottom, separated by `/`. Each
+//!   row is run-length encoded as a sequence of `x` (occupied) and `.` (empty) cells,
+//!   each optionally prefixed by a repeat count, e.g. `4x.5x` is a cheese row with a hole
+//!   in column 4. An empty matrix is written as a single empty row, e.g. `10.`.
+//! - `<hold>` is the held piece, or `-` if hold is empty.
+//! - `<queue>` is the active piece followed by the previews, or `-` if the queue is
+//!   empty.
+//! - `<bag>` (optional) lists the pieces remaining in the current bag after the last
+//!   preview, surrounded by parentheses, e.g. `(JLS)`.
+//!
+//! For example, `4x.5x/x9. - TIOLJ (SZ)` has two rows of garbage, nothing in hold, a T
+//! piece to place, four previews, and S and Z left in the bag.
+
+use super::Snapshot;
+use crate::{BasicMatrix, Color, MAX_COLS};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum ParseSnapshotError {
+    #[error("expected '<matrix> <hold> <queue>' optionally followed by '<bag>'")]
+    Fields,
+    #[error("invalid cell {0:?} in matrix row")]
+    Cell(char),
+    #[error("repeat count not followed by a cell")]
+    Count,
+    #[error("matrix rows have inconsistent widths")]
+    Width,
+    #[error("matrix must have between 1 and {} columns", MAX_COLS)]
+    Cols,
+    #[error("not a valid piece: {0:?}")]
+    Piece(char),
+    #[error("expected bag to be surrounded by parentheses")]
+    Bag,
+}
+
+impl std::str::FromStr for Snapshot {
+    type Err = ParseSnapshotError;
+    fn from_str(s: &str) -> Result<Self, ParseSnapshotError> {
+        let mut fields = s.split_whitespace();
+        let matrix = fields.next().ok_or(ParseSnapshotError::Fields)?;
+        let hold = fields.next().ok_or(ParseSnapshotError::Fields)?;
+        let queue = fields.next().ok_or(ParseSnapshotError::Fields)?;
+        let bag = fields.next();
+        if fields.next().is_some() {
+            return Err(ParseSnapshotError::Fields);
+        }
+
+        let matrix = parse_matrix(matrix)?;
+        let hold = match parse_pieces(hold)?.as_slice() {
+            [] => None,
+            &[c] => Some(c),
+            _ => return Err(ParseSnapshotError::Fields),
+        };
+        let queue = parse_pieces(queue)?;
+        let bag = match bag {
+            Some(bag) => {
+                if !bag.starts_with('(') || !bag.ends_with(')') || bag.len() < 2 {
+                    return Err(ParseSnapshotError::Bag);
+                }
+                let bag = &bag[1..bag.len() - 1];
+                Some(bag.chars().map(piece).collect::<Result<_, _>>()?)
+            }
+            None => None,
+        };
+
+        Ok(Snapshot {
+            hold,
+            queue,
+            matrix,
+            bag,
+            refill: None,
+            combo: None,
+            b2b: false,
+        })
+    }
+}
+
+impl std::fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write_matrix(f, &self.matrix)?;
+        f.write_str(" ")?;
+        write_pieces(f, self.hold.iter().cloned())?;
+        f.write_str(" ")?;
+        write_pieces(f, self.queue.iter().cloned())?;
+        if let Some(bag) = self.bag.as_ref() {
+            f.write_str(" (")?;
+            for c in bag.iter() {
+                write!(f, "{}", c.as_char())?;
+            }
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
+
+fn piece(ch: char) -> Result<Color, ParseSnapshotError> {
+    Color::try_from(ch).map_err(|_| ParseSnapshotError::Piece(ch))
+}
+
+/// Parses a list of pieces, where `-` represents no pieces.
+fn parse_pieces(s: &str) -> Result<Vec<Color>, ParseSnapshotError> {
+    if s == "-" {
+        Ok(vec![])
+    } else {
+        s.chars().map(piece).collect()
+    }
+}
+
+fn write_pieces(
+    f: &mut std::fmt::Formatter,
+    mut iter: impl Iterator<Item = Color>,
+) -> std::fmt::Result {
+    match iter.next() {
+        Some(c) => {
+            write!(f, "{}", c.as_char())?;
+            for c in iter {
+                write!(f, "{}", c.as_char())?;
+            }
+            Ok(())
+        }
+        None => f.write_str("-"),
+    }
+}
+
+/// Parses a single run-length encoded row into a list of cells.
+fn parse_row(s: &str) -> Result<Vec<bool>, ParseSnapshotError> {
+    let mut cells = vec![];
+    let mut count = None;
+    for ch in s.chars() {
+        let cell = match ch {
+            'x' => true,
+            '.' => false,
+            _ => match ch.to_digit(10) {
+                Some(d) => {
+                    let n = count.unwrap_or(0usize) * 10 + d as usize;
+                    if n > MAX_COLS as usize {
+                        return Err(ParseSnapshotError::Cols);
+                    }
+                    count = Some(n);
+                    continue;
+                }
+                None => return Err(ParseSnapshotError::Cell(ch)),
+            },
+        };
+        let n = count.take().unwrap_or(1);
+        cells.resize(cells.len() + n, cell);
+        if cells.len() > MAX_COLS as usize {
+            return Err(ParseSnapshotError::Cols);
+        }
+    }
+    if count.is_some() {
+        return Err(ParseSnapshotError::Count);
+    }
+    Ok(cells)
+}
+
+fn parse_matrix(s: &str) -> Result<BasicMatrix, ParseSnapshotError> {
+    let rows = s.split('/').map(parse_row).collect::<Result<Vec<_>, _>>()?;
+    let cols = rows[0].len();
+    if cols == 0 {
+        return Err(ParseSnapshotError::Cols);
+    }
+    if rows.iter().any(|row| row.len() != cols) {
+        return Err(ParseSnapshotError::Width);
+    }
+    let mut matrix = BasicMatrix::with_cols(cols as u16);
+    // rows are written from top to bottom
+    for (i, row) in rows.iter().rev().enumerate() {
+        for (j, &cell) in row.iter().enumerate() {
+            if cell {
+                matrix.set((i as u16, j as u16));
+            }
+        }
+    }
+    Ok(matrix)
+}
+
+fn write_matrix(f: &mut std::fmt::Formatter, matrix: &BasicMatrix) -> std::fmt::Result {
+    if matrix.rows() == 0 {
+        return write!(f, "{}.", matrix.cols());
+    }
+    for i in (0..matrix.rows()).rev() {
+        if i + 1 < matrix.rows() {
+            f.write_str("/")?;
+        }
+        let mut j = 0;
+        while j < matrix.cols() {
+            let cell = matrix.get((i, j));
+            let run = (j..matrix.cols())
+                .take_while(|&k| matrix.get((i, k)) == cell)
+                .count();
+            if run > 1 {
+                write!(f, "{}", run)?;
+            }
+            f.write_str(if cell { "x" } else { "." })?;
+            j += run as u16;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::basic_matrix;
+
+    fn queue(s: &str) -> Vec<Color> {
+        s.chars().map(Color::n).collect()
+    }
+
+    #[test]
+    fn test_parse_snapshot() {
+        let (xx, __) = (true, false);
+        assert_eq!(
+            "4x.5x/x9. - TIOLJ".parse::<Snapshot>(),
+            Ok(Snapshot {
+                hold: None,
+                queue: queue("TIOLJ"),
+                matrix: basic_matrix![
+                    [xx, __, __, __, __, __, __, __, __, __],
+                    [xx, xx, xx, xx, __, xx, xx, xx, xx, xx],
+                ],
+                bag: None,
+                refill: None,
+                combo: None,
+                b2b: false,
+            })
+        );
+        assert_eq!(
+            "10. S - (JLZ)".parse::<Snapshot>(),
+            Ok(Snapshot {
+                hold: Some(Color::n('S')),
+                queue: vec![],
+                matrix: BasicMatrix::with_cols(10),
+                bag: Some(queue("JLZ")),
+                refill: None,
+                combo: None,
+                b2b: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_snapshot_errors() {
+        let parse = |s: &str| s.parse::<Snapshot>().err();
+        assert_eq!(parse("10. -"), Some(ParseSnapshotError::Fields));
+        assert_eq!(parse("10. - T () ?"), Some(ParseSnapshotError::Fields));
+        assert_eq!(parse("10. TI O"), Some(ParseSnapshotError::Fields));
+        assert_eq!(parse("4x,5x - T"), Some(ParseSnapshotError::Cell(',')));
+        assert_eq!(parse("4x5 - T"), Some(ParseSnapshotError::Count));
+        assert_eq!(parse("4x/5x - T"), Some(ParseSnapshotError::Width));
+        assert_eq!(parse("17. - T"), Some(ParseSnapshotError::Cols));
+        assert_eq!(parse("/ - T"), Some(ParseSnapshotError::Cols));
+        assert_eq!(parse("10. - T?"), Some(ParseSnapshotError::Piece('?')));
+        assert_eq!(parse("10. - T JLZ"), Some(ParseSnapshotError::Bag));
+    }
+
+    #[test]
+    fn test_display_snapshot() {
+        let (xx, __) = (true, false);
+        let ss = Snapshot {
+            hold: Some(Color::n('I')),
+            queue: queue("ZSO"),
+            matrix: basic_matrix![
+                [xx, xx, xx, __, xx, xx],
+                [__, __, __, __, __, xx],
+                [__, __, xx, __, __, xx],
+            ],
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        assert_eq!(format!("{}", ss), "2.x2.x/5.x/3x.2x I ZSO");
+        let ss = Snapshot {
+            hold: None,
+            queue: vec![],
+            matrix: BasicMatrix::with_cols(10),
+            bag: Some(vec![]),
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        assert_eq!(format!("{}", ss), "10. - - ()");
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        for s in &[
+            "10. - TIOLJ",
+            "4x.5x/x9. - TIOLJ (SZ)",
+            "x.x.x.x.x./.x.x.x.x.x/9x. L IJOSZT",
+            "2.x2.x/5.x/3x.2x I ZSO ()",
+        ] {
+            let ss: Snapshot = s.parse().unwrap();
+            assert_eq!(&format!("{}", ss), s);
+        }
+    }
+}
@@ -0,0 +1,471 @@
+use super::{
+    analysis::reconstruct_inputs, endgame::NodeLimitReached, state::State, Snapshot, StopHandle,
+};
+use crate::{
+    common::{Color, Input},
+    matrix::BasicMatrix,
+    place::PlaceFinder,
+    shape::{NormalizedShapeTransform, ShapeTable},
+};
+use std::collections::HashSet;
+
+/// A sequence of placements that results in a perfect clear.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PcSolution {
+    pub placements: Vec<PcPlacement>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PcPlacement {
+    /// The piece being placed.
+    pub color: Color,
+    /// List of inputs to perform the placement, ending in a hard drop.
+    pub inputs: Vec<Input>,
+}
+
+/// Results of a search for perfect clears.
+#[derive(Clone, Debug, Eq, PartialEq, Default)]
+pub struct PcSolutions {
+    /// Every solution found, ordered by number of pieces.
+    pub solutions: Vec<PcSolution>,
+    /// `false` if the search was stopped by the node limit or a stop handle, in which
+    /// case there may be more solutions than were found.
+    pub complete: bool,
+    /// Number of nodes generated.
+    pub nodes: usize,
+}
+
+/// Finds every perfect clear of `snapshot` using at most `max_pieces` pieces from the
+/// queue (including the hold piece), giving up after generating `node_limit` nodes or once
+/// `stop` is stopped.
+pub fn find(
+    shtb: &ShapeTable,
+    snapshot: Snapshot,
+    max_pieces: usize,
+    node_limit: usize,
+    stop: &StopHandle,
+) -> PcSolutions {
+    let root: State = snapshot.into();
+    let max_pieces = std::cmp::min(max_pieces, root.queue_len());
+    let sizes = Sizes::new(shtb);
+    let mut finder = Finder {
+        pfind: PlaceFinder::new(shtb),
+        sizes,
+        node_count: 0,
+        node_limit,
+        stop: stop.clone(),
+        dead: HashSet::new(),
+        found: HashSet::new(),
+        traces: vec![],
+    };
+    let mut complete = true;
+    for height in pc_heights(root.matrix(), max_pieces, sizes) {
+        let mut path = Vec::with_capacity(max_pieces);
+        if finder.search(&root, height, max_pieces, &mut path).is_err() {
+            complete = false;
+            break;
+        }
+    }
+
+    let mut solutions = finder
+        .traces
+        .iter()
+        .map(|trace| {
+            let idxs = trace.iter().map(|&(idx, _)| idx).collect::<Vec<_>>();
+            let inputs = reconstruct_inputs(shtb, root.clone(), &idxs);
+            let mut inputs = inputs.into_iter();
+            let placements = trace
+                .iter()
+                .map(|&(_, color)| PcPlacement {
+                    color,
+                    inputs: inputs
+                        .by_ref()
+                        .take_while(|&inp| inp != Input::HD)
+                        .chain(std::iter::once(Input::HD))
+                        .collect(),
+                })
+                .collect();
+            PcSolution { placements }
+        })
+        .collect::<Vec<_>>();
+    solutions.sort_by_key(|sol| sol.placements.len());
+    PcSolutions {
+        solutions,
+        complete,
+        nodes: finder.node_count,
+    }
+}
+
+/// Number of cells in the pieces of a shape table, used to rule out areas that cannot be
+/// filled exactly.
+#[derive(Copy, Clone, Debug)]
+struct Sizes {
+    /// Number of cells in the largest piece.
+    max: usize,
+    /// Greatest common divisor of the number of cells in every piece, which any area
+    /// filled with pieces must be a multiple of.
+    gcd: usize,
+}
+
+impl Sizes {
+    fn new(shtb: &ShapeTable) -> Self {
+        let sizes = shtb.piece_sizes();
+        Self {
+            max: sizes.last().cloned().unwrap_or(4),
+            gcd: sizes.into_iter().fold(0, gcd),
+        }
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Returns the heights that a perfect clear of `matrix` may fill up to, using at most
+/// `max_pieces` pieces.
+fn pc_heights(matrix: &BasicMatrix, max_pieces: usize, sizes: Sizes) -> impl Iterator<Item = u16> {
+    let cols = matrix.cols() as usize;
+    let filled = (0..matrix.rows())
+        .map(|i| cols - empty_cells(matrix, i))
+        .sum::<usize>();
+    let min_height = std::cmp::max(matrix.rows(), 1);
+    (min_height..)
+        .take_while(move |&h| (h as usize) * cols <= filled + max_pieces * sizes.max)
+        .filter(move |&h| ((h as usize) * cols - filled) % sizes.gcd == 0)
+}
+
+/// Returns the number of empty cells in row `i` of `matrix`, which may be above the top
+/// of the matrix.
+fn empty_cells(matrix: &BasicMatrix, i: u16) -> usize {
+    if i < matrix.rows() {
+        matrix.gaps(i).map(|r| r.len()).sum()
+    } else {
+        matrix.cols() as usize
+    }
+}
+
+struct Finder<'s> {
+    pfind: PlaceFinder<'s>,
+    sizes: Sizes,
+    node_count: usize,
+    node_limit: usize,
+    stop: StopHandle,
+    // states that are known to have no solutions
+    dead: HashSet<Key>,
+    // placements of each solution found, to avoid duplicates
+    found: HashSet<Vec<NormalizedShapeTransform>>,
+    traces: Vec<Vec<(usize, Color)>>,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash)]
+struct Key {
+    matrix: BasicMatrix,
+    height_left: u16,
+    next: (Option<Color>, Option<Color>),
+    queue_len: usize,
+    pieces_left: usize,
+}
+
+impl<'s> Finder<'s> {
+    /// Searches for perfect clears from `state`, where the matrix must not be filled
+    /// above `height` rows (counting the rows cleared so far), and at most `pieces_left`
+    /// pieces may be placed. Returns `true` if any solutions were found.
+    fn search(
+        &mut self,
+        state: &State,
+        height: u16,
+        pieces_left: usize,
+        path: &mut Vec<(usize, Color, NormalizedShapeTransform)>,
+    ) -> Result<bool, NodeLimitReached> {
+        let key = Key {
+            matrix: state.matrix().clone(),
+            height_left: height - state.lines_cleared() as u16,
+            next: state.next(),
+            queue_len: state.queue_len(),
+            pieces_left,
+        };
+        if pieces_left == 0 || self.dead.contains(&key) {
+            return Ok(false);
+        }
+
+        let mut any_found = false;
+        let placements = state.placements(&mut self.pfind).collect::<Vec<_>>();
+        for pl in placements {
+            self.count_node()?;
+            let mut succ = state.clone();
+            succ.place(&pl);
+            if succ.matrix().rows() as usize + succ.lines_cleared() > height as usize {
+                continue;
+            }
+            path.push((pl.idx, pl.shape.color(), pl.normal()));
+            if succ.matrix().rows() == 0 {
+                any_found = true;
+                self.record(path);
+            } else if is_feasible(&succ, height, pieces_left - 1, self.sizes) {
+                any_found |= self.search(&succ, height, pieces_left - 1, path)?;
+            }
+            path.pop();
+        }
+
+        if !any_found {
+            self.dead.insert(key);
+        }
+        Ok(any_found)
+    }
+
+    fn record(&mut self, path: &[(usize, Color, NormalizedShapeTransform)]) {
+        let normals = path.iter().map(|&(_, _, n)| n).collect();
+        if self.found.insert(normals) {
+            let trace = path.iter().map(|&(idx, color, _)| (idx, color)).collect();
+            self.traces.push(trace);
+        }
+    }
+
+    fn count_node(&mut self) -> Result<(), NodeLimitReached> {
+        self.node_count += 1;
+        if self.node_count > self.node_limit || self.stop.is_stopped() {
+            Err(NodeLimitReached)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns `true` if the empty space below `height` in `state` could possibly be filled
+/// with at most `pieces_left` pieces: every enclosed region must have a multiple of
+/// `sizes.gcd` cells, and there must be enough pieces to fill them.
+fn is_feasible(state: &State, height: u16, pieces_left: usize, sizes: Sizes) -> bool {
+    let matrix = state.matrix();
+    let rows = height - state.lines_cleared() as u16;
+    let cols = matrix.cols();
+    let empty = (0..rows).map(|i| empty_cells(matrix, i)).sum::<usize>();
+    let pieces_needed = (empty + sizes.max - 1) / sizes.max;
+    if pieces_needed > pieces_left || pieces_needed > state.queue_len() {
+        return false;
+    }
+
+    // flood fill each region of empty cells
+    let mut seen = vec![false; (rows as usize) * (cols as usize)];
+    let mut stack = vec![];
+    for i0 in 0..rows {
+        for j0 in 0..cols {
+            let idx = (i0 as usize) * (cols as usize) + (j0 as usize);
+            if seen[idx] || matrix.get((i0, j0)) {
+                continue;
+            }
+            seen[idx] = true;
+            stack.push((i0, j0));
+            let mut area = 0;
+            while let Some((i, j)) = stack.pop() {
+                area += 1;
+                let neighbors = [
+                    (i.wrapping_sub(1), j),
+                    (i + 1, j),
+                    (i, j.wrapping_sub(1)),
+                    (i, j + 1),
+                ];
+                for &(i, j) in neighbors.iter() {
+                    if i >= rows || j >= cols || matrix.get((i, j)) {
+                        continue;
+                    }
+                    let idx = (i as usize) * (cols as usize) + (j as usize);
+                    if !seen[idx] {
+                        seen[idx] = true;
+                        stack.push((i, j));
+                    }
+                }
+            }
+            if area % sizes.gcd != 0 {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        basic_matrix,
+        shape::{pentomino, srs},
+    };
+
+    const TETROMINOES: Sizes = Sizes { max: 4, gcd: 4 };
+
+    fn snapshot(hold: Option<char>, queue: &str, matrix: BasicMatrix) -> Snapshot {
+        Snapshot {
+            hold: hold.map(Color::n),
+            queue: queue.chars().map(Color::n).collect(),
+            matrix,
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        }
+    }
+
+    #[test]
+    fn test_pc_heights() {
+        let (xx, __) = (true, false);
+        let heights =
+            |matrix, max_pieces, sizes| pc_heights(matrix, max_pieces, sizes).collect::<Vec<_>>();
+        let empty = BasicMatrix::with_cols(10);
+        assert_eq!(heights(&empty, 10, TETROMINOES), [2, 4]);
+        assert_eq!(heights(&empty, 4, TETROMINOES), [] as [u16; 0]);
+        let matrix = basic_matrix![[xx, xx, xx, xx, xx, xx, __, __, __, __]];
+        assert_eq!(heights(&matrix, 6, TETROMINOES), [1, 3]);
+        // pentominoes fill a row with two pieces
+        let pentominoes = Sizes { max: 5, gcd: 5 };
+        assert_eq!(heights(&empty, 4, pentominoes), [1, 2]);
+        // mixed tetrominoes and pentominoes
+        let mixed = Sizes { max: 5, gcd: 1 };
+        assert_eq!(heights(&matrix, 1, mixed), [1]);
+    }
+
+    #[test]
+    fn test_is_feasible() {
+        let (xx, __) = (true, false);
+        let state = |matrix| -> State { snapshot(None, "IIII", matrix).into() };
+        let s = state(basic_matrix![[xx, xx, xx, xx, xx, xx, __, __, __, __]]);
+        assert!(is_feasible(&s, 1, 1, TETROMINOES));
+        assert!(!is_feasible(&s, 1, 0, TETROMINOES));
+        // regions of 3 and 1 cells
+        let s = state(basic_matrix![[xx, xx, xx, xx, xx, __, __, __, xx, __]]);
+        assert!(!is_feasible(&s, 1, 1, TETROMINOES));
+    }
+
+    #[test]
+    fn test_find_pc() {
+        let (xx, __) = (true, false);
+        let srs = srs();
+        // I fills the last 4 cells, O cannot be used
+        let matrix = basic_matrix![[xx, xx, xx, xx, xx, xx, __, __, __, __]];
+        let pcs = find(
+            &srs,
+            snapshot(None, "OI", matrix.clone()),
+            2,
+            10_000,
+            &StopHandle::default(),
+        );
+        assert!(pcs.complete);
+        assert_eq!(pcs.solutions.len(), 1);
+        let sol = &pcs.solutions[0];
+        assert_eq!(sol.placements.len(), 1);
+        assert_eq!(sol.placements[0].color, Color::n('I'));
+        assert_eq!(sol.placements[0].inputs.first(), Some(&Input::Hold));
+        assert_eq!(sol.placements[0].inputs.last(), Some(&Input::HD));
+
+        // not enough pieces
+        let pcs = find(
+            &srs,
+            snapshot(None, "OO", matrix),
+            2,
+            10_000,
+            &StopHandle::default(),
+        );
+        assert!(pcs.complete);
+        assert!(pcs.solutions.is_empty());
+    }
+
+    #[test]
+    fn test_find_pc_two_rows() {
+        let (xx, __) = (true, false);
+        let srs = srs();
+        let matrix = basic_matrix![
+            [xx, xx, xx, xx, xx, xx, __, __, __, __],
+            [xx, xx, xx, xx, xx, xx, __, __, __, __],
+        ];
+        let pcs = find(
+            &srs,
+            snapshot(Some('O'), "OII", matrix),
+            4,
+            100_000,
+            &StopHandle::default(),
+        );
+        assert!(pcs.complete);
+        // both O's, placed left first or right first
+        assert_eq!(pcs.solutions.len(), 2);
+        for sol in pcs.solutions.iter() {
+            let colors = sol.placements.iter().map(|p| p.color.as_char());
+            assert_eq!(colors.collect::<String>(), "OO");
+        }
+    }
+
+    #[test]
+    fn test_find_pc_empty_matrix() {
+        let srs = srs();
+        let pcs = find(
+            &srs,
+            snapshot(None, "IIOOO", BasicMatrix::with_cols(10)),
+            5,
+            1_000_000,
+            &StopHandle::default(),
+        );
+        assert!(pcs.complete);
+        assert!(!pcs.solutions.is_empty());
+        for sol in pcs.solutions.iter() {
+            assert_eq!(sol.placements.len(), 5);
+        }
+    }
+
+    #[test]
+    fn test_find_pc_pentominoes() {
+        let (xx, __) = (true, false);
+        let pentomino = pentomino();
+        let pcs = find(
+            &pentomino,
+            snapshot(None, "II", BasicMatrix::with_cols(10)),
+            2,
+            100_000,
+            &StopHandle::default(),
+        );
+        assert!(pcs.complete);
+        // both I's flat, placed left first or right first
+        assert_eq!(pcs.solutions.len(), 2);
+        assert!(pcs.solutions.iter().all(|sol| sol.placements.len() == 2));
+        // a region of 4 cells cannot be filled with pentominoes
+        let s: State = snapshot(
+            None,
+            "PP",
+            basic_matrix![[xx, xx, xx, xx, xx, xx, __, __, __, __]],
+        )
+        .into();
+        assert!(is_feasible(&s, 1, 1, TETROMINOES));
+        assert!(!is_feasible(&s, 1, 1, Sizes::new(&pentomino)));
+    }
+
+    #[test]
+    fn test_find_pc_node_limit() {
+        let srs = srs();
+        let pcs = find(
+            &srs,
+            snapshot(None, "IOTLJSZIOTL", BasicMatrix::with_cols(10)),
+            10,
+            100,
+            &StopHandle::default(),
+        );
+        assert!(!pcs.complete);
+        assert_eq!(pcs.nodes, 101);
+    }
+
+    #[test]
+    fn test_find_pc_stopped() {
+        let srs = srs();
+        let stop = StopHandle::default();
+        stop.stop();
+        let pcs = find(
+            &srs,
+            snapshot(None, "IOTLJSZIOTL", BasicMatrix::with_cols(10)),
+            10,
+            std::usize::MAX,
+            &stop,
+        );
+        assert!(!pcs.complete);
+        assert_eq!(pcs.nodes, 1);
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex},
+};
+use thiserror::Error;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Error returned by `WorkerPool::execute` when there are no workers left to run the job.
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+#[error("worker pool has no workers")]
+pub struct NoWorkers;
+
+/// A fixed set of worker threads that analyses can be run on, see `AI::set_worker_pool`.
+/// Cloning a pool shares its workers, so that many `AI`s can be limited to the same
+/// number of analyses running at once. The workers exit once every clone is dropped and
+/// the remaining jobs are finished. A job that panics does not take its worker down with
+/// it.
+#[derive(Clone)]
+pub struct WorkerPool {
+    tx: Arc<Mutex<mpsc::Sender<Job>>>,
+    workers: usize,
+}
+
+impl WorkerPool {
+    /// Spawns a pool of `workers` threads. Panics if `workers` is zero.
+    pub fn new(workers: usize) -> Self {
+        assert!(workers > 0, "worker pool must have at least one worker");
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..workers {
+            let rx = rx.clone();
+            std::thread::spawn(move || loop {
+                // release the lock before running the job, so other workers can pick up
+                // jobs in the meantime
+                let job = rx.lock().unwrap().recv();
+                match job {
+                    Ok(job) => {
+                        if catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            log::error!("worker pool job panicked");
+                        }
+                    }
+                    Err(_) => break,
+                }
+            });
+        }
+        Self {
+            tx: Arc::new(Mutex::new(tx)),
+            workers,
+        }
+    }
+
+    /// Returns the number of worker threads, which is also the number of jobs that may
+    /// run at once.
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Runs `job` on the first worker to become free. Fails if the workers have exited,
+    /// in which case `job` is dropped.
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) -> Result<(), NoWorkers> {
+        let tx = self.tx.lock().unwrap_or_else(|e| e.into_inner());
+        tx.send(Box::new(job)).map_err(|_| NoWorkers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_worker_pool_limits_jobs() {
+        let pool = WorkerPool::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let (done_tx, done_rx) = mpsc::channel();
+        for _ in 0..6 {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            let done_tx = done_tx.clone();
+            let job = move || {
+                let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(n, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                done_tx.send(()).unwrap();
+            };
+            pool.execute(job).unwrap();
+        }
+        for _ in 0..6 {
+            done_rx.recv().unwrap();
+        }
+        assert!(max_running.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn test_worker_pool_survives_panics() {
+        let pool = WorkerPool::new(1);
+        let (done_tx, done_rx) = mpsc::channel();
+        for i in 0..3 {
+            let done_tx = done_tx.clone();
+            let job = move || {
+                if i < 2 {
+                    panic!("job #{} failed", i);
+                }
+                done_tx.send(i).unwrap();
+            };
+            pool.execute(job).unwrap();
+        }
+        assert_eq!(done_rx.recv(), Ok(2));
+    }
+}
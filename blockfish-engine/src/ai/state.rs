@@ -1,4 +1,9 @@
-use super::Snapshot;
+use super::{
+    attack::{self, Chain},
+    eval::{eval, Eval},
+    goal::Progress,
+    Goal, Refill, Snapshot,
+};
 use crate::{
     common::Color,
     matrix::BasicMatrix,
@@ -14,6 +19,15 @@ pub struct State {
     queue_rev: Vec<Color>,
     has_held: bool,
     reached_goal: bool,
+    goal: Goal,
+    // number of cells in the largest piece
+    piece_size: usize,
+    lines_cleared: usize,
+    refill: Option<Refill>,
+    // garbage rows inserted below the matrix, whose holes are not known
+    hidden_rows: u16,
+    chain: Chain,
+    attack: usize,
 }
 
 impl State {
@@ -21,6 +35,36 @@ impl State {
         &self.matrix
     }
 
+    /// Evaluates this state. Garbage rows added below the matrix count towards the height
+    /// of the stack, but their holes are unknown so they are not taken into account
+    /// otherwise.
+    pub fn eval(&self) -> Eval {
+        let mut ev = eval(&self.matrix, self.piece_size);
+        ev.rows += self.hidden_rows;
+        ev.attack = self.attack as u16;
+        ev
+    }
+
+    pub fn goal(&self) -> Goal {
+        self.goal
+    }
+
+    /// Sets the goal used to decide if placements reached the goal.
+    pub fn set_goal(&mut self, goal: Goal) {
+        self.goal = goal;
+    }
+
+    /// Sets the number of cells in the largest piece, used by the evaluation. Defaults to
+    /// 4 (tetrominoes).
+    pub fn set_piece_size(&mut self, piece_size: usize) {
+        self.piece_size = piece_size;
+    }
+
+    /// Returns the total number of lines cleared by placements so far.
+    pub fn lines_cleared(&self) -> usize {
+        self.lines_cleared
+    }
+
     pub fn reached_goal(&self) -> bool {
         self.reached_goal
     }
@@ -29,6 +73,41 @@ impl State {
         self.reached_goal || self.queue_rev.is_empty()
     }
 
+    /// Returns the number of pieces available to place, including the hold piece.
+    pub fn queue_len(&self) -> usize {
+        self.queue_rev.len()
+    }
+
+    /// Returns the pieces available to place, in reverse order with the hold piece last,
+    /// and whether the hold piece is the last one. States with the same matrix and queue
+    /// have the same placements.
+    pub fn queue(&self) -> (&[Color], bool) {
+        (&self.queue_rev, self.has_held)
+    }
+
+    /// Returns the number of garbage rows left to clear. If the refill rules are known,
+    /// this includes the garbage that will be added later; otherwise it is the number of
+    /// rows at the bottom of the matrix with a single hole, as generated in cheese races.
+    pub fn garbage_rows(&self) -> usize {
+        match self.refill {
+            Some(refill) => {
+                let rows = (refill.garbage_rows + self.hidden_rows) as usize;
+                refill.lines_remaining.map_or(usize::MAX, |rem| rows + rem)
+            }
+            None => (0..self.matrix.rows())
+                .take_while(|&i| {
+                    let mut gaps = self.matrix.gaps(i);
+                    matches!((gaps.next(), gaps.next()), (Some(gap), None) if gap.len() == 1)
+                })
+                .count(),
+        }
+    }
+
+    /// Adds `color` to the end of the next queue.
+    pub fn push_back(&mut self, color: Color) {
+        self.queue_rev.insert(0, color);
+    }
+
     /// Returns the next available piece colors, as pair `(next_piece, hold_piece)`.
     /// Either may be `None` if not available (e.g. the next queue is empty).
     ///
@@ -71,11 +150,53 @@ impl State {
 
     /// Applies the given placement to this state, modifying the queue and matrix.
     pub fn place(&mut self, pl: &Place) {
+        let spin = attack::t_spin(&self.matrix, pl);
         pl.shape.blit_to(&mut self.matrix, pl.tf);
-        self.reached_goal |= self.matrix.sift_rows();
+        let lines = self.full_rows().count();
+        if self.refill.is_some() {
+            self.refill(lines);
+        }
+        let bottom_cleared = self.matrix.sift_rows();
+        let perfect_clear = lines > 0 && self.matrix.rows() == 0 && self.hidden_rows == 0;
+        self.attack += self.chain.lock(lines, 0, spin, perfect_clear).attack;
+        self.lines_cleared += lines;
+        self.reached_goal |= self.goal.is_reached(&Progress {
+            matrix: &self.matrix,
+            bottom_cleared,
+            lines_cleared: self.lines_cleared,
+        });
         self.pop(pl.did_hold);
     }
 
+    /// Returns the indices of the rows in the matrix that are entirely occupied.
+    fn full_rows<'a>(&'a self) -> impl Iterator<Item = u16> + 'a {
+        let matrix = &self.matrix;
+        (0..matrix.rows()).filter(move |&i| matrix.gaps(i).next().is_none())
+    }
+
+    /// Adds garbage rows according to the refill rules, given that the last placement
+    /// cleared `lines` lines. Must be called after a piece has been blitted to the matrix,
+    /// but before any lines are cleared.
+    fn refill(&mut self, lines: usize) {
+        let garbage_rows = self.refill.unwrap().garbage_rows;
+        let cleared_garbage = self.full_rows().filter(|&i| i < garbage_rows).count();
+        let refill = self.refill.as_mut().unwrap();
+        refill.garbage_rows -= cleared_garbage as u16;
+
+        let garbage = refill.garbage_rows + self.hidden_rows;
+        let height = if lines > 0 {
+            std::cmp::min(refill.min_height, refill.max_height)
+        } else {
+            refill.max_height
+        };
+        let mut num = height.saturating_sub(garbage);
+        if let Some(rem) = refill.lines_remaining.as_mut() {
+            num = std::cmp::min(num as usize, *rem) as u16;
+            *rem -= num as usize;
+        }
+        self.hidden_rows += num;
+    }
+
     /// Removes a piece from the next queue, or hold slot if `hold` is `true`.
     fn pop(&mut self, hold: bool) {
         //  | has_held | hold  | pos
@@ -107,6 +228,16 @@ impl From<Snapshot> for State {
             queue_rev,
             has_held,
             reached_goal: false,
+            goal: Goal::default(),
+            piece_size: 4,
+            lines_cleared: 0,
+            refill: snapshot.refill,
+            hidden_rows: 0,
+            chain: Chain {
+                combo: snapshot.combo,
+                b2b: snapshot.b2b,
+            },
+            attack: 0,
         }
     }
 }
@@ -114,7 +245,7 @@ impl From<Snapshot> for State {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{shape::srs, Orientation::*};
+    use crate::{basic_matrix, shape::srs, Orientation::*, Parameters};
 
     #[test]
     fn test_state_operations() {
@@ -124,6 +255,10 @@ mod test {
             hold: None,
             queue: queue().collect(),
             matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         }
         .into();
         assert!(!s.is_terminal());
@@ -149,6 +284,10 @@ mod test {
             hold: Some(Color::n('S')),
             queue: "LTJI".chars().map(Color::n).collect(),
             matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         }
         .into();
         assert_eq!(s.next(), (Some(Color::n('L')), Some(Color::n('S'))));
@@ -161,6 +300,10 @@ mod test {
             hold: None,
             queue: "LTJI".chars().map(Color::n).collect(),
             matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         }
         .into();
         assert_eq!(s.next(), (Some(Color::n('L')), Some(Color::n('T'))));
@@ -174,6 +317,10 @@ mod test {
             hold: None,
             queue: vec![Color::n('I')],
             matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         }
         .into();
         assert_eq!(s.next(), (Some(Color::n('I')), None));
@@ -181,8 +328,173 @@ mod test {
             hold: Some(Color::n('O')),
             queue: vec![],
             matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        }
+        .into();
+        assert_eq!(s.next(), (None, Some(Color::n('O'))));
+    }
+
+    #[test]
+    fn test_state_push_back() {
+        let mut s: State = Snapshot {
+            hold: Some(Color::n('O')),
+            queue: vec![Color::n('I')],
+            matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         }
         .into();
+        assert_eq!(s.queue_len(), 2);
+        s.pop(false);
         assert_eq!(s.next(), (None, Some(Color::n('O'))));
+        s.push_back(Color::n('T'));
+        assert_eq!(s.queue_len(), 2);
+        assert_eq!(s.next(), (Some(Color::n('T')), Some(Color::n('O'))));
+    }
+
+    #[test]
+    fn test_state_refill() {
+        let (xx, __) = (true, false);
+        let refill = Refill {
+            min_height: 0,
+            max_height: 3,
+            lines_remaining: Some(1),
+            garbage_rows: 1,
+        };
+        let mut s: State = Snapshot {
+            hold: None,
+            queue: "OI".chars().map(Color::n).collect(),
+            matrix: basic_matrix![[__, xx, xx, xx, xx, xx, xx, xx, xx, xx]],
+            bag: None,
+            refill: Some(refill),
+            combo: None,
+            b2b: false,
+        }
+        .into();
+        assert_eq!(s.garbage_rows(), 2);
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs);
+
+        // no lines cleared (leaving the hole open), so garbage is added up to the max height, limited by the
+        // number of lines remaining
+        let pl = s
+            .placements(&mut pfind)
+            .find(|pl| {
+                let mut s = s.clone();
+                s.place(pl);
+                !pl.did_hold && !s.matrix.get((1, 0))
+            })
+            .unwrap();
+        s.place(&pl);
+        assert_eq!(s.hidden_rows, 1);
+        assert_eq!(s.refill.unwrap().lines_remaining, Some(0));
+        assert_eq!(s.eval().rows, s.matrix.rows() + 1);
+        assert_eq!(s.garbage_rows(), 2);
+
+        // clearing the garbage row
+        let pl = s
+            .placements(&mut pfind)
+            .find(|pl| {
+                let mut s = s.clone();
+                s.place(pl);
+                s.reached_goal()
+            })
+            .unwrap();
+        s.place(&pl);
+        assert_eq!(s.hidden_rows, 1);
+        assert_eq!(s.refill.unwrap().garbage_rows, 0);
+        assert_eq!(s.garbage_rows(), 1);
+    }
+
+    #[test]
+    fn test_state_goal() {
+        let (xx, __) = (true, false);
+        let state = |goal| {
+            let mut s: State = Snapshot {
+                hold: None,
+                queue: vec![Color::n('I')],
+                matrix: basic_matrix![
+                    [xx, xx, xx, xx, xx, xx, __, __, __, __],
+                    [xx, xx, xx, xx, xx, xx, __, __, __, __],
+                ],
+                bag: None,
+                refill: None,
+                combo: None,
+                b2b: false,
+            }
+            .into();
+            s.set_goal(goal);
+            s
+        };
+        let srs = srs();
+        let reaches = |goal| {
+            let s = state(goal);
+            let mut pfind = PlaceFinder::new(&srs);
+            let placements = s.placements(&mut pfind).collect::<Vec<_>>();
+            placements.into_iter().any(|pl| {
+                let mut s = s.clone();
+                s.place(&pl);
+                s.reached_goal()
+            })
+        };
+        assert!(reaches(Goal::Cheese));
+        assert!(reaches(Goal::Sprint(1)));
+        assert!(!reaches(Goal::Sprint(2)));
+        assert!(!reaches(Goal::PerfectClear));
+        assert!(!reaches(Goal::Survival));
+    }
+
+    #[test]
+    fn test_state_attack() {
+        let (xx, __) = (true, false);
+        let row = [xx, xx, xx, xx, xx, xx, xx, xx, xx, __];
+        let state = |b2b| -> State {
+            Snapshot {
+                hold: None,
+                queue: "II".chars().map(Color::n).collect(),
+                matrix: basic_matrix![row, row, row, row, row],
+                bag: None,
+                refill: None,
+                combo: None,
+                b2b,
+            }
+            .into()
+        };
+        let srs = srs();
+        let mut pfind = PlaceFinder::new(&srs);
+        let tetris = |mut s: State, pfind: &mut PlaceFinder| {
+            let pl = s
+                .placements(pfind)
+                .find(|pl| {
+                    let mut s = s.clone();
+                    s.place(pl);
+                    s.lines_cleared() == 4
+                })
+                .unwrap();
+            s.place(&pl);
+            s
+        };
+        let s = tetris(state(false), &mut pfind);
+        assert_eq!(s.eval().attack, 4);
+        assert_eq!(s.chain.combo, Some(0));
+        let s = tetris(state(true), &mut pfind);
+        assert_eq!(s.eval().attack, 5);
+
+        // attack is rewarded only in versus mode
+        let params = Parameters::default();
+        assert_eq!(s.eval().score(&params), eval(s.matrix(), 4).score(&params));
+        let params = Parameters {
+            attack_factor: 3,
+            ..Parameters::default()
+        };
+        assert_eq!(
+            s.eval().score(&params),
+            eval(s.matrix(), 4).score(&params) - 15
+        );
     }
 }
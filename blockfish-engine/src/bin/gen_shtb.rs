@@ -1,6 +1,8 @@
 fn main() {
-    let guideline = block_stacker::Ruleset::guideline();
-    let srs = blockfish::ShapeTable::from_ruleset(&guideline);
+    let name = std::env::args().nth(1);
+    let name = name.as_deref().unwrap_or("guideline");
+    let rules = block_stacker::Ruleset::builtin(name).expect("unknown ruleset");
+    let shtb = blockfish::ShapeTable::from_ruleset(&rules);
     let stdout = std::io::stdout();
-    serde_json::to_writer(stdout.lock(), &srs).unwrap();
+    serde_json::to_writer(stdout.lock(), &shtb).unwrap();
 }
@@ -1,17 +1,23 @@
 use argh::FromArgs;
-use block_stacker::{Config as BSConfig, Ruleset, Stacker};
-use blockfish::{ai::AI, Config as BFConfig, StackerExt as _};
-use serde::Serialize;
+use block_stacker::{Config as BSConfig, Ruleset, RulesetSource, Stacker};
+use blockfish::{
+    ai::{Goal, AI},
+    Config as BFConfig, Input, StackerExt as _,
+};
+use serde::{Deserialize, Serialize};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{atomic, Arc};
 use std::time::{Duration, Instant};
 
 #[derive(FromArgs)]
-/// Headless cheese race simulator for Blockfish.
+/// Headless race simulator for Blockfish.
 struct Args {
     #[argh(positional)]
     goal: Option<usize>,
+    /// game mode: "cheese" (default), "sprint:<lines>", "pc" or "survival"
+    #[argh(option, short = 'm')]
+    mode: Option<Goal>,
     /// don't display stats until race finishes
     #[argh(switch, short = 'q')]
     quiet: bool,
@@ -30,57 +36,152 @@ struct Args {
     /// display ASCII rendering of game state at the end
     #[argh(switch)]
     ascii: bool,
+    /// let the engine simulate garbage being added after each placement
+    #[argh(switch)]
+    refill: bool,
     /// write a trace of the race to the given file
     #[argh(option)]
     trace_file: Option<PathBuf>,
+    /// replay a trace file and check that it reproduces, using the ruleset recorded in
+    /// the trace unless --ruleset is given
+    #[argh(option)]
+    replay: Option<PathBuf>,
+    /// ruleset name (guideline, ars, classic, srs+, srs-x, pentomino) or JSON file,
+    /// defaults to the guideline rules
+    #[argh(option)]
+    ruleset: Option<PathBuf>,
 }
 
 impl Args {
     fn to_game_config(&self) -> BSConfig {
         let mut cfg = BSConfig::default();
-        if let Some(h) = self.garbage {
-            cfg.garbage.max_height = h;
-        }
-        if let Some(h) = self.min_garbage {
-            cfg.garbage.min_height = h;
+        match self.to_ai_config().goal {
+            Goal::Sprint(_) | Goal::PerfectClear => {
+                // no garbage
+                cfg.garbage.max_height = 0;
+                cfg.garbage.min_height = 0;
+                cfg.garbage.total_lines = Some(0);
+            }
+            Goal::Cheese | Goal::Survival => {
+                if let Some(h) = self.garbage {
+                    cfg.garbage.max_height = h;
+                }
+                if let Some(h) = self.min_garbage {
+                    cfg.garbage.min_height = h;
+                }
+                cfg.garbage.total_lines = self.goal;
+            }
         }
-        cfg.garbage.total_lines = self.goal;
         cfg.prng_seed = self.seed;
         cfg
     }
 
     fn to_ai_config(&self) -> BFConfig {
-        self.ai_params.clone().unwrap_or_default()
+        let mut cfg = self.ai_params.clone().unwrap_or_default();
+        if let Some(goal) = self.mode {
+            cfg.goal = goal;
+        }
+        cfg
     }
 }
 
 struct Race {
     ai: AI,
     stacker: Stacker,
+    refill: bool,
+    goal: Goal,
     ds_goal: Option<usize>,
+    lines: usize,
+    perfect_clears: usize,
+    attack: usize,
     start_time: Instant,
     trace: Vec<usize>,
+    pieces: Vec<TracePiece>,
 }
 
-#[derive(Serialize)]
-struct Trace<'a> {
+#[derive(Serialize, Deserialize)]
+struct Trace {
     time: f64,
     seed: u64,
-    ds: &'a [usize],
+    garbage: usize,
+    min_garbage: usize,
+    goal: Option<usize>,
+    /// Ruleset the race was run with, if not the guideline rules.
+    #[serde(default)]
+    ruleset: Option<RulesetSource>,
+    #[serde(default)]
+    refill: bool,
+    ai_params: String,
+    /// Exact search limit, since `ai_params` rounds it to the nearest thousand.
+    #[serde(default)]
+    search_limit: Option<usize>,
+    ds: Vec<usize>,
+    pieces: Vec<TracePiece>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TracePiece {
+    piece: char,
+    hold: bool,
+    /// `(row, col, rot)` of the piece when it locked.
+    transform: (i16, i16, i32),
+    inputs: Vec<Input>,
+    lines: usize,
+    garbage: usize,
+    /// `None` for traces written before attack was recorded.
+    #[serde(default)]
+    attack: Option<usize>,
+    rating: i64,
+    think_time: f64,
+}
+
+impl Trace {
+    fn to_ai_config(&self) -> Result<BFConfig, String> {
+        let mut cfg: BFConfig = self
+            .ai_params
+            .parse()
+            .map_err(|e| format!("invalid AI parameters in trace: {}", e))?;
+        if let Some(limit) = self.search_limit {
+            cfg.search_limit = limit;
+        }
+        Ok(cfg)
+    }
+
+    fn to_game_config(&self) -> BSConfig {
+        let mut cfg = BSConfig::default();
+        cfg.garbage.max_height = self.garbage;
+        cfg.garbage.min_height = self.min_garbage;
+        cfg.garbage.total_lines = self.goal;
+        cfg.prng_seed = Some(self.seed);
+        cfg
+    }
 }
 
 impl Race {
-    fn new(ai_cfg: BFConfig, game_cfg: BSConfig, rules: Ruleset) -> Self {
+    fn new(
+        ai_cfg: BFConfig,
+        game_cfg: BSConfig,
+        rules: Ruleset,
+        refill: bool,
+    ) -> Result<Self, String> {
+        let goal = ai_cfg.goal;
         let ds_goal = game_cfg.garbage.total_lines;
+        let mut ai = AI::new(ai_cfg);
+        ai.set_ruleset(&rules).map_err(|e| e.to_string())?;
         let stacker = Stacker::new(rules.into(), game_cfg);
-        let ai = AI::new(ai_cfg);
-        Self {
+        Ok(Self {
             ai,
             stacker,
+            refill,
+            goal,
             ds_goal,
+            lines: 0,
+            perfect_clears: 0,
+            attack: 0,
             start_time: Instant::now(),
             trace: Vec::with_capacity(ds_goal.unwrap_or(100) * 5),
-        }
+            pieces: Vec::with_capacity(ds_goal.unwrap_or(100) * 5),
+        })
     }
 
     fn ds(&self) -> usize {
@@ -92,10 +193,17 @@ impl Race {
     }
 
     fn won(&self) -> bool {
-        if let Some(goal) = self.ds_goal {
-            self.ds() >= goal
-        } else {
-            false
+        match self.goal {
+            Goal::Cheese => {
+                if let Some(goal) = self.ds_goal {
+                    self.ds() >= goal
+                } else {
+                    false
+                }
+            }
+            Goal::Sprint(lines) => self.lines >= lines,
+            Goal::PerfectClear => self.perfect_clears > 0,
+            Goal::Survival => false,
         }
     }
 
@@ -108,24 +216,60 @@ impl Race {
         self.won() || self.lost()
     }
 
-    fn next_inputs(&mut self) -> Vec<blockfish::Input> {
-        let snapshot = self.stacker.snapshot().expect("no snapshot");
-        let mut analysis = self.ai.analyze(snapshot);
+    /// Runs the engine on the current position, returning the suggested inputs (ending in
+    /// a hard drop), their rating, and the time taken.
+    fn next_inputs(&mut self) -> (Vec<Input>, i64, Duration) {
+        let start_time = Instant::now();
+        let mut snapshot = self.stacker.snapshot().expect("no snapshot");
+        if self.refill {
+            snapshot.refill = Some(self.stacker.garbage_refill());
+        }
+        let mut cfg = self.ai.config();
+        cfg.goal = self.goal.remaining(self.lines);
+        let mut analysis = self.ai.analyze_with_config(snapshot, cfg);
         analysis.wait();
         let move_id = analysis
             .all_moves()
             .min_by(|&m, &n| analysis.cmp(m, n))
             .expect("no suggestions");
-        let mut inputs = analysis.suggestion(move_id, 1).inputs;
-        assert_eq!(inputs.pop(), Some(blockfish::Input::HD));
-        inputs
+        let sugg = analysis.suggestion(move_id, 1);
+        assert_eq!(sugg.inputs.last(), Some(&Input::HD));
+        (sugg.inputs, sugg.rating, Instant::now() - start_time)
+    }
+
+    /// Plays `inputs` (ending in a hard drop) on the game state, returning the trace
+    /// entry for the placed piece.
+    fn play(&mut self, mut inputs: Vec<Input>, rating: i64, think_time: Duration) -> TracePiece {
+        assert_eq!(inputs.pop(), Some(Input::HD));
+        let hold = inputs.first() == Some(&Input::Hold);
+        self.stacker.run(inputs.iter().cloned());
+        let (piece, row, col, rot) = self.stacker.current_piece_ghost().expect("no piece");
+        let lock = self.stacker.hard_drop();
+        inputs.push(Input::HD);
+        self.trace.push(self.ds() + lock.garbage);
+        self.lines += lock.lines;
+        self.attack += lock.attack;
+        if lock.perfect_clear {
+            self.perfect_clears += 1;
+        }
+        let piece = TracePiece {
+            piece,
+            hold,
+            transform: (row, col, rot),
+            inputs,
+            lines: lock.lines,
+            garbage: lock.garbage,
+            attack: Some(lock.attack),
+            rating,
+            think_time: think_time.as_secs_f64(),
+        };
+        self.pieces.push(piece.clone());
+        piece
     }
 
     fn step(&mut self) {
-        let inputs = self.next_inputs();
-        self.stacker.run(inputs);
-        let (_, garbage_cleared) = self.stacker.hard_drop();
-        self.trace.push(self.ds() + garbage_cleared);
+        let (inputs, rating, think_time) = self.next_inputs();
+        self.play(inputs, rating, think_time);
     }
 
     fn print_stats(&self, w: &mut impl Write, now: Instant, short: bool) -> std::io::Result<()> {
@@ -134,45 +278,212 @@ impl Race {
         let pc = self.pc();
         let pps = (pc as f64) / elapsed;
         if short {
-            write!(w, "{} p, {}", pc, ds)?;
-            if let Some(goal) = self.ds_goal {
-                write!(w, "/{}", goal)?;
+            match self.goal {
+                Goal::Cheese => {
+                    write!(w, "{} p, {}", pc, ds)?;
+                    if let Some(goal) = self.ds_goal {
+                        write!(w, "/{}", goal)?;
+                    }
+                    write!(w, "L ds, ")?;
+                }
+                Goal::Sprint(lines) => write!(w, "{} p, {}/{}L, ", pc, self.lines, lines)?,
+                Goal::PerfectClear | Goal::Survival => {
+                    write!(w, "{} p, {}L, ", pc, self.lines)?;
+                }
             }
-            write!(w, "L ds, {:.2} pps ", pps)?;
+            write!(w, "{:.2} pps ", pps)?;
         } else {
             if self.lost() {
                 writeln!(w, "topped out early")?;
             }
             writeln!(w, "{} pieces", pc)?;
-            writeln!(w, "{}L downstack", ds)?;
+            match self.goal {
+                Goal::Cheese => writeln!(w, "{}L downstack", ds)?,
+                Goal::Sprint(_) | Goal::Survival => writeln!(w, "{}L cleared", self.lines)?,
+                Goal::PerfectClear => {
+                    writeln!(w, "{}L cleared", self.lines)?;
+                    writeln!(w, "{} perfect clears", self.perfect_clears)?;
+                }
+            }
+            writeln!(w, "{}L attack", self.attack)?;
             writeln!(w, "total time: {:.2}s ({:.2}pps)", elapsed, pps)?;
             writeln!(w, "PRNG seed: {}", self.stacker.prng_seed())?;
         }
         Ok(())
     }
 
-    fn as_trace(&self) -> Trace {
+    fn as_trace(&self, ruleset: Option<&RulesetSource>) -> Trace {
         let time = (Instant::now() - self.start_time).as_secs_f64();
         let seed = self.stacker.prng_seed();
+        let cfg = self.stacker.config();
         Trace {
             time,
             seed,
-            ds: &self.trace,
+            garbage: cfg.garbage.max_height,
+            min_garbage: cfg.garbage.min_height,
+            goal: cfg.garbage.total_lines,
+            ruleset: ruleset.cloned(),
+            refill: self.refill,
+            ai_params: self.ai.config().to_string(),
+            search_limit: Some(self.ai.config().search_limit),
+            ds: self.trace.clone(),
+            pieces: self.pieces.clone(),
         }
     }
 }
 
+/// Re-runs the race recorded in `trace`. Returns an error if the recorded placements do
+/// not reproduce the same game, otherwise returns the number of pieces where the engine
+/// now suggests something different than what was recorded.
+fn replay(trace: &Trace, ai_cfg: Option<BFConfig>, rules: Ruleset) -> Result<usize, String> {
+    let ai_cfg = match ai_cfg {
+        Some(cfg) => cfg,
+        None => trace.to_ai_config()?,
+    };
+    let mut race = Race::new(ai_cfg, trace.to_game_config(), rules, trace.refill)?;
+    let mut diverged = 0;
+    for (i, expected) in trace.pieces.iter().enumerate() {
+        if race.lost() {
+            return Err(format!("piece #{}: topped out early", i + 1));
+        }
+        let (inputs, rating, _) = race.next_inputs();
+        if inputs != expected.inputs || rating != expected.rating {
+            if diverged == 0 {
+                println!(
+                    "piece #{}: engine played {:?} (rating {}), trace has {:?} (rating {})",
+                    i + 1,
+                    inputs,
+                    rating,
+                    expected.inputs,
+                    expected.rating,
+                );
+            }
+            diverged += 1;
+        }
+        let actual = race.play(
+            expected.inputs.clone(),
+            expected.rating,
+            Duration::default(),
+        );
+        if (actual.piece, actual.hold, actual.transform)
+            != (expected.piece, expected.hold, expected.transform)
+            || (actual.lines, actual.garbage) != (expected.lines, expected.garbage)
+            || matches!(expected.attack, Some(attack) if actual.attack != Some(attack))
+        {
+            return Err(format!(
+                "piece #{}: expected {:?}, got {:?}",
+                i + 1,
+                expected,
+                actual
+            ));
+        }
+    }
+    if race.ds() != trace.ds.last().cloned().unwrap_or(0) {
+        return Err(format!(
+            "expected {}L downstack, got {}L",
+            trace.ds.last().cloned().unwrap_or(0),
+            race.ds()
+        ));
+    }
+    Ok(diverged)
+}
+
+/// Replays the trace at `path`, using the ruleset recorded in the trace unless `ruleset`
+/// is given.
+fn replay_main(path: PathBuf, ai_cfg: Option<BFConfig>, ruleset: Option<PathBuf>) -> ! {
+    let trace: Trace = match std::fs::File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+    {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("error reading trace file:\n{}", e);
+            std::process::exit(1);
+        }
+    };
+    let ruleset = match open_ruleset(ruleset.as_deref()) {
+        Ok(src) => src.or_else(|| trace.ruleset.clone()),
+        Err(e) => {
+            eprintln!("error reading ruleset file:\n{}", e);
+            std::process::exit(1);
+        }
+    };
+    let rules = match load_ruleset(ruleset.as_ref()) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("error reading ruleset file:\n{}", e);
+            std::process::exit(1);
+        }
+    };
+    match replay(&trace, ai_cfg, rules) {
+        Ok(0) => {
+            println!("{} pieces reproduced", trace.pieces.len());
+            std::process::exit(0);
+        }
+        Ok(n) => {
+            println!("{} pieces reproduced", trace.pieces.len());
+            println!("engine diverged from trace on {} pieces", n);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("trace does not reproduce:\n{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads the `--ruleset` argument `path`, which names a bundled ruleset or a JSON file.
+fn open_ruleset(path: Option<&Path>) -> Result<Option<RulesetSource>, String> {
+    path.map(RulesetSource::open)
+        .transpose()
+        .map_err(|e| e.to_string())
+}
+
+/// Loads the ruleset from `src`. Returns the guideline rules if `None`.
+fn load_ruleset(src: Option<&RulesetSource>) -> Result<Ruleset, String> {
+    match src {
+        Some(src) => src.load().map_err(|e| e.to_string()),
+        None => Ok(Ruleset::guideline()),
+    }
+}
+
 static REFRESH_PERIOD: Duration = Duration::from_millis(500);
 
 fn main() {
     let args = argh::from_env::<Args>();
+    if let Some(path) = args.replay {
+        replay_main(path, args.ai_params, args.ruleset);
+    }
+
+    let ruleset = match open_ruleset(args.ruleset.as_deref()) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("error reading ruleset file:\n{}", e);
+            std::process::exit(1);
+        }
+    };
+    let rules = match load_ruleset(ruleset.as_ref()) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("error reading ruleset file:\n{}", e);
+            std::process::exit(1);
+        }
+    };
 
     // cheese race bot
-    let mut race = Race::new(
+    let race = Race::new(
         args.to_ai_config(),
         args.to_game_config(),
-        Ruleset::guideline(),
+        rules,
+        args.refill,
     );
+    let mut race = match race {
+        Ok(race) => race,
+        Err(e) => {
+            eprintln!("unsupported ruleset:\n{}", e);
+            std::process::exit(1);
+        }
+    };
 
     // periodic stats updates
     let stderr = std::io::stderr();
@@ -237,7 +548,7 @@ fn main() {
                 std::process::exit(1);
             }
         };
-        serde_json::to_writer(file, &race.as_trace()).unwrap();
+        serde_json::to_writer(file, &race.as_trace(ruleset.as_ref())).unwrap();
     }
 
     std::process::exit(if race.won() {
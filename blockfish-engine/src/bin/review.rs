@@ -0,0 +1,453 @@
+use argh::FromArgs;
+use block_stacker::{Config as BSConfig, Ruleset, RulesetSource, Stacker};
+use blockfish::{
+    ai::{Goal, Snapshot, AI},
+    Config as BFConfig, Input, StackerExt as _,
+};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(FromArgs)]
+/// Reviews a finished cheese race, comparing each placement to Blockfish's suggestion.
+struct Args {
+    /// recorded game to review: a trace from blockfish-race or a recording saved by
+    /// blockfish-client
+    #[argh(positional)]
+    game_file: PathBuf,
+    /// parameters to AI, defaults to the parameters recorded in the game, or else
+    /// "25/0,3,4"
+    #[argh(option, short = 'A')]
+    ai_params: Option<BFConfig>,
+    /// ruleset name (guideline, ars, classic, srs+, srs-x, pentomino) or JSON file,
+    /// defaults to the ruleset recorded in the game
+    #[argh(option)]
+    ruleset: Option<PathBuf>,
+    /// estimated pieces lost for a placement to be flagged as a mistake, defaults to 1
+    #[argh(option, short = 't')]
+    threshold: Option<f64>,
+    /// print the report as JSON instead of a table
+    #[argh(switch)]
+    json: bool,
+}
+
+/// A recorded game, in the format shared by race traces and client recordings: the
+/// settings needed to regenerate the pieces and garbage, and the inputs played for each
+/// piece. Race traces also record how the engine was run, which client recordings leave
+/// out. Other fields of race traces are ignored.
+#[derive(Deserialize)]
+struct Game {
+    seed: u64,
+    garbage: usize,
+    min_garbage: usize,
+    goal: Option<usize>,
+    /// Ruleset the game was played with, if not the guideline rules.
+    #[serde(default)]
+    ruleset: Option<RulesetSource>,
+    #[serde(default)]
+    refill: bool,
+    #[serde(default)]
+    ai_params: Option<String>,
+    /// Exact search limit, since `ai_params` rounds it to the nearest thousand.
+    #[serde(default)]
+    search_limit: Option<usize>,
+    pieces: Vec<GamePiece>,
+}
+
+#[derive(Deserialize)]
+struct GamePiece {
+    /// Inputs played for this piece, ending in a hard drop.
+    inputs: Vec<Input>,
+}
+
+impl Game {
+    fn to_game_config(&self) -> BSConfig {
+        let mut cfg = BSConfig::default();
+        cfg.garbage.max_height = self.garbage;
+        cfg.garbage.min_height = self.min_garbage;
+        cfg.garbage.total_lines = self.goal;
+        cfg.prng_seed = Some(self.seed);
+        cfg
+    }
+
+    /// Returns the engine config the game was played with, or the default config if the
+    /// game does not record one.
+    fn to_ai_config(&self) -> Result<BFConfig, Error> {
+        let mut cfg = match self.ai_params.as_ref() {
+            Some(params) => params
+                .parse::<BFConfig>()
+                .map_err(|e| Error::AiParams(e.to_string()))?,
+            None => BFConfig::default(),
+        };
+        if let Some(limit) = self.search_limit {
+            cfg.search_limit = limit;
+        }
+        Ok(cfg)
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    pieces: Vec<PieceReport>,
+    mistakes: usize,
+    pieces_lost: f64,
+}
+
+#[derive(Serialize)]
+struct PieceReport {
+    snapshot: String,
+    played: Vec<Input>,
+    /// `None` if the engine did not rate the placement that was played.
+    played_rating: Option<i64>,
+    best: Vec<Input>,
+    best_rating: i64,
+    /// Estimated number of pieces lost by the played placement compared to the best.
+    pieces_lost: Option<f64>,
+    mistake: bool,
+}
+
+#[derive(Debug)]
+enum Error {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    AiParams(String),
+    Ruleset(serde_json::Error),
+    UnsupportedRuleset(blockfish::ai::UnsupportedRuleset),
+    NoHardDrop(usize),
+    ToppedOut(usize),
+    NoSuggestions(usize),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "error reading game file:\n{}", e),
+            Error::Json(e) => write!(f, "invalid game file:\n{}", e),
+            Error::AiParams(e) => write!(f, "invalid AI parameters in game file:\n{}", e),
+            Error::Ruleset(e) => write!(f, "error reading ruleset:\n{}", e),
+            Error::UnsupportedRuleset(e) => write!(f, "unsupported ruleset:\n{}", e),
+            Error::NoHardDrop(i) => write!(f, "placement #{} does not end in a hard drop", i),
+            Error::ToppedOut(i) => write!(f, "game ended before placement #{}", i),
+            Error::NoSuggestions(i) => write!(f, "engine found no moves for placement #{}", i),
+        }
+    }
+}
+
+struct Review {
+    ai: AI,
+    stacker: Stacker,
+    refill: bool,
+    goal: Goal,
+    lines: usize,
+    threshold: f64,
+}
+
+impl Review {
+    /// Runs the engine on the current position, then plays `played`, which is placement
+    /// number `num`.
+    fn step(&mut self, num: usize, played: &[Input]) -> Result<PieceReport, Error> {
+        let mut snapshot = self.stacker.snapshot().ok_or(Error::ToppedOut(num))?;
+        if self.refill {
+            snapshot.refill = Some(self.stacker.garbage_refill());
+        }
+        let mut cfg = self.ai.config();
+        cfg.goal = self.goal.remaining(self.lines);
+        let mut analysis = self.ai.analyze_with_config(snapshot.clone(), cfg);
+        analysis.wait();
+
+        let best_id = analysis
+            .all_moves()
+            .min_by(|&m, &n| analysis.cmp(m, n))
+            .ok_or(Error::NoSuggestions(num))?;
+        let best = analysis.suggestion(best_id, 1);
+
+        // find the move that results in the same position as the one played
+        let played_pos = self.after(played);
+        let played_rating = analysis
+            .all_moves()
+            .map(|m| analysis.suggestion(m, 1))
+            .find(|sugg| self.after(&sugg.inputs) == played_pos)
+            .map(|sugg| sugg.rating);
+
+        let piece_penalty = self.ai.config().parameters.piece_penalty;
+        let pieces_lost = played_rating.and_then(|r| pieces_lost(r, best.rating, piece_penalty));
+        self.stacker.run(played[..played.len() - 1].iter().cloned());
+        self.lines += self.stacker.hard_drop().lines;
+        Ok(PieceReport {
+            snapshot: snapshot.to_string(),
+            played: played.to_vec(),
+            played_rating,
+            best: best.inputs,
+            best_rating: best.rating,
+            pieces_lost,
+            mistake: matches!(pieces_lost, Some(n) if n >= self.threshold),
+        })
+    }
+
+    /// Returns the position resulting from playing `inputs` on the current position.
+    fn after(&self, inputs: &[Input]) -> Option<Snapshot> {
+        let mut stacker = self.stacker.clone();
+        stacker.run(inputs.iter().cloned());
+        stacker.snapshot()
+    }
+}
+
+/// Estimates the number of pieces lost by a placement rated `played` compared to the best
+/// placement rated `best`. Returns `None` if the parameters give no penalty per piece.
+fn pieces_lost(played: i64, best: i64, piece_penalty: i64) -> Option<f64> {
+    if piece_penalty > 0 {
+        Some((played - best) as f64 / piece_penalty as f64)
+    } else {
+        None
+    }
+}
+
+fn review(args: &Args) -> Result<Report, Error> {
+    let file = std::fs::File::open(&args.game_file).map_err(Error::Io)?;
+    let game: Game = serde_json::from_reader(file).map_err(Error::Json)?;
+    let ruleset = match args.ruleset.as_deref() {
+        Some(path) => Some(RulesetSource::open(path).map_err(Error::Ruleset)?),
+        None => None,
+    };
+    let threshold = args.threshold.unwrap_or(1.0);
+    review_game(&game, args.ai_params.clone(), ruleset, threshold)
+}
+
+/// Reviews `game` using the engine config and ruleset recorded in the game, unless
+/// `ai_cfg` or `ruleset` is given.
+fn review_game(
+    game: &Game,
+    ai_cfg: Option<BFConfig>,
+    ruleset: Option<RulesetSource>,
+    threshold: f64,
+) -> Result<Report, Error> {
+    let ai_cfg = match ai_cfg {
+        Some(cfg) => cfg,
+        None => game.to_ai_config()?,
+    };
+    let rules = match ruleset.as_ref().or(game.ruleset.as_ref()) {
+        Some(src) => src.load().map_err(Error::Ruleset)?,
+        None => Ruleset::guideline(),
+    };
+    let goal = ai_cfg.goal;
+    let mut ai = AI::new(ai_cfg);
+    ai.set_ruleset(&rules).map_err(Error::UnsupportedRuleset)?;
+    let mut review = Review {
+        ai,
+        stacker: Stacker::new(rules.into(), game.to_game_config()),
+        refill: game.refill,
+        goal,
+        lines: 0,
+        threshold,
+    };
+    let mut pieces = Vec::with_capacity(game.pieces.len());
+    for (i, piece) in game.pieces.iter().enumerate() {
+        if piece.inputs.last() != Some(&Input::HD) {
+            return Err(Error::NoHardDrop(i + 1));
+        }
+        pieces.push(review.step(i + 1, &piece.inputs)?);
+    }
+
+    Ok(Report {
+        mistakes: pieces.iter().filter(|p| p.mistake).count(),
+        pieces_lost: pieces.iter().filter_map(|p| p.pieces_lost).sum(),
+        pieces,
+    })
+}
+
+fn print_inputs(inputs: &[Input]) -> String {
+    let inputs = inputs
+        .iter()
+        .map(|i| format!("{:?}", i))
+        .collect::<Vec<_>>();
+    inputs.join(",")
+}
+
+fn print_report(w: &mut impl Write, report: &Report) -> std::io::Result<()> {
+    writeln!(
+        w,
+        "{:>4} {:>6} {:>6} {:>6}  played / best",
+        "#", "played", "best", "lost"
+    )?;
+    for (i, p) in report.pieces.iter().enumerate() {
+        let played_rating = p.played_rating.map_or("?".to_string(), |r| r.to_string());
+        let lost = p
+            .pieces_lost
+            .map_or("?".to_string(), |n| format!("{:.1}", n));
+        write!(
+            w,
+            "{:>4} {:>6} {:>6} {:>6}  {}",
+            i + 1,
+            played_rating,
+            p.best_rating,
+            lost,
+            print_inputs(&p.played)
+        )?;
+        if p.mistake {
+            write!(w, " / {}  <-- mistake", print_inputs(&p.best))?;
+        }
+        writeln!(w)?;
+    }
+    writeln!(w)?;
+    writeln!(w, "{} pieces", report.pieces.len())?;
+    writeln!(w, "{} mistakes", report.mistakes)?;
+    writeln!(w, "{:.1} pieces lost (estimated)", report.pieces_lost)?;
+    Ok(())
+}
+
+fn main() {
+    let args = argh::from_env::<Args>();
+    let report = match review(&args) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    if args.json {
+        serde_json::to_writer(&mut stdout, &report).unwrap();
+        writeln!(stdout).unwrap();
+    } else {
+        print_report(&mut stdout, &report).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ai_config() -> BFConfig {
+        BFConfig {
+            search_limit: 2_000,
+            ..BFConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_read_race_trace() {
+        let json = r#"{"time":1.5,"seed":7,"garbage":9,"min_garbage":2,"goal":100,
+            "ai_params":"25/5,10,10,10","ds":[0],"pieces":[{"piece":"T","hold":false,
+            "transform":[0,4,0],"inputs":["HD"],"lines":0,"garbage":0,"rating":5,
+            "think_time":0.1}]}"#;
+        let game: Game = serde_json::from_str(json).unwrap();
+        assert_eq!(game.pieces.len(), 1);
+        assert_eq!(game.pieces[0].inputs, [Input::HD]);
+        let cfg = game.to_game_config();
+        assert_eq!(cfg.prng_seed, Some(7));
+        assert_eq!(cfg.garbage.total_lines, Some(100));
+        assert!(game.ruleset.is_none());
+        assert_eq!(game.to_ai_config().unwrap().search_limit, 25_000);
+
+        let json = r#"{"seed":7,"garbage":0,"min_garbage":0,"goal":0,"ruleset":"srs+",
+            "refill":true,"ai_params":"2/5,10,10,10/sprint:40","search_limit":1500,
+            "pieces":[]}"#;
+        let game: Game = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            game.ruleset,
+            Some(RulesetSource::Builtin("srs+".to_string()))
+        );
+        assert!(game.refill);
+        let cfg = game.to_ai_config().unwrap();
+        assert_eq!(cfg.search_limit, 1_500);
+        assert_eq!(cfg.goal, Goal::Sprint(40));
+    }
+
+    #[test]
+    fn test_pieces_lost() {
+        assert_eq!(pieces_lost(110, 50, 30), Some(2.0));
+        assert_eq!(pieces_lost(50, 50, 30), Some(0.0));
+        assert_eq!(pieces_lost(110, 50, 0), None);
+    }
+
+    #[test]
+    fn test_review_engine_game() {
+        let mut game = Game {
+            seed: 1,
+            garbage: 9,
+            min_garbage: 2,
+            goal: Some(100),
+            ruleset: None,
+            refill: false,
+            ai_params: None,
+            search_limit: None,
+            pieces: vec![],
+        };
+        // play a few pieces the way the engine would
+        let mut ai = AI::new(ai_config());
+        let mut stacker = Stacker::new(Ruleset::guideline().into(), game.to_game_config());
+        for _ in 0..3 {
+            let mut analysis = ai.analyze(stacker.snapshot().unwrap());
+            analysis.wait();
+            let best = analysis
+                .all_moves()
+                .min_by(|&m, &n| analysis.cmp(m, n))
+                .unwrap();
+            let inputs = analysis.suggestion(best, 1).inputs;
+            stacker.run(inputs.iter().cloned());
+            game.pieces.push(GamePiece { inputs });
+        }
+
+        let report = review_game(&game, Some(ai_config()), None, 1.0).unwrap();
+        assert_eq!(report.pieces.len(), 3);
+        assert_eq!(report.mistakes, 0);
+        for p in report.pieces.iter() {
+            assert_eq!(p.played_rating, Some(p.best_rating));
+            assert_eq!(p.pieces_lost, Some(0.0));
+        }
+
+        game.pieces[1].inputs.pop();
+        assert!(matches!(
+            review_game(&game, Some(ai_config()), None, 1.0),
+            Err(Error::NoHardDrop(2))
+        ));
+    }
+
+    #[test]
+    fn test_review_trace_settings() {
+        let mut cfg = ai_config();
+        cfg.goal = Goal::Sprint(40);
+        let mut game = Game {
+            seed: 3,
+            garbage: 0,
+            min_garbage: 0,
+            goal: Some(0),
+            ruleset: Some(RulesetSource::Builtin("pentomino".to_string())),
+            refill: false,
+            ai_params: Some(cfg.to_string()),
+            search_limit: Some(cfg.search_limit),
+            pieces: vec![],
+        };
+        // play a few pieces the way the engine would, with the recorded settings
+        let rules = Ruleset::pentomino();
+        let mut ai = AI::new(cfg);
+        ai.set_ruleset(&rules).unwrap();
+        let mut stacker = Stacker::new(rules.into(), game.to_game_config());
+        for _ in 0..3 {
+            let mut analysis = ai.analyze(stacker.snapshot().unwrap());
+            analysis.wait();
+            let best = analysis
+                .all_moves()
+                .min_by(|&m, &n| analysis.cmp(m, n))
+                .unwrap();
+            let inputs = analysis.suggestion(best, 1).inputs;
+            stacker.run(inputs.iter().cloned());
+            game.pieces.push(GamePiece { inputs });
+        }
+
+        let report = review_game(&game, None, None, 1.0).unwrap();
+        assert_eq!(report.pieces.len(), 3);
+        assert_eq!(report.mistakes, 0);
+        for p in report.pieces.iter() {
+            assert_eq!(p.played_rating, Some(p.best_rating));
+        }
+
+        // the ruleset given on the command line takes precedence
+        let guideline = RulesetSource::Builtin("guideline".to_string());
+        if let Ok(other) = review_game(&game, None, Some(guideline), 1.0) {
+            assert_ne!(other.pieces[0].snapshot, report.pieces[0].snapshot);
+        }
+    }
+}
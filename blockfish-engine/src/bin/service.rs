@@ -1,7 +1,20 @@
-use blockfish::protos;
+use argh::FromArgs;
+use block_stacker::Ruleset;
+use blockfish::{
+    ai::{StopHandle, WorkerPool},
+    protos,
+};
 use std::{
-    convert::{Infallible, TryFrom},
-    sync::mpsc,
+    collections::HashMap,
+    convert::TryFrom,
+    io::{BufRead, BufReader},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -11,17 +24,98 @@ enum Error {
     Io(#[from] std::io::Error),
     #[error("encountered Protobuf error")]
     Protobuf(#[from] protobuf::error::ProtobufError),
+    #[error("encountered JSON error")]
+    Json(#[from] serde_json::Error),
     #[error("unexpectedly quit")]
     EarlyExit,
+    #[error("Unix domain sockets are not supported on this platform")]
+    UnixSocketsUnsupported,
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Range of protocol versions that clients may pick with a "hello" request.
+const MIN_PROTOCOL_VERSION: u32 = 1;
+const MAX_PROTOCOL_VERSION: u32 = 1;
+
+/// Largest node limit that a "config" may set, which bounds the memory used by a single
+/// search. Larger limits are lowered to this one.
+const MAX_NODE_LIMIT: u32 = 5_000_000;
+
+/// Reasons for rejecting a request, which are reported back to the client with an
+/// "error" response.
+#[derive(Debug, Error)]
+enum RequestError {
+    #[error("unknown request type")]
+    UnknownRequest,
+    #[error("malformed request: {0}")]
+    Malformed(serde_json::Error),
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(#[from] SnapshotError),
+    #[error("invalid ruleset: {0}")]
+    InvalidRuleset(serde_json::Error),
+    #[error("unsupported ruleset: {0}")]
+    UnsupportedRuleset(blockfish::ai::UnsupportedRuleset),
+    #[error("no running analysis with id {0}")]
+    NotRunning(i64),
+    #[error("protocol version {0} is not supported")]
+    UnsupportedProtocol(u32),
+}
+
+impl RequestError {
+    fn code(&self) -> protos::Response_Error_Code {
+        use protos::Response_Error_Code as Code;
+        match self {
+            RequestError::UnknownRequest | RequestError::Malformed(_) => Code::INVALID_REQUEST,
+            RequestError::InvalidSnapshot(_) => Code::INVALID_SNAPSHOT,
+            RequestError::InvalidRuleset(_) | RequestError::UnsupportedRuleset(_) => {
+                Code::INVALID_RULESET
+            }
+            RequestError::NotRunning(_) => Code::NOT_RUNNING,
+            RequestError::UnsupportedProtocol(_) => Code::UNSUPPORTED_PROTOCOL,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+enum SnapshotError {
+    #[error("the queue is empty")]
+    EmptyQueue,
+    #[error("{0:?} is not a piece in the current ruleset")]
+    InvalidPiece(char),
+    #[error("hold {0:?} is more than one piece")]
+    InvalidHold(String),
+    #[error("row {row} is {len} cells wide instead of {cols}")]
+    RowWidth { row: usize, len: usize, cols: u16 },
+    #[error("{len} rows do not fit in the matrix's {rows}")]
+    TooManyRows { len: usize, rows: u16 },
+}
+
+#[derive(FromArgs)]
+/// Runs the Blockfish service, reading requests from stdin and writing responses to
+/// stdout, or serving any number of clients over a socket.
+struct Args {
+    /// exchange newline-delimited JSON instead of length-delimited protobuf messages
+    #[argh(switch)]
+    json: bool,
+    /// listen for clients on this TCP port of localhost
+    #[argh(option)]
+    port: Option<u16>,
+    /// listen for clients on a Unix domain socket created at this path
+    #[argh(option)]
+    socket: Option<PathBuf>,
+    /// maximum number of analyses running at once, shared by every client, defaults to 4
+    #[argh(option)]
+    max_analyses: Option<usize>,
+}
+
 fn main() {
     pretty_env_logger::init();
-    let err = entry();
-    error_trace(&err);
-    std::process::exit(1)
+    let args = argh::from_env::<Args>();
+    if let Err(err) = entry(&args) {
+        error_trace(&err);
+        std::process::exit(1)
+    }
 }
 
 /// Logs the error trace.
@@ -33,44 +127,171 @@ fn error_trace(err: &(dyn std::error::Error + 'static)) {
     }
 }
 
-/// Runs the main loop, blocking indefinitely or returning an error.
-fn entry() -> Error {
+/// Worker threads shared by every session.
+#[derive(Clone)]
+struct Workers {
+    /// Runs the analyses.
+    analyses: WorkerPool,
+    /// Runs the thunks that wait on requests and send their responses. Each thunk starts
+    /// its own analysis, so that an analysis never runs without being waited on.
+    responders: WorkerPool,
+}
+
+impl Workers {
+    /// Allows `max_analyses` analyses, and as many requests waiting on them, at once.
+    fn new(max_analyses: usize) -> Self {
+        Self {
+            analyses: WorkerPool::new(max_analyses),
+            responders: WorkerPool::new(max_analyses),
+        }
+    }
+}
+
+/// Runs the main loop, serving clients from a socket if one was given, otherwise serving
+/// stdin and stdout until stdin is closed.
+fn entry(args: &Args) -> Result<()> {
+    let workers = Workers::new(std::cmp::max(args.max_analyses.unwrap_or(4), 1));
+    if let Some(port) = args.port {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+        log::info!("listening on {}", listener.local_addr()?);
+        serve(
+            listener.incoming(),
+            TcpStream::try_clone,
+            args.json,
+            workers,
+        )
+    } else if let Some(path) = &args.socket {
+        serve_unix(path, args.json, workers)
+    } else {
+        session(std::io::stdin(), std::io::stdout(), args.json, workers)
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix(path: &Path, json: bool, workers: Workers) -> Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+    let listener = UnixListener::bind(path)?;
+    log::info!("listening on {}", path.display());
+    serve(listener.incoming(), UnixStream::try_clone, json, workers)
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_path: &Path, _json: bool, _workers: Workers) -> Result<()> {
+    Err(Error::UnixSocketsUnsupported)
+}
+
+/// Accepts clients from `incoming`, serving each one on a new thread with its own
+/// session. `try_clone` is used to get a second handle to a client's stream, for writing.
+fn serve<S>(
+    incoming: impl Iterator<Item = std::io::Result<S>>,
+    try_clone: impl Fn(&S) -> std::io::Result<S>,
+    json: bool,
+    workers: Workers,
+) -> Result<()>
+where
+    S: std::io::Read + std::io::Write + Send + 'static,
+{
+    for (n, stream) in incoming.enumerate() {
+        let (rdr, wtr) = match stream.and_then(|s| Ok((try_clone(&s)?, s))) {
+            Ok(halves) => halves,
+            Err(err) => {
+                log::warn!("failed to accept client: {}", err);
+                continue;
+            }
+        };
+        log::info!("client #{} connected", n);
+        let workers = workers.clone();
+        std::thread::spawn(move || match session(rdr, wtr, json, workers) {
+            Ok(()) => log::info!("client #{} disconnected", n),
+            Err(err) => {
+                log::warn!("client #{} disconnected with an error", n);
+                error_trace(&err);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Serves a single client, reading requests from `rdr` and writing responses to `wtr`.
+/// Blocks until `rdr` is closed and every response has been written, or returns an
+/// error. If `json` is `true`, uses the JSON wire format. Requests are run on `workers`.
+fn session(
+    rdr: impl std::io::Read + Send + 'static,
+    wtr: impl std::io::Write + Send + 'static,
+    json: bool,
+    workers: Workers,
+) -> Result<()> {
     let (req_tx, req_rx) = mpsc::sync_channel(0);
     let (res_tx, res_rx) = mpsc::sync_channel(0);
-    let (err_tx1, err_rx) = mpsc::sync_channel(0);
-    let err_tx2 = err_tx1.clone();
-    let err_tx3 = err_tx1.clone();
+    let (done_tx1, done_rx) = mpsc::sync_channel(0);
+    let done_tx2 = done_tx1.clone();
+    let done_tx3 = done_tx1.clone();
+    let err_tx = res_tx.clone();
     std::thread::spawn(move || {
-        let stdin = std::io::stdin();
-        if let Err(err) = reader(stdin.lock(), req_tx) {
-            let _ = err_tx1.send(err);
+        let result = match json {
+            true => json_reader(BufReader::new(rdr), req_tx, err_tx),
+            false => reader(rdr, req_tx),
+        };
+        if let Err(err) = result {
+            let _ = done_tx1.send(Err(err));
         }
     });
     std::thread::spawn(move || {
-        let stdout = std::io::stdout();
-        if let Err(err) = writer(stdout.lock(), res_rx) {
-            let _ = err_tx2.send(err);
-        }
+        // finishes once the service and every analysis drop their end of the channel
+        let result = match json {
+            true => json_writer(wtr, res_rx),
+            false => writer(wtr, res_rx),
+        };
+        let _ = done_tx2.send(result);
     });
     std::thread::spawn(move || {
-        let err = match service(res_tx, req_rx) {
-            Ok(impossible) => match impossible {},
-            Err(err) => err,
-        };
-        let _ = err_tx3.send(err);
+        if let Err(err) = service(res_tx, req_rx, workers) {
+            let _ = done_tx3.send(Err(err));
+        }
     });
-    err_rx.recv().unwrap()
+    done_rx.recv().unwrap()
 }
 
 /// Reader thread: deserializes requests from `rdr` into `tx`.
 fn reader(mut rdr: impl std::io::Read, tx: mpsc::SyncSender<protos::Request>) -> Result<()> {
     let mut cis = protobuf::CodedInputStream::new(&mut rdr);
-    loop {
+    while !cis.eof()? {
         let req = cis.read_message()?;
         if tx.send(req).is_err() {
-            return Ok(());
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Reader thread for the JSON wire format: deserializes one request per line from `rdr`
+/// into `tx`. Malformed requests are skipped, sending an "error" response to `err_tx`.
+fn json_reader(
+    rdr: impl BufRead,
+    tx: mpsc::SyncSender<protos::Request>,
+    err_tx: mpsc::SyncSender<protos::Response>,
+) -> Result<()> {
+    for line in rdr.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let req = match serde_json::from_str(&line) {
+            Ok(req) => from_json_request(req),
+            Err(err) => {
+                let err = RequestError::Malformed(err);
+                log::warn!("rejecting request: {}", err);
+                if err_tx.send(to_error_proto(0, &err)).is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+        if tx.send(req).is_err() {
+            break;
         }
     }
+    Ok(())
 }
 
 /// Writer thread: serializes responses from `rx` into `wtr`.
@@ -87,46 +308,199 @@ fn writer(mut wtr: impl std::io::Write, rx: mpsc::Receiver<protos::Response>) ->
     }
 }
 
+/// Writer thread for the JSON wire format: serializes responses from `rx` into `wtr`, one
+/// per line.
+fn json_writer(mut wtr: impl std::io::Write, rx: mpsc::Receiver<protos::Response>) -> Result<()> {
+    loop {
+        let res = match rx.recv() {
+            Ok(res) => res,
+            Err(_) => return Ok(()),
+        };
+        let res = match to_json_response(res) {
+            Some(res) => res,
+            None => {
+                log::warn!("dropping response without a type");
+                continue;
+            }
+        };
+        serde_json::to_writer(&mut wtr, &res)?;
+        wtr.write_all(b"\n")?;
+        wtr.flush()?;
+    }
+}
+
 struct Service {
     ai: blockfish::ai::AI,
+    settings: Settings,
+    cols: u16,
+    rows: u16,
+    // queues are cut down to the current piece and previews, once a ruleset is set
+    previews: Option<usize>,
+    // analyses, batches and perfect clear searches that may still be running, by id
+    running: HashMap<i64, Running>,
+    // number of analyses the worker pool runs at once
+    workers: usize,
+    // pieces of the current ruleset
+    pieces: Vec<blockfish::Color>,
+    // protocol version picked by the client, or the oldest one if it never said hello
+    protocol_version: u32,
+}
+
+/// Service settings that are not part of the engine config.
+#[derive(Clone)]
+struct Settings {
     max_results: usize,
     max_placements: usize,
+    progress_interval: Option<Duration>,
 }
 
-/// Service thread: responds to requests.
+/// Controls for a running analysis, batch or perfect clear search.
+struct Running {
+    stop: StopHandle,
+    // set to drop the responses once stopped
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Sends the responses to a request that runs after the service thread has moved on.
+type ResponseThunk = Box<dyn FnOnce(&mpsc::SyncSender<protos::Response>) + Send>;
+
+/// Service thread: responds to requests, until there are no more requests.
 fn service(
     tx: mpsc::SyncSender<protos::Response>,
     rx: mpsc::Receiver<protos::Request>,
-) -> Result<Infallible> {
+    workers: Workers,
+) -> Result<()> {
     log::debug!("started service thread");
 
     // running ai instance
-    let mut service = Service::new();
+    let mut service = Service::new(workers.analyses.clone());
+    let respond = |res_thunk: ResponseThunk| {
+        let tx = tx.clone();
+        if let Err(e) = workers.responders.execute(move || res_thunk(&tx)) {
+            log::error!("failed to respond to request: {}", e);
+        }
+    };
     tx.send(service.greeting()?).map_err(|_| Error::EarlyExit)?;
 
     loop {
         // recv & dispatch requests
-        let mut req = rx.recv().map_err(|_| Error::EarlyExit)?;
-        if req.has_set_config() {
-            service.set_config(req.take_set_config())?;
+        let mut req = match rx.recv() {
+            Ok(req) => req,
+            Err(_) => {
+                log::debug!("no more requests, stopping service thread");
+                return Ok(());
+            }
+        };
+        let id = request_id(&req);
+        // the response to send right away, if any
+        let result = if req.has_set_config() {
+            service.set_config(req.take_set_config());
+            Ok(None)
         } else if req.has_set_ruleset() {
-            service.set_ruleset(req.take_set_ruleset())?;
+            service.set_ruleset(req.take_set_ruleset()).map(|()| None)
         } else if req.has_analyze() {
-            let res_thunk = service.analyze(req.take_analyze())?;
-            let tx = tx.clone();
-            std::thread::spawn(move || tx.send(res_thunk()));
+            service.analyze(req.take_analyze()).map(|res_thunk| {
+                respond(Box::new(res_thunk));
+                None
+            })
+        } else if req.has_analyze_batch() {
+            service
+                .analyze_batch(req.take_analyze_batch())
+                .map(|res_thunk| {
+                    respond(Box::new(res_thunk));
+                    None
+                })
+        } else if req.has_find_perfect_clears() {
+            service
+                .perfect_clears(req.take_find_perfect_clears())
+                .map(|res_thunk| {
+                    respond(Box::new(res_thunk));
+                    None
+                })
+        } else if req.has_static_eval() {
+            service.static_eval(req.get_static_eval()).map(Some)
+        } else if req.has_cancel() {
+            service.stop(req.get_cancel().id, true).map(|()| None)
+        } else if req.has_stop() {
+            service.stop(req.get_stop().id, false).map(|()| None)
+        } else if req.has_hello() {
+            service.hello(req.get_hello()).map(Some)
         } else {
-            log::warn!("got an unknown request type");
-        }
+            Err(RequestError::UnknownRequest)
+        };
+        let res = match result {
+            Ok(Some(res)) => res,
+            Ok(None) => continue,
+            Err(err) => {
+                log::warn!("rejecting request: {}", err);
+                to_error_proto(id, &err)
+            }
+        };
+        tx.send(res).map_err(|_| Error::EarlyExit)?;
+    }
+}
+
+/// Features advertised in the greeting.
+fn capabilities() -> protos::Capabilities {
+    let mut caps = protos::Capabilities::new();
+    caps.set_rulesets(
+        Ruleset::BUILTIN_NAMES
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+    );
+    caps.set_goals(
+        ["cheese", "sprint", "perfect_clear", "survival"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+    );
+    caps.progress = true;
+    caps.cancellation = true;
+    caps.batches = true;
+    caps.perfect_clears = true;
+    caps.max_node_limit = MAX_NODE_LIMIT;
+    caps
+}
+
+/// Returns the id of request `req`, or 0 if it doesn't have one.
+fn request_id(req: &protos::Request) -> i64 {
+    if req.has_analyze() {
+        req.get_analyze().id
+    } else if req.has_analyze_batch() {
+        req.get_analyze_batch().id
+    } else if req.has_find_perfect_clears() {
+        req.get_find_perfect_clears().id
+    } else if req.has_static_eval() {
+        req.get_static_eval().id
+    } else if req.has_cancel() {
+        req.get_cancel().id
+    } else if req.has_stop() {
+        req.get_stop().id
+    } else {
+        0
     }
 }
 
 impl Service {
-    fn new() -> Self {
+    fn new(pool: WorkerPool) -> Self {
+        let workers = pool.workers();
+        let mut ai = blockfish::ai::AI::new(blockfish::Config::default());
+        ai.set_worker_pool(pool);
         Self {
-            ai: blockfish::ai::AI::new(blockfish::Config::default()),
-            max_results: std::usize::MAX,
-            max_placements: std::usize::MAX,
+            ai,
+            settings: Settings {
+                max_results: std::usize::MAX,
+                max_placements: std::usize::MAX,
+                progress_interval: None,
+            },
+            cols: 10,
+            rows: Ruleset::guideline().rows as u16,
+            previews: None,
+            running: HashMap::new(),
+            workers,
+            pieces: Ruleset::guideline().types().filter_map(color).collect(),
+            protocol_version: MIN_PROTOCOL_VERSION,
         }
     }
 
@@ -135,44 +509,273 @@ impl Service {
         let mut grt = protos::Response_Greeting::new();
         grt.set_version(blockfish::version().to_string());
         grt.set_motd("Hello world".to_string());
+        grt.min_protocol_version = MIN_PROTOCOL_VERSION;
+        grt.max_protocol_version = MAX_PROTOCOL_VERSION;
+        grt.set_capabilities(capabilities());
         let mut res = protos::Response::new();
         res.set_greeting(grt);
         Ok(res)
     }
 
+    /// Handles a "hello" request, switching to the protocol version picked by the client.
+    /// Every supported protocol version is currently spoken the same way.
+    fn hello(
+        &mut self,
+        msg: &protos::Request_Hello,
+    ) -> std::result::Result<protos::Response, RequestError> {
+        let version = msg.protocol_version;
+        if version < MIN_PROTOCOL_VERSION || version > MAX_PROTOCOL_VERSION {
+            return Err(RequestError::UnsupportedProtocol(version));
+        }
+        self.protocol_version = version;
+        let mut res = protos::Response::new();
+        res.mut_hello().protocol_version = version;
+        Ok(res)
+    }
+
     /// Handles a "set_config" request.
-    fn set_config(&mut self, msg: protos::Request_Config) -> Result<()> {
-        let cfg = self.ai.config_mut();
-        set_if_nonzero(&mut cfg.search_limit, msg.node_limit as usize);
-        set_if_nonzero(&mut self.max_results, msg.max_results as usize);
-        set_if_nonzero(&mut self.max_placements, msg.max_placements as usize);
-        Ok(())
+    fn set_config(&mut self, msg: protos::Request_Config) {
+        apply_config(&msg, self.ai.config_mut(), &mut self.settings);
     }
 
     /// Handles a "set_ruleset" request.
-    fn set_ruleset(&mut self, _: protos::Request_Ruleset) -> Result<()> {
-        log::warn!("`set_ruleset` is useless currently");
+    fn set_ruleset(
+        &mut self,
+        msg: protos::Request_Ruleset,
+    ) -> std::result::Result<(), RequestError> {
+        let rules = from_ruleset_proto(&msg).map_err(RequestError::InvalidRuleset)?;
+        self.ai
+            .set_ruleset(&rules)
+            .map_err(RequestError::UnsupportedRuleset)?;
+        self.cols = rules.cols as u16;
+        self.rows = rules.rows as u16;
+        self.previews = Some(rules.previews);
+        self.pieces = rules.types().filter_map(color).collect();
         Ok(())
     }
 
-    /// Handles a "analyze" request. Returns a thunk that, when called, blocks until the
-    /// analysis finishes, returning a response.
+    /// Converts and validates a snapshot to be searched, which must have a nonempty queue.
+    fn snapshot(
+        &self,
+        ss: &protos::Snapshot,
+    ) -> std::result::Result<blockfish::ai::Snapshot, SnapshotError> {
+        let mut ss = from_snapshot_proto(ss, self.cols, self.rows, &self.pieces)?;
+        if ss.queue.is_empty() {
+            return Err(SnapshotError::EmptyQueue);
+        }
+        if let Some(previews) = self.previews {
+            ss.queue.truncate(previews + 1);
+        }
+        Ok(ss)
+    }
+
+    /// Handles a "analyze" request. Returns a thunk that, when called, starts the analysis
+    /// and blocks until it finishes, sending "progress" responses along the way (if
+    /// configured) and then the final response to `tx`.
     fn analyze(
         &mut self,
         msg: protos::Request_Analyze,
-    ) -> Result<impl (FnOnce() -> protos::Response) + Send> {
+    ) -> std::result::Result<impl FnOnce(&mpsc::SyncSender<protos::Response>) + Send, RequestError>
+    {
         let id = msg.id;
-        let ss = from_snapshot_proto(msg.get_snapshot());
-        let count = self.max_results;
-        let len = self.max_placements;
-        let mut handle = self.ai.analyze(ss);
-        Ok(move || {
+        let ss = self.snapshot(msg.get_snapshot())?;
+        let mut cfg = self.ai.config();
+        let mut settings = self.settings.clone();
+        if msg.has_config() {
+            apply_config(msg.get_config(), &mut cfg, &mut settings);
+        }
+        let count = settings.max_results;
+        let len = settings.max_placements;
+        let interval = settings.progress_interval;
+        let mut ai = self.ai.clone();
+        let stop = StopHandle::default();
+        let cancelled = self.register(id, stop.clone());
+
+        Ok(move |tx: &mpsc::SyncSender<protos::Response>| {
+            let is_cancelled = || cancelled.load(Ordering::Relaxed);
+            let mut handle = ai.analyze_with_stop_handle(ss, cfg, stop);
+            if let Some(interval) = interval {
+                // report the best suggestions every interval, as long as the search made
+                // progress since the last report
+                let mut nodes = 0;
+                while !handle.wait_timeout(interval) {
+                    if is_cancelled() {
+                        break;
+                    }
+                    let stats = handle.progress();
+                    if stats.nodes == nodes {
+                        continue;
+                    }
+                    nodes = stats.nodes;
+                    let mut res = protos::Response::new();
+                    let progress = res.mut_progress();
+                    progress.id = id;
+                    to_analysis_proto(&handle, count, len, progress);
+                    if tx.send(res).is_err() {
+                        return;
+                    }
+                }
+            }
             handle.wait();
+            if is_cancelled() {
+                return;
+            }
             let mut res = protos::Response::new();
-            let mut finished = res.mut_finished();
+            let finished = res.mut_finished();
             finished.id = id;
-            to_analysis_proto(handle, count, len, &mut finished);
-            res
+            to_analysis_proto(&handle, count, len, finished);
+            let _ = tx.send(res);
+        })
+    }
+
+    /// Handles an "analyze_batch" request. Returns a thunk that, when called, blocks until
+    /// every analysis in the batch finishes, sending each "finished" response to `tx` as
+    /// soon as it is ready, and then the "batch_finished" response.
+    fn analyze_batch(
+        &mut self,
+        msg: protos::Request_AnalyzeBatch,
+    ) -> std::result::Result<impl FnOnce(&mpsc::SyncSender<protos::Response>) + Send, RequestError>
+    {
+        let id = msg.id;
+        let mut cfg = self.ai.config();
+        let mut settings = self.settings.clone();
+        if msg.has_config() {
+            apply_config(msg.get_config(), &mut cfg, &mut settings);
+        }
+        let count = settings.max_results;
+        let len = settings.max_placements;
+        let max_running = match msg.max_running {
+            0 => self.workers,
+            n => n as usize,
+        };
+        let ids = msg.items.iter().map(|item| item.id).collect::<Vec<_>>();
+        let snapshots = msg
+            .items
+            .iter()
+            .map(|item| self.snapshot(item.get_snapshot()))
+            .collect::<std::result::Result<_, _>>()?;
+        let mut batch = self.ai.analyze_batch(snapshots, cfg, max_running);
+        let cancelled = self.register(id, batch.stop_handle());
+
+        Ok(move |tx: &mpsc::SyncSender<protos::Response>| {
+            let is_cancelled = || cancelled.load(Ordering::Relaxed);
+            for (idx, analysis) in &mut batch {
+                if is_cancelled() {
+                    continue;
+                }
+                let mut res = protos::Response::new();
+                let finished = res.mut_finished();
+                finished.id = ids[idx];
+                to_analysis_proto(&analysis, count, len, finished);
+                if tx.send(res).is_err() {
+                    return;
+                }
+            }
+            if is_cancelled() {
+                return;
+            }
+            let mut res = protos::Response::new();
+            let out = res.mut_batch_finished();
+            out.id = id;
+            out.count = ids.len() as u32;
+            out.set_stats(to_stats_proto(&batch.stats()));
+            let _ = tx.send(res);
+        })
+    }
+
+    /// Handles a "static_eval" request.
+    fn static_eval(
+        &self,
+        msg: &protos::Request_StaticEval,
+    ) -> std::result::Result<protos::Response, RequestError> {
+        // only the matrix is evaluated, so the queue may be empty
+        let ss = from_snapshot_proto(msg.get_snapshot(), self.cols, self.rows, &self.pieces)?;
+        let params = if msg.has_parameters() {
+            from_parameters_proto(msg.get_parameters())
+        } else {
+            self.ai.config().parameters
+        };
+        let eval = self.ai.static_eval(&ss);
+        let mut res = protos::Response::new();
+        let out = res.mut_static_eval();
+        out.id = msg.id;
+        out.rows = eval.rows as u32;
+        out.piece_estimate = eval.piece_estimate as u32;
+        out.i_dependencies = eval.i_dependencies as u32;
+        out.attack = eval.attack as u32;
+        out.score = eval.score(&params);
+        Ok(res)
+    }
+
+    /// Registers a request `id` that runs until `stop` is stopped, so that it can be
+    /// cancelled or stopped by later requests. Returns the flag that is set once it is
+    /// cancelled; the request counts as running as long as the flag is shared.
+    fn register(&mut self, id: i64, stop: StopHandle) -> Arc<AtomicBool> {
+        // forget about requests that already finished
+        self.running
+            .retain(|_, running| Arc::strong_count(&running.cancelled) > 1);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let running = Running {
+            stop,
+            cancelled: cancelled.clone(),
+        };
+        if let Some(prev) = self.running.insert(id, running) {
+            log::warn!("request id {} reused while still running", id);
+            prev.stop.stop();
+        }
+        cancelled
+    }
+
+    /// Handles a "cancel" request if `cancel` is `true`, otherwise a "stop" request.
+    fn stop(&mut self, id: i64, cancel: bool) -> std::result::Result<(), RequestError> {
+        let running = match self.running.remove(&id) {
+            Some(running) if Arc::strong_count(&running.cancelled) > 1 => running,
+            _ => return Err(RequestError::NotRunning(id)),
+        };
+        if cancel {
+            running.cancelled.store(true, Ordering::Relaxed);
+        }
+        running.stop.stop();
+        Ok(())
+    }
+
+    /// Handles a "find_perfect_clears" request. Returns a thunk that, when called, runs
+    /// the search and sends the response to `tx`.
+    fn perfect_clears(
+        &mut self,
+        msg: protos::Request_FindPerfectClears,
+    ) -> std::result::Result<impl FnOnce(&mpsc::SyncSender<protos::Response>) + Send, RequestError>
+    {
+        let id = msg.id;
+        let ss = self.snapshot(msg.get_snapshot())?;
+        // zero means no limit besides the length of the queue
+        let max_pieces = match msg.max_pieces {
+            0 => std::usize::MAX,
+            n => n as usize,
+        };
+        let count = self.settings.max_results;
+        let ai = self.ai.clone();
+        let stop = StopHandle::default();
+        let cancelled = self.register(id, stop.clone());
+
+        Ok(move |tx: &mpsc::SyncSender<protos::Response>| {
+            let pcs = ai.perfect_clears_with_stop_handle(ss, max_pieces, &stop);
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            let mut res = protos::Response::new();
+            let out = res.mut_perfect_clears();
+            out.id = id;
+            out.complete = pcs.complete;
+            out.nodes = pcs.nodes as u64;
+            out.set_solutions(
+                pcs.solutions
+                    .iter()
+                    .take(count)
+                    .map(to_perfect_clear_proto)
+                    .collect(),
+            );
+            let _ = tx.send(res);
         })
     }
 }
@@ -181,42 +784,165 @@ impl Service {
 // Below is all helper functions for converting between `protos::*` types
 //////////////////////////////////////////////////////////////////////////////////////////
 
+/// Applies the nonzero fields of `msg` to the engine config `cfg` and `settings`, except
+/// for the progress interval, which is always applied. The node limit is capped at
+/// `MAX_NODE_LIMIT`.
+fn apply_config(
+    msg: &protos::Request_Config,
+    cfg: &mut blockfish::Config,
+    settings: &mut Settings,
+) {
+    let node_limit = std::cmp::min(msg.node_limit, MAX_NODE_LIMIT);
+    set_if_nonzero(&mut cfg.search_limit, node_limit as usize);
+    if msg.has_goal() {
+        cfg.goal = from_goal_proto(msg.get_goal());
+    }
+    if msg.has_parameters() {
+        cfg.parameters = from_parameters_proto(msg.get_parameters());
+    }
+    set_if_nonzero(&mut settings.max_results, msg.max_results as usize);
+    set_if_nonzero(&mut settings.max_placements, msg.max_placements as usize);
+    // zero turns progress reports off
+    settings.progress_interval = match msg.progress_interval_millis {
+        0 => None,
+        millis => Some(Duration::from_millis(millis as u64)),
+    };
+}
+
 fn set_if_nonzero(y: &mut usize, x: usize) {
     if x != 0 {
         *y = x;
     }
 }
 
-fn from_snapshot_proto(ss: &protos::Snapshot) -> blockfish::ai::Snapshot {
-    blockfish::ai::Snapshot {
-        hold: ss.hold.chars().next().and_then(color),
-        queue: ss.queue.chars().filter_map(color).collect(),
-        matrix: matrix(ss.rows.iter().map(|s| s.as_str())),
+/// Converts `ss`, checking that it has at most `rows` rows, each `cols` wide, and that the
+/// hold and queue only contain `pieces`.
+fn from_snapshot_proto(
+    ss: &protos::Snapshot,
+    cols: u16,
+    rows: u16,
+    pieces: &[blockfish::Color],
+) -> std::result::Result<blockfish::ai::Snapshot, SnapshotError> {
+    let piece = |ch| match color(ch) {
+        Some(c) if pieces.contains(&c) => Ok(c),
+        _ => Err(SnapshotError::InvalidPiece(ch)),
+    };
+    if ss.hold.chars().count() > 1 {
+        return Err(SnapshotError::InvalidHold(ss.hold.clone()));
     }
+    if ss.rows.len() > rows as usize {
+        let len = ss.rows.len();
+        return Err(SnapshotError::TooManyRows { len, rows });
+    }
+    for (row, row_str) in ss.rows.iter().enumerate() {
+        let len = row_str.chars().count();
+        if len != cols as usize {
+            return Err(SnapshotError::RowWidth { row, len, cols });
+        }
+    }
+    Ok(blockfish::ai::Snapshot {
+        hold: ss.hold.chars().next().map(piece).transpose()?,
+        queue: ss
+            .queue
+            .chars()
+            .map(piece)
+            .collect::<std::result::Result<_, _>>()?,
+        matrix: matrix(ss.rows.iter().map(|s| s.as_str()), cols),
+        bag: None,
+        refill: None,
+        combo: None,
+        b2b: false,
+    })
+}
+
+fn from_goal_proto(goal: &protos::Goal) -> blockfish::ai::Goal {
+    use blockfish::ai::Goal;
+    if goal.has_sprint() {
+        Goal::Sprint(goal.get_sprint().lines as usize)
+    } else if goal.has_perfect_clear() {
+        Goal::PerfectClear
+    } else if goal.has_survival() {
+        Goal::Survival
+    } else {
+        Goal::Cheese
+    }
+}
+
+fn from_parameters_proto(params: &protos::Parameters) -> blockfish::Parameters {
+    blockfish::Parameters {
+        row_factor: params.row_factor,
+        piece_estimate_factor: params.piece_estimate_factor,
+        i_dependency_factor: params.i_dependency_factor,
+        piece_penalty: params.piece_penalty,
+        attack_factor: params.attack_factor,
+    }
+}
+
+fn from_ruleset_proto(rules: &protos::Request_Ruleset) -> serde_json::Result<Ruleset> {
+    if rules.has_json() {
+        Ruleset::from_json(rules.get_json())
+    } else if rules.has_custom() {
+        Ruleset::from_json(&custom_ruleset_json(rules.get_custom()).to_string())
+    } else if rules.has_name() {
+        Ruleset::builtin(rules.get_name()).ok_or_else(|| {
+            use serde::de::Error;
+            serde_json::Error::custom(format!("unknown ruleset {:?}", rules.get_name()))
+        })
+    } else {
+        Ok(Ruleset::guideline())
+    }
+}
+
+/// Converts a structured ruleset into the JSON format accepted by `Ruleset::from_json`.
+fn custom_ruleset_json(rules: &protos::Request_Ruleset_Custom) -> serde_json::Value {
+    use serde_json::{json, Map, Value};
+    let coord = |c: &protos::Request_Ruleset_Coord| json!([c.row, c.col]);
+    let coords = |cs: &[protos::Request_Ruleset_Coord]| cs.iter().map(coord).collect::<Vec<_>>();
+    let minos = rules
+        .pieces
+        .iter()
+        .map(|pc| {
+            let kicks = pc
+                .kicks
+                .iter()
+                .map(|k| (k.rotation.clone(), Value::from(coords(&k.offsets))))
+                .collect::<Map<_, _>>();
+            let mino = json!({
+                "width": pc.width,
+                "coords": coords(&pc.coords),
+                "spawn": coord(pc.get_spawn()),
+                "kicks": kicks,
+            });
+            (pc.piece.clone(), mino)
+        })
+        .collect::<Map<_, _>>();
+    json!({
+        "cols": rules.cols,
+        "rows": rules.rows,
+        "visible_rows": rules.visible_rows,
+        "previews": rules.previews,
+        "minos": minos,
+    })
 }
 
 fn color(ch: char) -> Option<blockfish::Color> {
     blockfish::Color::try_from(ch).ok()
 }
 
-fn matrix<'a>(row_strs: impl Iterator<Item = &'a str>) -> blockfish::BasicMatrix {
-    let mut mat = None;
+fn matrix<'a>(row_strs: impl Iterator<Item = &'a str>, cols: u16) -> blockfish::BasicMatrix {
+    let mut mat = blockfish::BasicMatrix::with_cols(cols);
     for (i, row_str) in row_strs.enumerate() {
-        let mat = mat.get_or_insert_with(|| {
-            let cols = row_str.chars().count();
-            blockfish::BasicMatrix::with_cols(cols as u16)
-        });
-        for (j, ch) in row_str.chars().enumerate() {
+        for (j, ch) in row_str.chars().take(cols as usize).enumerate() {
             if blockfish::Color::try_from(ch).is_ok() {
                 mat.set((i as u16, j as u16));
             }
         }
     }
-    mat.unwrap_or_else(|| blockfish::BasicMatrix::with_cols(10))
+    mat
 }
 
 fn to_analysis_proto(
-    analysis: blockfish::ai::Analysis,
+    analysis: &blockfish::ai::Analysis,
     count: usize,
     len: usize,
     out: &mut protos::Response_Analysis,
@@ -230,9 +956,7 @@ fn to_analysis_proto(
             .map(|&m_id| to_suggestion_proto(&analysis.suggestion(m_id, len)))
             .collect(),
     );
-    if let Some(stats) = analysis.stats() {
-        out.set_stats(to_stats_proto(&stats));
-    }
+    out.set_stats(to_stats_proto(&analysis.progress()));
 }
 
 fn to_suggestion_proto(sugg: &blockfish::ai::Suggestion) -> protos::Suggestion {
@@ -242,6 +966,22 @@ fn to_suggestion_proto(sugg: &blockfish::ai::Suggestion) -> protos::Suggestion {
     proto
 }
 
+fn to_perfect_clear_proto(sol: &blockfish::ai::PcSolution) -> protos::PerfectClear {
+    let mut proto = protos::PerfectClear::new();
+    proto.set_placements(
+        sol.placements
+            .iter()
+            .map(|pl| {
+                let mut proto = protos::PerfectClear_Placement::new();
+                proto.piece = pl.color.as_char().to_string();
+                proto.inputs = pl.inputs.iter().map(|&i| to_input_proto(i)).collect();
+                proto
+            })
+            .collect(),
+    );
+    proto
+}
+
 fn to_stats_proto(stats: &blockfish::ai::Stats) -> protos::Stats {
     let mut proto = protos::Stats::new();
     proto.nodes = stats.nodes as u64;
@@ -259,5 +999,910 @@ fn to_input_proto(i: blockfish::Input) -> protos::Input {
         blockfish::Input::Hold => protos::Input::HOLD,
         blockfish::Input::SD => protos::Input::SD,
         blockfish::Input::HD => protos::Input::HD,
+        blockfish::Input::Flip => protos::Input::FLIP,
+    }
+}
+
+fn to_error_proto(id: i64, err: &RequestError) -> protos::Response {
+    let mut res = protos::Response::new();
+    let out = res.mut_error();
+    out.id = id;
+    out.code = err.code();
+    out.message = err.to_string();
+    res
+}
+
+fn from_input_proto(i: protos::Input) -> blockfish::Input {
+    match i {
+        protos::Input::LEFT => blockfish::Input::Left,
+        protos::Input::RIGHT => blockfish::Input::Right,
+        protos::Input::CW => blockfish::Input::CW,
+        protos::Input::CCW => blockfish::Input::CCW,
+        protos::Input::HOLD => blockfish::Input::Hold,
+        protos::Input::SD => blockfish::Input::SD,
+        protos::Input::HD => blockfish::Input::HD,
+        protos::Input::FLIP => blockfish::Input::Flip,
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Below is the JSON wire format, which mirrors `blockfish.proto`
+//////////////////////////////////////////////////////////////////////////////////////////
+
+/// Requests and responses for `--json` mode. Each message is a single line containing an
+/// object whose only key is the name of the request or response type, e.g.
+///
+/// ```text
+/// > {"set_ruleset":{"name":"srs+"}}
+/// > {"analyze":{"id":1,"snapshot":{"hold":"T","queue":["L","O","Z"]}}}
+/// < {"greeting":{"version":"...","motd":"Hello world"}}
+/// < {"finished":{"id":1,"stats":{...},"suggestions":[{"rating":12,"inputs":["HD"]}]}}
+/// ```
+///
+/// Omitted fields take the same defaults as in the protobuf format, except for evaluation
+/// parameters, which take the engine's defaults. The matrix and inputs use the serde
+/// formats of `BasicMatrix` and `Input`.
+mod json {
+    use blockfish::{BasicMatrix, Color, Input};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Request {
+        SetConfig(Config),
+        SetRuleset(Ruleset),
+        Analyze {
+            id: i64,
+            snapshot: Snapshot,
+            config: Option<Config>,
+        },
+        FindPerfectClears {
+            id: i64,
+            snapshot: Snapshot,
+            #[serde(default)]
+            max_pieces: u32,
+        },
+        StaticEval {
+            id: i64,
+            snapshot: Snapshot,
+            parameters: Option<Parameters>,
+        },
+        AnalyzeBatch {
+            id: i64,
+            items: Vec<BatchItem>,
+            config: Option<Config>,
+            #[serde(default)]
+            max_running: u32,
+        },
+        Cancel {
+            id: i64,
+        },
+        Stop {
+            id: i64,
+        },
+        Hello {
+            protocol_version: u32,
+        },
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    pub struct Config {
+        pub node_limit: u32,
+        pub max_results: u32,
+        pub max_placements: u32,
+        pub goal: Option<Goal>,
+        pub progress_interval_millis: u32,
+        pub parameters: Option<Parameters>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Goal {
+        Cheese,
+        Sprint { lines: u32 },
+        PerfectClear,
+        Survival,
+    }
+
+    /// Omitted parameters take the engine's default values, rather than zero.
+    #[derive(Deserialize)]
+    #[serde(default)]
+    pub struct Parameters {
+        pub row_factor: i64,
+        pub piece_estimate_factor: i64,
+        pub i_dependency_factor: i64,
+        pub piece_penalty: i64,
+        pub attack_factor: i64,
+    }
+
+    impl Default for Parameters {
+        fn default() -> Self {
+            let params = blockfish::Parameters::default();
+            Self {
+                row_factor: params.row_factor,
+                piece_estimate_factor: params.piece_estimate_factor,
+                i_dependency_factor: params.i_dependency_factor,
+                piece_penalty: params.piece_penalty,
+                attack_factor: params.attack_factor,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Ruleset {
+        Srs,
+        /// Either a string or an object, in the format of `support/guideline.json`.
+        Json(serde_json::Value),
+        Name(String),
+    }
+
+    #[derive(Deserialize)]
+    pub struct BatchItem {
+        pub id: i64,
+        pub snapshot: Snapshot,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default)]
+    pub struct Snapshot {
+        pub hold: Option<Color>,
+        pub queue: Vec<Color>,
+        pub matrix: Option<BasicMatrix>,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Response {
+        Greeting {
+            version: String,
+            motd: String,
+            min_protocol_version: u32,
+            max_protocol_version: u32,
+            capabilities: Capabilities,
+        },
+        Hello {
+            protocol_version: u32,
+        },
+        Finished(Analysis),
+        PerfectClears {
+            id: i64,
+            solutions: Vec<PerfectClear>,
+            complete: bool,
+            nodes: u64,
+        },
+        Progress(Analysis),
+        StaticEval {
+            id: i64,
+            rows: u32,
+            piece_estimate: u32,
+            i_dependencies: u32,
+            attack: u32,
+            score: i64,
+        },
+        BatchFinished {
+            id: i64,
+            count: u32,
+            stats: Stats,
+        },
+        Error {
+            id: i64,
+            code: ErrorCode,
+            message: String,
+        },
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ErrorCode {
+        InvalidRequest,
+        InvalidSnapshot,
+        InvalidRuleset,
+        NotRunning,
+        UnsupportedProtocol,
+    }
+
+    #[derive(Serialize)]
+    pub struct Capabilities {
+        pub rulesets: Vec<String>,
+        pub goals: Vec<String>,
+        pub progress: bool,
+        pub cancellation: bool,
+        pub batches: bool,
+        pub perfect_clears: bool,
+        pub max_node_limit: u32,
+    }
+
+    #[derive(Serialize)]
+    pub struct Analysis {
+        pub id: i64,
+        pub stats: Stats,
+        pub suggestions: Vec<Suggestion>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Stats {
+        pub nodes: u64,
+        pub iterations: u64,
+        pub time_taken_millis: u64,
+    }
+
+    #[derive(Serialize)]
+    pub struct Suggestion {
+        pub rating: i64,
+        pub inputs: Vec<Input>,
+    }
+
+    #[derive(Serialize)]
+    pub struct PerfectClear {
+        pub placements: Vec<Placement>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Placement {
+        pub piece: String,
+        pub inputs: Vec<Input>,
+    }
+}
+
+fn from_json_request(req: json::Request) -> protos::Request {
+    let mut proto = protos::Request::new();
+    match req {
+        json::Request::SetConfig(cfg) => proto.set_set_config(from_json_config(cfg)),
+        json::Request::SetRuleset(rules) => {
+            let out = proto.mut_set_ruleset();
+            match rules {
+                json::Ruleset::Srs => out.set_srs(protos::Request_Ruleset_SRS::new()),
+                json::Ruleset::Json(serde_json::Value::String(s)) => out.set_json(s),
+                json::Ruleset::Json(value) => out.set_json(value.to_string()),
+                json::Ruleset::Name(name) => out.set_name(name),
+            }
+        }
+        json::Request::Analyze {
+            id,
+            snapshot,
+            config,
+        } => {
+            let out = proto.mut_analyze();
+            out.id = id;
+            out.set_snapshot(from_json_snapshot(snapshot));
+            if let Some(cfg) = config {
+                out.set_config(from_json_config(cfg));
+            }
+        }
+        json::Request::FindPerfectClears {
+            id,
+            snapshot,
+            max_pieces,
+        } => {
+            let out = proto.mut_find_perfect_clears();
+            out.id = id;
+            out.set_snapshot(from_json_snapshot(snapshot));
+            out.max_pieces = max_pieces;
+        }
+        json::Request::StaticEval {
+            id,
+            snapshot,
+            parameters,
+        } => {
+            let out = proto.mut_static_eval();
+            out.id = id;
+            out.set_snapshot(from_json_snapshot(snapshot));
+            if let Some(params) = parameters {
+                out.set_parameters(from_json_parameters(params));
+            }
+        }
+        json::Request::AnalyzeBatch {
+            id,
+            items,
+            config,
+            max_running,
+        } => {
+            let out = proto.mut_analyze_batch();
+            out.id = id;
+            out.set_items(
+                items
+                    .into_iter()
+                    .map(|item| {
+                        let mut proto = protos::Request_AnalyzeBatch_Item::new();
+                        proto.id = item.id;
+                        proto.set_snapshot(from_json_snapshot(item.snapshot));
+                        proto
+                    })
+                    .collect(),
+            );
+            if let Some(cfg) = config {
+                out.set_config(from_json_config(cfg));
+            }
+            out.max_running = max_running;
+        }
+        json::Request::Cancel { id } => proto.mut_cancel().id = id,
+        json::Request::Stop { id } => proto.mut_stop().id = id,
+        json::Request::Hello { protocol_version } => {
+            proto.mut_hello().protocol_version = protocol_version
+        }
+    }
+    proto
+}
+
+fn from_json_config(cfg: json::Config) -> protos::Request_Config {
+    let mut proto = protos::Request_Config::new();
+    proto.node_limit = cfg.node_limit;
+    proto.max_results = cfg.max_results;
+    proto.max_placements = cfg.max_placements;
+    proto.progress_interval_millis = cfg.progress_interval_millis;
+    if let Some(goal) = cfg.goal {
+        let out = proto.mut_goal();
+        match goal {
+            json::Goal::Cheese => out.set_cheese(protos::Goal_Cheese::new()),
+            json::Goal::Sprint { lines } => out.mut_sprint().lines = lines,
+            json::Goal::PerfectClear => out.set_perfect_clear(protos::Goal_PerfectClear::new()),
+            json::Goal::Survival => out.set_survival(protos::Goal_Survival::new()),
+        }
+    }
+    if let Some(params) = cfg.parameters {
+        proto.set_parameters(from_json_parameters(params));
+    }
+    proto
+}
+
+fn from_json_parameters(params: json::Parameters) -> protos::Parameters {
+    let mut proto = protos::Parameters::new();
+    proto.row_factor = params.row_factor;
+    proto.piece_estimate_factor = params.piece_estimate_factor;
+    proto.i_dependency_factor = params.i_dependency_factor;
+    proto.piece_penalty = params.piece_penalty;
+    proto.attack_factor = params.attack_factor;
+    proto
+}
+
+fn from_json_snapshot(ss: json::Snapshot) -> protos::Snapshot {
+    let mut proto = protos::Snapshot::new();
+    proto.hold = ss.hold.map(|c| c.as_char().to_string()).unwrap_or_default();
+    proto.queue = ss.queue.iter().map(|c| c.as_char()).collect();
+    if let Some(mat) = ss.matrix {
+        // any letter marks a filled cell, see `matrix`
+        let mat = &mat;
+        let row = |i: u16| (0..mat.cols()).map(move |j| if mat.get((i, j)) { 'G' } else { ' ' });
+        proto.set_rows((0..mat.rows()).map(|i| row(i).collect()).collect());
+    }
+    proto
+}
+
+/// Converts a response to the JSON wire format. Returns `None` for a response without a
+/// type, which the service never sends.
+fn to_json_response(res: protos::Response) -> Option<json::Response> {
+    use protos::Response_oneof_res as Res;
+    Some(match res.res? {
+        Res::greeting(mut grt) => {
+            let caps = grt.take_capabilities();
+            json::Response::Greeting {
+                version: grt.version,
+                motd: grt.motd,
+                min_protocol_version: grt.min_protocol_version,
+                max_protocol_version: grt.max_protocol_version,
+                capabilities: json::Capabilities {
+                    rulesets: caps.rulesets.into_vec(),
+                    goals: caps.goals.into_vec(),
+                    progress: caps.progress,
+                    cancellation: caps.cancellation,
+                    batches: caps.batches,
+                    perfect_clears: caps.perfect_clears,
+                    max_node_limit: caps.max_node_limit,
+                },
+            }
+        }
+        Res::hello(hello) => json::Response::Hello {
+            protocol_version: hello.protocol_version,
+        },
+        Res::finished(analysis) => json::Response::Finished(to_json_analysis(&analysis)),
+        Res::progress(analysis) => json::Response::Progress(to_json_analysis(&analysis)),
+        Res::perfect_clears(pcs) => json::Response::PerfectClears {
+            id: pcs.id,
+            solutions: pcs
+                .solutions
+                .iter()
+                .map(|sol| json::PerfectClear {
+                    placements: sol
+                        .placements
+                        .iter()
+                        .map(|pl| json::Placement {
+                            piece: pl.piece.clone(),
+                            inputs: pl.inputs.iter().map(|&i| from_input_proto(i)).collect(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            complete: pcs.complete,
+            nodes: pcs.nodes,
+        },
+        Res::static_eval(eval) => json::Response::StaticEval {
+            id: eval.id,
+            rows: eval.rows,
+            piece_estimate: eval.piece_estimate,
+            i_dependencies: eval.i_dependencies,
+            attack: eval.attack,
+            score: eval.score,
+        },
+        Res::batch_finished(batch) => json::Response::BatchFinished {
+            id: batch.id,
+            count: batch.count,
+            stats: to_json_stats(batch.get_stats()),
+        },
+        Res::error(err) => {
+            use protos::Response_Error_Code as Code;
+            json::Response::Error {
+                id: err.id,
+                code: match err.code {
+                    Code::INVALID_REQUEST => json::ErrorCode::InvalidRequest,
+                    Code::INVALID_SNAPSHOT => json::ErrorCode::InvalidSnapshot,
+                    Code::INVALID_RULESET => json::ErrorCode::InvalidRuleset,
+                    Code::NOT_RUNNING => json::ErrorCode::NotRunning,
+                    Code::UNSUPPORTED_PROTOCOL => json::ErrorCode::UnsupportedProtocol,
+                },
+                message: err.message,
+            }
+        }
+    })
+}
+
+fn to_json_analysis(analysis: &protos::Response_Analysis) -> json::Analysis {
+    json::Analysis {
+        id: analysis.id,
+        stats: to_json_stats(analysis.get_stats()),
+        suggestions: analysis
+            .suggestions
+            .iter()
+            .map(|sugg| json::Suggestion {
+                rating: sugg.rating,
+                inputs: sugg.inputs.iter().map(|&i| from_input_proto(i)).collect(),
+            })
+            .collect(),
+    }
+}
+
+fn to_json_stats(stats: &protos::Stats) -> json::Stats {
+    json::Stats {
+        nodes: stats.nodes,
+        iterations: stats.iterations,
+        time_taken_millis: stats.time_taken_millis,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            max_results: std::usize::MAX,
+            max_placements: std::usize::MAX,
+            progress_interval: None,
+        }
+    }
+
+    #[test]
+    fn test_apply_config_progress_interval() {
+        let mut cfg = blockfish::Config::default();
+        let mut settings = settings();
+        let mut msg = protos::Request_Config::new();
+        msg.progress_interval_millis = 100;
+        apply_config(&msg, &mut cfg, &mut settings);
+        assert_eq!(settings.progress_interval, Some(Duration::from_millis(100)));
+        // zero turns progress off again, while other zero fields are left alone
+        let mut msg = protos::Request_Config::new();
+        msg.max_results = 3;
+        apply_config(&msg, &mut cfg, &mut settings);
+        assert_eq!(settings.progress_interval, None);
+        assert_eq!(settings.max_results, 3);
+        apply_config(&protos::Request_Config::new(), &mut cfg, &mut settings);
+        assert_eq!(settings.max_results, 3);
+    }
+
+    #[test]
+    fn test_apply_config_node_limit() {
+        let mut cfg = blockfish::Config::default();
+        let mut msg = protos::Request_Config::new();
+        msg.node_limit = 1000;
+        apply_config(&msg, &mut cfg, &mut settings());
+        assert_eq!(cfg.search_limit, 1000);
+        msg.node_limit = std::u32::MAX;
+        apply_config(&msg, &mut cfg, &mut settings());
+        assert_eq!(cfg.search_limit, MAX_NODE_LIMIT as usize);
+        assert_eq!(capabilities().max_node_limit, MAX_NODE_LIMIT);
+    }
+
+    #[test]
+    fn test_hello() {
+        let mut service = Service::new(WorkerPool::new(1));
+        assert_eq!(service.protocol_version, MIN_PROTOCOL_VERSION);
+        let mut msg = protos::Request_Hello::new();
+        msg.protocol_version = MAX_PROTOCOL_VERSION;
+        let res = service.hello(&msg).unwrap();
+        assert_eq!(res.get_hello().protocol_version, MAX_PROTOCOL_VERSION);
+        assert_eq!(service.protocol_version, MAX_PROTOCOL_VERSION);
+        // a rejected version keeps the one picked before
+        msg.protocol_version = MAX_PROTOCOL_VERSION + 1;
+        match service.hello(&msg) {
+            Err(RequestError::UnsupportedProtocol(v)) => assert_eq!(v, MAX_PROTOCOL_VERSION + 1),
+            res => panic!("expected unsupported protocol, got {:?}", res),
+        }
+        assert_eq!(service.protocol_version, MAX_PROTOCOL_VERSION);
+    }
+
+    fn json_request(line: &str) -> protos::Request {
+        from_json_request(serde_json::from_str(line).unwrap())
+    }
+
+    fn json_response(res: protos::Response) -> serde_json::Value {
+        serde_json::to_value(to_json_response(res).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_json_parameters_default() {
+        let req =
+            json_request(r#"{"static_eval":{"id":3,"snapshot":{},"parameters":{"row_factor":7}}}"#);
+        let params = from_parameters_proto(req.get_static_eval().get_parameters());
+        assert_eq!(
+            params,
+            blockfish::Parameters {
+                row_factor: 7,
+                ..blockfish::Parameters::default()
+            }
+        );
+        let req = json_request(r#"{"set_config":{"parameters":{}}}"#);
+        let params = from_parameters_proto(req.get_set_config().get_parameters());
+        assert_eq!(params, blockfish::Parameters::default());
+    }
+
+    #[test]
+    fn test_json_requests() {
+        let mut matrix = blockfish::BasicMatrix::with_cols(10);
+        matrix.set((0, 0));
+        matrix.set((1, 9));
+        let line = serde_json::json!({
+            "analyze": {
+                "id": 5,
+                "snapshot": {"hold": "T", "queue": ["L", "O"], "matrix": matrix},
+                "config": {"node_limit": 1000, "goal": {"sprint": {"lines": 40}}},
+            }
+        });
+        let mut req = json_request(&line.to_string());
+        assert_eq!(request_id(&req), 5);
+        let mut msg = req.take_analyze();
+        let ss = msg.take_snapshot();
+        assert_eq!((ss.hold.as_str(), ss.queue.as_str()), ("T", "LO"));
+        let ss =
+            from_snapshot_proto(&ss, 10, 40, &Service::new(WorkerPool::new(1)).pieces).unwrap();
+        assert_eq!(ss.matrix, matrix);
+        let cfg = msg.get_config();
+        assert_eq!(cfg.node_limit, 1000);
+        assert_eq!(cfg.get_goal().get_sprint().lines, 40);
+
+        let req = json_request(
+            r#"{"analyze_batch":{"id":2,"items":[{"id":3,"snapshot":{"queue":["I"]}}]}}"#,
+        );
+        let batch = req.get_analyze_batch();
+        assert_eq!((batch.id, batch.max_running), (2, 0));
+        assert_eq!(batch.items[0].id, 3);
+        assert_eq!(batch.items[0].get_snapshot().queue, "I");
+
+        let req = json_request(r#"{"set_ruleset":{"name":"srs+"}}"#);
+        assert_eq!(req.get_set_ruleset().get_name(), "srs+");
+        let req = json_request(r#"{"set_ruleset":{"json":{"cols":4}}}"#);
+        assert_eq!(req.get_set_ruleset().get_json(), r#"{"cols":4}"#);
+        assert_eq!(json_request(r#"{"cancel":{"id":9}}"#).get_cancel().id, 9);
+        assert_eq!(json_request(r#"{"stop":{"id":8}}"#).get_stop().id, 8);
+        let req = json_request(r#"{"hello":{"protocol_version":1}}"#);
+        assert_eq!(req.get_hello().protocol_version, 1);
+        assert!(serde_json::from_str::<json::Request>(r#"{"launch":{}}"#).is_err());
+    }
+
+    #[test]
+    fn test_json_responses() {
+        use serde_json::json;
+
+        let mut res = protos::Response::new();
+        res.mut_hello().protocol_version = 1;
+        assert_eq!(
+            json_response(res),
+            json!({"hello": {"protocol_version": 1}})
+        );
+
+        let mut res = protos::Response::new();
+        let finished = res.mut_finished();
+        finished.id = 4;
+        let mut sugg = protos::Suggestion::new();
+        sugg.rating = 12;
+        sugg.inputs = vec![protos::Input::FLIP, protos::Input::HD];
+        finished.mut_suggestions().push(sugg);
+        finished.mut_stats().nodes = 100;
+        assert_eq!(
+            json_response(res),
+            json!({"finished": {
+                "id": 4,
+                "stats": {"nodes": 100, "iterations": 0, "time_taken_millis": 0},
+                "suggestions": [{"rating": 12, "inputs": ["Flip", "HD"]}],
+            }})
+        );
+
+        let mut res = protos::Response::new();
+        let eval = res.mut_static_eval();
+        eval.id = 6;
+        eval.rows = 2;
+        eval.score = -5;
+        assert_eq!(
+            json_response(res),
+            json!({"static_eval": {
+                "id": 6,
+                "rows": 2,
+                "piece_estimate": 0,
+                "i_dependencies": 0,
+                "attack": 0,
+                "score": -5,
+            }})
+        );
+
+        let mut res = protos::Response::new();
+        let batch = res.mut_batch_finished();
+        batch.id = 7;
+        batch.count = 3;
+        assert_eq!(
+            json_response(res),
+            json!({"batch_finished": {
+                "id": 7,
+                "count": 3,
+                "stats": {"nodes": 0, "iterations": 0, "time_taken_millis": 0},
+            }})
+        );
+
+        let res = to_error_proto(8, &RequestError::NotRunning(8));
+        assert_eq!(
+            json_response(res),
+            json!({"error": {
+                "id": 8,
+                "code": "not_running",
+                "message": "no running analysis with id 8",
+            }})
+        );
+
+        assert!(to_json_response(protos::Response::new()).is_none());
+    }
+
+    fn snapshot_proto(hold: &str, queue: &str, rows: &[&str]) -> protos::Snapshot {
+        let mut ss = protos::Snapshot::new();
+        ss.hold = hold.to_string();
+        ss.queue = queue.to_string();
+        ss.rows = rows.iter().map(|row| row.to_string()).collect();
+        ss
+    }
+
+    #[test]
+    fn test_from_snapshot_proto() {
+        let pieces = Service::new(WorkerPool::new(1)).pieces;
+        let convert = |ss| from_snapshot_proto(&ss, 4, 3, &pieces);
+        let ss = convert(snapshot_proto("T", "IO", &["GG.G", " G  "])).unwrap();
+        assert_eq!(ss.hold, color('T'));
+        assert_eq!(ss.queue, [color('I').unwrap(), color('O').unwrap()]);
+        assert_eq!(ss.matrix.rows(), 2);
+        assert!(ss.matrix.get((0, 3)) && !ss.matrix.get((0, 2)));
+        assert!(ss.matrix.get((1, 1)) && !ss.matrix.get((1, 0)));
+        // the empty snapshot is fine here; only searches need a queue
+        assert!(convert(snapshot_proto("", "", &[])).is_ok());
+
+        match convert(snapshot_proto("", "TQI", &[])) {
+            Err(SnapshotError::InvalidPiece('Q')) => {}
+            res => panic!("expected invalid piece, got {:?}", res),
+        }
+        match convert(snapshot_proto("", "T.", &[])) {
+            Err(SnapshotError::InvalidPiece('.')) => {}
+            res => panic!("expected invalid piece, got {:?}", res),
+        }
+        match convert(snapshot_proto("Q", "T", &[])) {
+            Err(SnapshotError::InvalidPiece('Q')) => {}
+            res => panic!("expected invalid piece, got {:?}", res),
+        }
+        match convert(snapshot_proto("TI", "O", &[])) {
+            Err(SnapshotError::InvalidHold(hold)) => assert_eq!(hold, "TI"),
+            res => panic!("expected invalid hold, got {:?}", res),
+        }
+        match convert(snapshot_proto("", "T", &["GGGG", "GG G "])) {
+            Err(SnapshotError::RowWidth { row, len, cols }) => {
+                assert_eq!((row, len, cols), (1, 5, 4))
+            }
+            res => panic!("expected wrong row width, got {:?}", res),
+        }
+        match convert(snapshot_proto("", "T", &["GGG ", "GG G", " GGG", "G GG"])) {
+            Err(SnapshotError::TooManyRows { len, rows }) => assert_eq!((len, rows), (4, 3)),
+            res => panic!("expected too many rows, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_session_errors() {
+        let requests = [
+            r#"{"analyze":{"id":1,"snapshot":{}}}"#,
+            r#"{"find_perfect_clears":{"id":3,"snapshot":{"hold":"Q","queue":["T"]}}}"#,
+            r#"{"set_ruleset":{"name":"tetris99"}}"#,
+            r#"{"set_ruleset":{"json":"{"}}"#,
+            r#"{"stop":{"id":4}}"#,
+            r#"{"hello":{"protocol_version":99}}"#,
+            r#"{"analyze":{"id":5}"#,
+        ];
+        let output = json_session(&requests);
+        let mut errors = output
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let res: serde_json::Value = serde_json::from_str(line).unwrap();
+                let error = &res["error"];
+                assert_ne!(error["message"].as_str(), Some(""));
+                assert!(error["message"].is_string());
+                (error["id"].as_i64().unwrap(), error["code"].clone())
+            })
+            .collect::<Vec<_>>();
+        // the malformed request is rejected by the reader, possibly before the others
+        errors.sort_by_key(|(id, code)| (*id, code.to_string()));
+        assert_eq!(
+            errors,
+            [
+                (0, "invalid_request".into()),
+                (0, "invalid_ruleset".into()),
+                (0, "invalid_ruleset".into()),
+                (0, "unsupported_protocol".into()),
+                (1, "invalid_snapshot".into()),
+                (3, "invalid_snapshot".into()),
+                (4, "not_running".into()),
+            ]
+        );
+    }
+
+    /// Writer whose output can be read back after the session that owns it ends.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Response type of each JSON response line, in order.
+    fn response_types(lines: &str) -> Vec<String> {
+        lines
+            .lines()
+            .map(|line| {
+                let res: serde_json::Value = serde_json::from_str(line).unwrap();
+                res.as_object().unwrap().keys().next().unwrap().clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_session_json() {
+        let requests = [
+            r#"{"hello":{"protocol_version":1}}"#,
+            r#"{"analyze":{"id":1,"snapshot":{"queue":["T","I","O"]},"config":{"node_limit":100}}}"#,
+            r#"{"analyze":{"id":2,"snapshot":{"queue":["L","J"]},"config":{"node_limit":100}}}"#,
+            r#"{"static_eval":{"id":3,"snapshot":{}}}"#,
+            r#"not json"#,
+        ];
+        let mut types = response_types(&json_session(&requests));
+        assert_eq!(types[0], "greeting");
+        types.sort();
+        assert_eq!(
+            types,
+            [
+                "error",
+                "finished",
+                "finished",
+                "greeting",
+                "hello",
+                "static_eval"
+            ]
+        );
+    }
+
+    /// Runs a JSON session with `requests`, returning the response lines.
+    fn json_session(requests: &[&str]) -> String {
+        let rdr = std::io::Cursor::new(requests.join("\n"));
+        let wtr = SharedBuf::default();
+        // returns once every request has responded
+        session(rdr, wtr.clone(), true, Workers::new(2)).unwrap();
+        let output = wtr.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_session_batch_stop() {
+        // node limits are capped at MAX_NODE_LIMIT, so check that "stop" cut the
+        // searches short rather than waiting for them to run to the limit
+        let batch = r#"{"analyze_batch":{"id":1,"items":[
+            {"id":2,"snapshot":{"queue":["L","J","S","Z","O","T","I"]}},
+            {"id":3,"snapshot":{"queue":["T","I","O","S","Z"]}},
+            {"id":4,"snapshot":{"queue":["I","O"]}}
+        ],"config":{"node_limit":4294967295},"max_running":2}}"#
+            .replace('\n', "");
+        let output = json_session(&[&batch, r#"{"stop":{"id":1}}"#]);
+        let types = response_types(&output);
+        assert_eq!(
+            types,
+            [
+                "greeting",
+                "finished",
+                "finished",
+                "finished",
+                "batch_finished"
+            ]
+        );
+        let nodes: u64 = output
+            .lines()
+            .filter_map(|line| {
+                let res: serde_json::Value = serde_json::from_str(line).unwrap();
+                res["finished"]["stats"]["nodes"].as_u64()
+            })
+            .sum();
+        assert!(nodes < MAX_NODE_LIMIT as u64, "{} nodes searched", nodes);
+        let output = json_session(&[&batch, r#"{"cancel":{"id":1}}"#]);
+        assert_eq!(response_types(&output), ["greeting"]);
+    }
+
+    #[test]
+    fn test_session_perfect_clears_stop() {
+        let requests = [
+            r#"{"set_config":{"node_limit":4294967295}}"#,
+            r#"{"find_perfect_clears":{"id":1,"snapshot":{"queue":["I","O","T","L","J","S","Z","I","O","T","L"]}}}"#,
+            r#"{"stop":{"id":1}}"#,
+        ];
+        let output = json_session(&requests);
+        let res: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1]["perfect_clears"]["id"], 1);
+        assert_eq!(res[1]["perfect_clears"]["complete"], false);
+        let output = json_session(&[requests[0], requests[1], r#"{"cancel":{"id":1}}"#]);
+        assert_eq!(response_types(&output), ["greeting"]);
+    }
+
+    #[test]
+    fn test_serve_json() {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            serve(
+                listener.incoming(),
+                TcpStream::try_clone,
+                true,
+                Workers::new(1),
+            )
+        });
+        // each client gets its own session, sharing the workers
+        let clients: Vec<_> = (0..2)
+            .map(|id| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                let req = serde_json::json!({
+                    "analyze": {"id": id, "snapshot": {"queue": ["S", "Z"]}, "config": {"node_limit": 100}},
+                });
+                writeln!(stream, "{}", req).unwrap();
+                stream
+            })
+            .collect();
+        for (id, stream) in clients.into_iter().enumerate() {
+            let mut lines = std::io::BufReader::new(stream).lines();
+            let greeting = lines.next().unwrap().unwrap();
+            assert_eq!(response_types(&greeting), ["greeting"]);
+            let finished: serde_json::Value =
+                serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+            assert_eq!(finished["finished"]["id"], id);
+        }
     }
 }
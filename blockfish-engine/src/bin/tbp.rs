@@ -0,0 +1,748 @@
+use argh::FromArgs;
+use block_stacker::{Chain, Config as BSConfig, Ruleset, Stacker};
+use blockfish::{
+    ai::{Analysis, Goal, Snapshot, UnsupportedRuleset, AI},
+    BasicMatrix, Color, Config as BFConfig, Input, StackerExt as _,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(FromArgs)]
+/// Runs Blockfish as a bot speaking the Tetris Bot Protocol (TBP) over stdin and stdout.
+struct Args {
+    /// game mode: "cheese" (default), "sprint:<lines>", "pc" or "survival"
+    #[argh(option, short = 'm')]
+    mode: Option<Goal>,
+    /// parameters to AI, defaults to "25/0,3,4"
+    #[argh(option, short = 'A')]
+    ai_params: Option<BFConfig>,
+    /// ruleset name (guideline, ars, classic, srs+, srs-x, pentomino) or JSON file,
+    /// defaults to the guideline rules. only tetrominoes can be suggested
+    #[argh(option)]
+    ruleset: Option<PathBuf>,
+}
+
+impl Args {
+    fn to_ai_config(&self) -> BFConfig {
+        let mut cfg = self.ai_params.clone().unwrap_or_default();
+        if let Some(goal) = self.mode {
+            cfg.goal = goal;
+        }
+        cfg
+    }
+}
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("encountered IO error")]
+    Io(#[from] std::io::Error),
+    #[error("encountered JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported ruleset: {0}")]
+    UnsupportedRuleset(#[from] UnsupportedRuleset),
+}
+
+fn main() {
+    let args = argh::from_env::<Args>();
+    if let Err(err) = run(&args) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Messages
+
+/// Messages sent from the frontend to the bot.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FrontendMessage {
+    Rules {},
+    Start(Start),
+    Suggest {},
+    Play {
+        #[serde(rename = "move")]
+        mov: Move,
+    },
+    NewPiece {
+        piece: Color,
+    },
+    Stop {},
+    Quit {},
+    /// Unknown messages must be ignored, for forwards compatibility.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Messages sent from the bot to the frontend.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotMessage {
+    Info {
+        name: &'static str,
+        version: &'static str,
+        author: &'static str,
+        features: Vec<String>,
+    },
+    Ready {},
+    Suggestion {
+        moves: Vec<Move>,
+    },
+}
+
+#[derive(Deserialize)]
+struct Start {
+    hold: Option<Color>,
+    queue: Vec<Color>,
+    /// Number of consecutive line clears, including the last placement.
+    combo: usize,
+    back_to_back: bool,
+    /// Rows of cells, bottom row first. Empty cells are `null`.
+    board: Vec<Vec<Option<Color>>>,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+struct Move {
+    location: PieceLocation,
+    spin: Spin,
+}
+
+/// Location of a piece. `(x, y)` is the position of the piece's center, with `(0, 0)`
+/// being the bottom-left cell of the board.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+struct PieceLocation {
+    #[serde(rename = "type")]
+    piece: Color,
+    orientation: Orientation,
+    x: i16,
+    y: i16,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Orientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+impl From<block_stacker::Spin> for Spin {
+    fn from(spin: block_stacker::Spin) -> Self {
+        match spin {
+            block_stacker::Spin::None => Spin::None,
+            block_stacker::Spin::Mini => Spin::Mini,
+            block_stacker::Spin::Full => Spin::Full,
+        }
+    }
+}
+
+impl From<Spin> for block_stacker::Spin {
+    fn from(spin: Spin) -> Self {
+        match spin {
+            Spin::None => block_stacker::Spin::None,
+            Spin::Mini => block_stacker::Spin::Mini,
+            Spin::Full => block_stacker::Spin::Full,
+        }
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Piece locations
+
+/// Returns the cells of `piece` in the north orientation, relative to its center, or
+/// `None` if the piece is not one of the seven tetrominoes.
+fn piece_cells(piece: Color) -> Option<[(i16, i16); 4]> {
+    Some(match piece.as_char() {
+        'I' => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        'O' => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        'T' => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        'L' => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        'J' => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        'S' => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        'Z' => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+        _ => return None,
+    })
+}
+
+impl Orientation {
+    /// Returns the orientation after `rot` clockwise rotations from north.
+    fn from_rot(rot: i32) -> Self {
+        match rot.rem_euclid(4) {
+            0 => Orientation::North,
+            1 => Orientation::East,
+            2 => Orientation::South,
+            _ => Orientation::West,
+        }
+    }
+
+    /// Rotates the north relative coordinate `(x, y)` into this orientation.
+    fn rotate(self, (x, y): (i16, i16)) -> (i16, i16) {
+        match self {
+            Orientation::North => (x, y),
+            Orientation::East => (y, -x),
+            Orientation::South => (-x, -y),
+            Orientation::West => (-y, x),
+        }
+    }
+}
+
+impl PieceLocation {
+    /// Returns the `(x, y)` coordinates of the cells occupied by the piece, or `None` if
+    /// the piece is not one of the seven tetrominoes.
+    fn cells(&self) -> Option<Vec<(i16, i16)>> {
+        let cells = piece_cells(self.piece)?;
+        Some(
+            cells
+                .iter()
+                .map(|&c| {
+                    let (x, y) = self.orientation.rotate(c);
+                    (self.x + x, self.y + y)
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the location of `piece` with orientation `orientation` occupying `cells`.
+    fn from_cells(
+        piece: Color,
+        orientation: Orientation,
+        cells: impl IntoIterator<Item = (i16, i16)>,
+    ) -> Option<Self> {
+        // line up the lowest, leftmost cell of the rotated shape with the given cells
+        let key = |&(x, y): &(i16, i16)| (y, x);
+        let rel = piece_cells(piece)?
+            .iter()
+            .map(|&c| orientation.rotate(c))
+            .min_by_key(key)?;
+        let abs = cells.into_iter().min_by_key(key)?;
+        Some(Self {
+            piece,
+            orientation,
+            x: abs.0 - rel.0,
+            y: abs.1 - rel.1,
+        })
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Game state
+
+/// Game state as told by the frontend, updated after each "play" and "new_piece".
+struct Game {
+    matrix: BasicMatrix,
+    hold: Option<Color>,
+    queue: Vec<Color>,
+    chain: Chain,
+    /// Lines cleared since the "start" message, for sprint goals.
+    lines: usize,
+}
+
+impl Game {
+    fn new(start: Start, cols: u16) -> Self {
+        let mut matrix = BasicMatrix::with_cols(cols);
+        for (i, row) in start.board.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                if cell.is_some() {
+                    matrix.set((i as u16, j as u16));
+                }
+            }
+        }
+        let chain = Chain {
+            combo: start.combo.checked_sub(1),
+            b2b: start.back_to_back,
+        };
+        Self {
+            matrix,
+            hold: start.hold,
+            queue: start.queue,
+            chain,
+            lines: 0,
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            hold: self.hold,
+            queue: self.queue.clone(),
+            matrix: self.matrix.clone(),
+            bag: None,
+            refill: None,
+            combo: self.chain.combo,
+            b2b: self.chain.b2b,
+        }
+    }
+
+    /// Returns a stacker for simulating the current piece.
+    fn stacker(&self, rules: &Rc<Ruleset>) -> Stacker {
+        let cells = (0..self.matrix.rows()).flat_map(|i| {
+            (0..self.matrix.cols())
+                .filter(move |&j| self.matrix.get((i, j)))
+                .map(move |j| ((i, j), 'G'))
+        });
+        let hold = self.hold.map(|c| c.as_char());
+        let queue = self.queue.iter().map(|c| c.as_char()).collect::<Vec<_>>();
+        Stacker::with_position(rules.clone(), BSConfig::default(), cells, hold, &queue)
+    }
+
+    /// Returns the move performed by `inputs`, which should end in a hard drop, or
+    /// `None` if the inputs do not lock a tetromino.
+    fn simulate(&self, rules: &Rc<Ruleset>, inputs: &[Input]) -> Option<Move> {
+        let inputs = match inputs.split_last() {
+            Some((Input::HD, inputs)) => inputs,
+            _ => return None,
+        };
+        let mut stacker = self.stacker(rules);
+        stacker.run(inputs.iter().cloned());
+        let (typ, row, col, rot) = stacker.current_piece_ghost()?;
+        let cells = rules
+            .coords(typ, rot)
+            .map(|(i, j)| (col + j as i16, row + i as i16));
+        let location =
+            PieceLocation::from_cells(typ.try_into().ok()?, Orientation::from_rot(rot), cells)?;
+        let spin = stacker.hard_drop().spin.into();
+        Some(Move { location, spin })
+    }
+
+    /// Updates the game state after the frontend played `mov`.
+    fn play(&mut self, mov: &Move) {
+        let loc = &mov.location;
+        // the played piece comes from hold if it isn't the first piece in the queue
+        if !self.queue.is_empty() {
+            let first = self.queue.remove(0);
+            if first != loc.piece && self.hold.replace(first).is_none() && !self.queue.is_empty() {
+                self.queue.remove(0);
+            }
+        }
+
+        let cells = match loc.cells() {
+            Some(cells) => cells,
+            None => {
+                eprintln!(
+                    "warning: ignoring placement of unknown piece {}",
+                    loc.piece.as_char()
+                );
+                return;
+            }
+        };
+        for (x, y) in cells {
+            if x >= 0 && y >= 0 {
+                self.matrix.set((y as u16, x as u16));
+            }
+        }
+
+        let cols = self.matrix.cols();
+        let full_rows = (0..self.matrix.rows())
+            .filter(|&i| (0..cols).all(|j| self.matrix.get((i, j))))
+            .collect::<Vec<_>>();
+        for &i in full_rows.iter().rev() {
+            self.matrix.remove_rows(i..i + 1);
+        }
+        let perfect_clear = (0..cols).all(|j| self.matrix.col_height(j) == 0);
+        let lines = full_rows.len();
+        self.lines += lines;
+        self.chain
+            .lock(lines, 0, mov.spin.into(), lines > 0 && perfect_clear);
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Main loop
+
+/// How long to wait on a running analysis before checking for new messages.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+fn run(args: &Args) -> Result<(), Error> {
+    let rules = Rc::new(load_ruleset(args.ruleset.as_deref())?);
+    let mut ai = AI::new(args.to_ai_config());
+    ai.set_ruleset(&rules)?;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    send(
+        &mut stdout,
+        &BotMessage::Info {
+            name: "Blockfish",
+            version: blockfish::version(),
+            author: "iitalics",
+            features: vec![],
+        },
+    )?;
+
+    // read messages on another thread, so that they are handled while analyzing
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let failed = line.is_err();
+            if tx.send(line).is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let mut game = None;
+    // analysis for the last "suggest" message, until it is answered
+    let mut pending: Option<Analysis> = None;
+    loop {
+        if let Some(analysis) = pending.as_mut() {
+            if analysis.wait_timeout(POLL_INTERVAL) {
+                let analysis = pending.take().unwrap();
+                let moves = match &game {
+                    Some(game) => suggestions(&rules, game, &analysis),
+                    None => vec![],
+                };
+                send(&mut stdout, &BotMessage::Suggestion { moves })?;
+            }
+        }
+        let line = if pending.is_some() {
+            match rx.try_recv() {
+                Ok(line) => line,
+                Err(mpsc::TryRecvError::Empty) => continue,
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match rx.recv() {
+                Ok(line) => line,
+                Err(_) => break,
+            }
+        };
+        let msg = match serde_json::from_str(&line?) {
+            Ok(msg) => msg,
+            Err(err) => {
+                eprintln!("warning: ignoring invalid message: {}", err);
+                continue;
+            }
+        };
+        match msg {
+            FrontendMessage::Rules {} => send(&mut stdout, &BotMessage::Ready {})?,
+            FrontendMessage::Start(start) => {
+                cancel(&mut pending);
+                game = Some(Game::new(start, rules.cols as u16));
+            }
+            FrontendMessage::Suggest {} if pending.is_some() => {
+                eprintln!("warning: ignoring suggest while still analyzing");
+            }
+            FrontendMessage::Suggest {} => {
+                pending = game.as_ref().and_then(|game| analyze(&mut ai, game));
+                if pending.is_none() {
+                    send(&mut stdout, &BotMessage::Suggestion { moves: vec![] })?;
+                }
+            }
+            FrontendMessage::Play { mov } => {
+                // the suggestion would be for a position that is gone
+                cancel(&mut pending);
+                if let Some(game) = game.as_mut() {
+                    game.play(&mov);
+                }
+            }
+            FrontendMessage::NewPiece { piece } => {
+                if let Some(game) = game.as_mut() {
+                    game.queue.push(piece);
+                }
+            }
+            FrontendMessage::Stop {} => {
+                cancel(&mut pending);
+                game = None;
+            }
+            FrontendMessage::Quit {} => break,
+            FrontendMessage::Unknown => {}
+        }
+    }
+    cancel(&mut pending);
+    Ok(())
+}
+
+/// Loads the bundled ruleset named `path`, or else the ruleset from the JSON file at
+/// `path`. Returns the guideline rules if `None`.
+fn load_ruleset(path: Option<&Path>) -> Result<Ruleset, Error> {
+    if let Some(rules) = path.and_then(Path::to_str).and_then(Ruleset::builtin) {
+        return Ok(rules);
+    }
+    match path {
+        Some(path) => Ok(Ruleset::from_json(&std::fs::read_to_string(path)?)?),
+        None => Ok(Ruleset::guideline()),
+    }
+}
+
+/// Stops the pending analysis, if any, without answering it.
+fn cancel(pending: &mut Option<Analysis>) {
+    if let Some(analysis) = pending.take() {
+        analysis.stop();
+    }
+}
+
+fn send(wtr: &mut impl Write, msg: &BotMessage) -> Result<(), Error> {
+    serde_json::to_writer(&mut *wtr, msg)?;
+    wtr.write_all(b"\n")?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Starts analyzing the game state, or returns `None` if there is no piece to play.
+fn analyze(ai: &mut AI, game: &Game) -> Option<Analysis> {
+    if game.queue.is_empty() {
+        return None;
+    }
+    let mut cfg = ai.config();
+    cfg.goal = cfg.goal.remaining(game.lines);
+    Some(ai.analyze_with_config(game.snapshot(), cfg))
+}
+
+/// Returns every possible move found by the finished `analysis`, from best to worst.
+fn suggestions(rules: &Rc<Ruleset>, game: &Game, analysis: &Analysis) -> Vec<Move> {
+    let mut move_ids = analysis.all_moves().collect::<Vec<_>>();
+    move_ids.sort_by(|&m, &n| analysis.cmp(m, n));
+    move_ids
+        .into_iter()
+        .filter_map(|m_id| game.simulate(rules, &analysis.suggestion(m_id, 1).inputs))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PIECES: &str = "IOTLJSZ";
+    const ORIENTATIONS: [Orientation; 4] = [
+        Orientation::North,
+        Orientation::East,
+        Orientation::South,
+        Orientation::West,
+    ];
+
+    fn color(ch: char) -> Color {
+        ch.try_into().unwrap()
+    }
+
+    fn location(piece: char, orientation: Orientation, x: i16, y: i16) -> PieceLocation {
+        PieceLocation {
+            piece: color(piece),
+            orientation,
+            x,
+            y,
+        }
+    }
+
+    fn sorted(mut cells: Vec<(i16, i16)>) -> Vec<(i16, i16)> {
+        cells.sort();
+        cells
+    }
+
+    fn start(json: serde_json::Value) -> Start {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn mov(piece: char, orientation: Orientation, x: i16, y: i16) -> Move {
+        Move {
+            location: location(piece, orientation, x, y),
+            spin: Spin::None,
+        }
+    }
+
+    #[test]
+    fn test_piece_location_cells() {
+        use Orientation::*;
+        let cells =
+            |piece, orientation| sorted(location(piece, orientation, 4, 1).cells().unwrap());
+        assert_eq!(cells('I', North), [(3, 1), (4, 1), (5, 1), (6, 1)]);
+        assert_eq!(cells('I', East), [(4, -1), (4, 0), (4, 1), (4, 2)]);
+        assert_eq!(cells('I', South), [(2, 1), (3, 1), (4, 1), (5, 1)]);
+        assert_eq!(cells('I', West), [(4, 0), (4, 1), (4, 2), (4, 3)]);
+        assert_eq!(cells('O', North), [(4, 1), (4, 2), (5, 1), (5, 2)]);
+        assert_eq!(cells('O', East), [(4, 0), (4, 1), (5, 0), (5, 1)]);
+        assert_eq!(cells('O', South), [(3, 0), (3, 1), (4, 0), (4, 1)]);
+        assert_eq!(cells('O', West), [(3, 1), (3, 2), (4, 1), (4, 2)]);
+        assert_eq!(cells('T', North), [(3, 1), (4, 1), (4, 2), (5, 1)]);
+        assert_eq!(cells('T', East), [(4, 0), (4, 1), (4, 2), (5, 1)]);
+        assert_eq!(cells('T', South), [(3, 1), (4, 0), (4, 1), (5, 1)]);
+        assert_eq!(cells('T', West), [(3, 1), (4, 0), (4, 1), (4, 2)]);
+        assert_eq!(cells('L', North), [(3, 1), (4, 1), (5, 1), (5, 2)]);
+        assert_eq!(cells('L', East), [(4, 0), (4, 1), (4, 2), (5, 0)]);
+        assert_eq!(cells('L', South), [(3, 0), (3, 1), (4, 1), (5, 1)]);
+        assert_eq!(cells('L', West), [(3, 2), (4, 0), (4, 1), (4, 2)]);
+        assert_eq!(cells('J', North), [(3, 1), (3, 2), (4, 1), (5, 1)]);
+        assert_eq!(cells('J', East), [(4, 0), (4, 1), (4, 2), (5, 2)]);
+        assert_eq!(cells('J', South), [(3, 1), (4, 1), (5, 0), (5, 1)]);
+        assert_eq!(cells('J', West), [(3, 0), (4, 0), (4, 1), (4, 2)]);
+        assert_eq!(cells('S', North), [(3, 1), (4, 1), (4, 2), (5, 2)]);
+        assert_eq!(cells('S', East), [(4, 1), (4, 2), (5, 0), (5, 1)]);
+        assert_eq!(cells('S', South), [(3, 0), (4, 0), (4, 1), (5, 1)]);
+        assert_eq!(cells('S', West), [(3, 1), (3, 2), (4, 0), (4, 1)]);
+        assert_eq!(cells('Z', North), [(3, 2), (4, 1), (4, 2), (5, 1)]);
+        assert_eq!(cells('Z', East), [(4, 0), (4, 1), (5, 1), (5, 2)]);
+        assert_eq!(cells('Z', South), [(3, 1), (4, 0), (4, 1), (5, 0)]);
+        assert_eq!(cells('Z', West), [(3, 0), (3, 1), (4, 1), (4, 2)]);
+        assert!(location('P', North, 4, 1).cells().is_none());
+    }
+
+    #[test]
+    fn test_piece_location_from_cells() {
+        for piece in PIECES.chars() {
+            for &orientation in ORIENTATIONS.iter() {
+                let loc = location(piece, orientation, 3, 5);
+                let mut cells = loc.cells().unwrap();
+                // the order of the cells does not matter
+                cells.reverse();
+                let found = PieceLocation::from_cells(loc.piece, orientation, cells).unwrap();
+                assert_eq!((found.x, found.y), (3, 5), "{} {:?}", piece, orientation);
+            }
+        }
+        assert!(PieceLocation::from_cells(color('P'), Orientation::North, vec![(0, 0)]).is_none());
+    }
+
+    #[test]
+    fn test_simulate_spawn_locations() {
+        use Input::*;
+        use Orientation::*;
+        let rules = Rc::new(Ruleset::guideline());
+        // every piece spawns centered at x = 4
+        for piece in PIECES.chars() {
+            let game = Game::new(
+                start(serde_json::json!({
+                    "hold": null,
+                    "queue": [piece.to_string()],
+                    "combo": 0,
+                    "back_to_back": false,
+                    "board": [],
+                })),
+                10,
+            );
+            let mov = game.simulate(&rules, &[HD]).unwrap();
+            assert_eq!(mov.location.orientation, North);
+            assert_eq!(mov.location.x, 4, "{}", piece);
+            let sim = |inputs: &[Input]| {
+                let loc = game.simulate(&rules, inputs).unwrap().location;
+                (loc.orientation, sorted(loc.cells().unwrap()))
+            };
+            // every rotation maps to the cells the stacker locks
+            for (inputs, orientation) in &[
+                (&[CW, HD][..], East),
+                (&[CW, CW, HD], South),
+                (&[CCW, HD], West),
+            ] {
+                let (found, cells) = sim(inputs);
+                assert_eq!(found, *orientation, "{} {:?}", piece, inputs);
+                let mut stacker = game.stacker(&rules);
+                stacker.run(inputs[..inputs.len() - 1].iter().cloned());
+                let (typ, row, col, rot) = stacker.current_piece_ghost().unwrap();
+                let expected = rules
+                    .coords(typ, rot)
+                    .map(|(i, j)| (col + j as i16, row + i as i16))
+                    .collect();
+                assert_eq!(cells, sorted(expected), "{} {:?}", piece, inputs);
+            }
+        }
+    }
+
+    #[test]
+    fn test_game_new() {
+        let game = Game::new(
+            start(serde_json::json!({
+                "hold": "T",
+                "queue": ["I", "O"],
+                "combo": 0,
+                "back_to_back": true,
+                "board": [["G", null, "I"], [null, "Z"]],
+            })),
+            10,
+        );
+        assert_eq!(game.hold, Some(color('T')));
+        assert_eq!(game.queue, [color('I'), color('O')]);
+        assert_eq!(game.chain.combo, None);
+        assert!(game.chain.b2b);
+        assert_eq!(game.matrix.rows(), 2);
+        assert!(game.matrix.get((0, 0)) && !game.matrix.get((0, 1)) && game.matrix.get((0, 2)));
+        assert!(!game.matrix.get((1, 0)) && game.matrix.get((1, 1)));
+
+        // the combo counts the last placement, which the chain does not
+        let combo = |combo| {
+            let json = serde_json::json!({
+                "hold": null,
+                "queue": [],
+                "combo": combo,
+                "back_to_back": false,
+                "board": [],
+            });
+            Game::new(start(json), 10).chain.combo
+        };
+        assert_eq!(combo(1), Some(0));
+        assert_eq!(combo(4), Some(3));
+    }
+
+    #[test]
+    fn test_game_play_hold() {
+        use Orientation::*;
+        let new_game = |hold: Option<&str>, queue: &[&str]| {
+            let json = serde_json::json!({
+                "hold": hold,
+                "queue": queue,
+                "combo": 0,
+                "back_to_back": false,
+                "board": [],
+            });
+            Game::new(start(json), 10)
+        };
+
+        // the first piece in the queue
+        let mut game = new_game(None, &["T", "I", "O"]);
+        game.play(&mov('T', North, 4, 0));
+        assert_eq!(
+            (game.hold, &game.queue[..]),
+            (None, &[color('I'), color('O')][..])
+        );
+
+        // the held piece, swapped with the first piece in the queue
+        let mut game = new_game(Some("L"), &["T", "I"]);
+        game.play(&mov('L', North, 4, 0));
+        assert_eq!(
+            (game.hold, &game.queue[..]),
+            (Some(color('T')), &[color('I')][..])
+        );
+
+        // nothing held yet, so the second piece is played after holding the first
+        let mut game = new_game(None, &["T", "I", "O"]);
+        game.play(&mov('I', North, 4, 0));
+        assert_eq!(
+            (game.hold, &game.queue[..]),
+            (Some(color('T')), &[color('O')][..])
+        );
+        assert!(game.matrix.get((0, 3)) && game.matrix.get((0, 6)));
+    }
+
+    #[test]
+    fn test_game_play_line_clear() {
+        use Orientation::*;
+        let json = serde_json::json!({
+            "hold": null,
+            "queue": ["I", "T"],
+            "combo": 2,
+            "back_to_back": false,
+            "board": [["G", "G", "G", "G", "G", "G"]],
+        });
+        let mut game = Game::new(start(json), 10);
+        game.play(&mov('I', North, 7, 0));
+        assert_eq!(game.lines, 1);
+        assert_eq!(game.matrix.rows(), 0);
+        // the combo continues
+        assert_eq!(game.chain.combo, Some(2));
+        game.play(&mov('T', North, 4, 0));
+        assert_eq!(game.lines, 1);
+        assert_eq!(game.chain.combo, None);
+        assert!(game.queue.is_empty());
+    }
+}
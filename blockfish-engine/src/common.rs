@@ -27,8 +27,8 @@ impl TryFrom<char> for Color {
     type Error = InvalidColorChar;
 
     fn try_from(c: char) -> Result<Self, InvalidColorChar> {
-        if c.is_alphabetic() {
-            // SAFETY: alphabetic chars are not null
+        if c.is_ascii_alphabetic() {
+            // SAFETY: ASCII letters fit in a byte and are not null
             Ok(Color(unsafe { NonZeroU8::new_unchecked(c as u8) }))
         } else {
             Err(InvalidColorChar)
@@ -106,6 +106,15 @@ impl Orientation {
         }
     }
 
+    pub fn flip(self) -> Orientation {
+        match self {
+            Orientation::R0 => Orientation::R2,
+            Orientation::R1 => Orientation::R3,
+            Orientation::R2 => Orientation::R0,
+            Orientation::R3 => Orientation::R1,
+        }
+    }
+
     pub fn from_u8(x: u8) -> Option<Self> {
         match x {
             0 => Some(Orientation::R0),
@@ -131,6 +140,8 @@ pub enum Input {
     Hold,
     SD,
     HD,
+    /// 180 degree rotation, only available in rulesets that have kicks for it.
+    Flip,
 }
 
 #[cfg(test)]
@@ -143,6 +154,8 @@ mod test {
         assert_eq!(Color::try_from('.').ok(), None);
         assert_eq!(Color::try_from(' ').ok(), None);
         assert_eq!(Color::try_from('\0').ok(), None);
+        assert_eq!(Color::try_from('t').ok(), Some(Color::n('t')));
+        assert_eq!(Color::try_from('é').ok(), None);
     }
 
     #[test]
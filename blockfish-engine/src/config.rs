@@ -1,3 +1,4 @@
+use crate::ai::{Goal, ParseGoalError};
 use std::convert::{TryFrom, TryInto};
 use thiserror::Error;
 
@@ -6,6 +7,7 @@ use thiserror::Error;
 pub struct Config {
     pub search_limit: usize,
     pub parameters: Parameters,
+    pub goal: Goal,
 }
 
 impl Default for Config {
@@ -13,6 +15,7 @@ impl Default for Config {
         Self {
             search_limit: 50_000,
             parameters: Parameters::default(),
+            goal: Goal::default(),
         }
     }
 }
@@ -24,6 +27,9 @@ pub struct Parameters {
     pub piece_estimate_factor: i64,
     pub i_dependency_factor: i64,
     pub piece_penalty: i64,
+    /// Reward for each garbage line sent. Zero plays purely for downstack; a positive
+    /// value is for versus play, trading off attack against the health of the stack.
+    pub attack_factor: i64,
 }
 
 impl Default for Parameters {
@@ -33,6 +39,7 @@ impl Default for Parameters {
             piece_estimate_factor: 10,
             i_dependency_factor: 10,
             piece_penalty: 10,
+            attack_factor: 0,
         }
     }
 }
@@ -45,7 +52,9 @@ pub enum ParseConfigError {
     Int(#[from] std::num::ParseIntError),
     #[error("invalid score parameters")]
     Parameters(#[from] ParseParametersError),
-    #[error("expected '<heap-size>' or '<heap-size>/<score-params>'")]
+    #[error("invalid goal")]
+    Goal(#[from] ParseGoalError),
+    #[error("expected '<heap-size>', '<heap-size>/<score-params>' or '<heap-size>/<score-params>/<goal>'")]
     Other,
 }
 
@@ -64,12 +73,17 @@ impl std::str::FromStr for Config {
                 .try_into()?,
             None => Parameters::default(),
         };
+        let goal = match ss.next() {
+            Some(s) => s.parse()?,
+            None => Goal::default(),
+        };
         if ss.next().is_some() {
             Err(ParseConfigError::Other)
         } else {
             Ok(Config {
                 search_limit,
                 parameters,
+                goal,
             })
         }
     }
@@ -79,18 +93,28 @@ impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let search_limit = (self.search_limit + 999) / 1_000;
         write!(f, "{}/", search_limit)?;
-        for (i, &v) in self.parameters.to_array().iter().enumerate() {
+        let values = self.parameters.to_array();
+        // the attack factor is only shown when used, for brevity
+        let n = if self.parameters.attack_factor == 0 {
+            4
+        } else {
+            5
+        };
+        for (i, &v) in values[..n].iter().enumerate() {
             if i > 0 {
                 f.write_str(",")?;
             }
             write!(f, "{}", v)?;
         }
+        if self.goal != Goal::default() {
+            write!(f, "/{}", self.goal)?;
+        }
         Ok(())
     }
 }
 
 #[derive(Debug, Error)]
-#[error("expected exactly 4 values")]
+#[error("expected 4 or 5 values")]
 pub struct ParseParametersError;
 
 impl<'a> TryFrom<&'a [i64]> for Parameters {
@@ -102,6 +126,14 @@ impl<'a> TryFrom<&'a [i64]> for Parameters {
                 piece_estimate_factor: *v2,
                 i_dependency_factor: *v3,
                 piece_penalty: *v4,
+                attack_factor: 0,
+            }),
+            [v1, v2, v3, v4, v5] => Ok(Parameters {
+                row_factor: *v1,
+                piece_estimate_factor: *v2,
+                i_dependency_factor: *v3,
+                piece_penalty: *v4,
+                attack_factor: *v5,
             }),
             _ => Err(ParseParametersError),
         }
@@ -109,12 +141,13 @@ impl<'a> TryFrom<&'a [i64]> for Parameters {
 }
 
 impl Parameters {
-    fn to_array(&self) -> [i64; 4] {
+    fn to_array(&self) -> [i64; 5] {
         [
             self.row_factor,
             self.piece_estimate_factor,
             self.i_dependency_factor,
             self.piece_penalty,
+            self.attack_factor,
         ]
     }
 }
@@ -131,7 +164,8 @@ mod test {
             "15".parse::<Config>().unwrap(),
             Config {
                 search_limit: 15_000,
-                parameters: Parameters::default()
+                parameters: Parameters::default(),
+                goal: Goal::Cheese,
             }
         );
         assert_eq!(
@@ -143,9 +177,16 @@ mod test {
                     piece_estimate_factor: 2,
                     i_dependency_factor: 3,
                     piece_penalty: 4,
+                    attack_factor: 0,
                 },
+                goal: Goal::Cheese,
             }
         );
+        assert_eq!(
+            "15/1,2,3,4/sprint:40".parse::<Config>().unwrap().goal,
+            Goal::Sprint(40)
+        );
+        assert!("15/1,2,3,4/dig".parse::<Config>().is_err());
     }
 
     #[test]
@@ -160,11 +201,23 @@ mod test {
                         piece_estimate_factor: 2,
                         i_dependency_factor: 3,
                         piece_penalty: 4,
+                        attack_factor: 0,
                     },
+                    goal: Goal::Cheese,
                 }
             ),
             "15/1,2,3,4"
         );
+        let cfg = Config {
+            goal: Goal::PerfectClear,
+            ..Config::default()
+        };
+        assert_eq!(cfg.to_string(), "50/5,10,10,10/pc");
+        assert_eq!(cfg.to_string().parse::<Config>().unwrap(), cfg);
+        let mut cfg = Config::default();
+        cfg.parameters.attack_factor = 3;
+        assert_eq!(cfg.to_string(), "50/5,10,10,10,3");
+        assert_eq!(cfg.to_string().parse::<Config>().unwrap(), cfg);
     }
 
     #[test]
@@ -174,8 +227,11 @@ mod test {
             piece_estimate_factor: 2,
             i_dependency_factor: 3,
             piece_penalty: 4,
+            attack_factor: 5,
         };
         let values = params.to_array();
         assert_eq!(Parameters::try_from(&values[..]).unwrap(), params);
+        assert_eq!(Parameters::try_from(&values[..4]).unwrap().attack_factor, 0);
+        assert!(Parameters::try_from(&values[..3]).is_err());
     }
 }
@@ -79,15 +79,22 @@ impl FinesseFinder {
         }
 
         // try moving in every direction (including sonic-drop)
-        let neighbors = [Input::CW, Input::CCW, Input::Left, Input::Right, Input::SD]
-            .iter()
-            .filter_map(|&input| {
-                match input {
-                    Input::SD => Some(sd_tf),
-                    _ => shape.try_input(matrix, tf0, input),
-                }
-                .map(|tf| (tf, input))
-            });
+        let neighbors = [
+            Input::CW,
+            Input::CCW,
+            Input::Left,
+            Input::Right,
+            Input::SD,
+            Input::Flip,
+        ]
+        .iter()
+        .filter_map(|&input| {
+            match input {
+                Input::SD => Some(sd_tf),
+                _ => shape.try_input(matrix, tf0, input),
+            }
+            .map(|tf| (tf, input))
+        });
 
         for (tf, input) in neighbors {
             // push if placement is newly discovered
@@ -0,0 +1,1032 @@
+//! Encoding and decoding of fumen (v115) diagrams.
+//!
+//! Fumen is the diagram format commonly used by the community to share boards and piece
+//! sequences. A fumen string consists of a number of pages, each with a 10 wide, 23 tall
+//! field (plus one row below the floor for rising garbage), an optional piece, and an
+//! optional comment.
+//!
+//! Queues are not part of the fumen format itself; they are only recovered from "quiz"
+//! comments of the form `#Q=[<hold>](<current>)<next>`.
+
+use crate::{
+    ai::{Snapshot, Suggestion},
+    shape::{srs, ShapeTable},
+    BasicMatrix, Color, Input, Orientation,
+};
+use std::{collections::VecDeque, convert::TryFrom};
+use thiserror::Error;
+
+const WIDTH: usize = 10;
+const HEIGHT: usize = 23;
+// the field data also contains the garbage row below the floor
+const BLOCKS: usize = WIDTH * (HEIGHT + 1);
+
+static DATA_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+static COMMENT_CHARS: &[u8] =
+    b" !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+// fumen piece types, indexed by their numeric value ('G' is gray)
+static PIECE_CHARS: &[u8] = b" ILOZTJSG";
+
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum FumenError {
+    #[error("unsupported fumen version (expected 'v115@...')")]
+    Version,
+    #[error("invalid character {0:?} in fumen data")]
+    Char(char),
+    #[error("fumen data ended unexpectedly")]
+    Truncated,
+    #[error("malformed fumen data")]
+    Malformed,
+    #[error("fumen fields must be {} columns wide", WIDTH)]
+    Cols,
+    #[error("matrix is too tall to fit in a fumen field")]
+    Height,
+    #[error("suggestion does not describe a valid sequence of placements")]
+    Suggestion,
+    #[error("piece {0:?} does not fit in the fumen field")]
+    Piece(Piece),
+}
+
+/// A piece on a fumen page.
+///
+/// The location `(x, y)` is the rotation center of the piece, where `y = 0` is the bottom
+/// row of the field. This follows the same conventions as other modern fumen tools.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Piece {
+    /// One of `"ILOZTJS"`.
+    pub kind: char,
+    pub rotation: Orientation,
+    pub x: i16,
+    pub y: i16,
+}
+
+/// A single page of a fumen diagram.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Page {
+    field: Field,
+    /// The piece shown on this page, if any.
+    pub piece: Option<Piece>,
+    /// The comment for this page. Comments are carried over from previous pages if not
+    /// changed.
+    pub comment: Option<String>,
+    /// If `true`, the piece is locked into the field for the following page.
+    pub lock: bool,
+    /// If `true`, the garbage row rises into the field for the following page.
+    pub rise: bool,
+    /// If `true`, the field is mirrored for the following page.
+    pub mirror: bool,
+    quiz: Option<Quiz>,
+}
+
+impl Page {
+    /// Constructs a page showing the matrix, hold and queue of `snapshot`. The queue is
+    /// stored as a quiz comment.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<Self, FumenError> {
+        let field = Field::from_matrix(&snapshot.matrix)?;
+        let quiz = Quiz {
+            hold: snapshot.hold.map(|c| c.as_char()),
+            queue: snapshot.queue.iter().map(|c| c.as_char()).collect(),
+        };
+        Ok(Self {
+            field,
+            piece: None,
+            comment: Some(quiz.to_string()),
+            lock: true,
+            rise: false,
+            mirror: false,
+            quiz: Some(quiz),
+        })
+    }
+
+    /// Returns the contents of the cell at `(x, y)`, if occupied. The result is one of
+    /// `"ILOZTJSG"` where `G` indicates a gray cell. `y = -1` refers to the garbage row
+    /// below the floor.
+    pub fn cell(&self, x: usize, y: i16) -> Option<char> {
+        match self.field.get(x, y) {
+            0 => None,
+            v => Some(PIECE_CHARS[v as usize] as char),
+        }
+    }
+
+    /// Returns a snapshot of this page's field. The hold and queue are recovered from the
+    /// quiz comment, if any; otherwise the queue consists only of this page's piece.
+    pub fn snapshot(&self) -> Snapshot {
+        let color = |c: char| Color::try_from(c).expect("bug: invalid piece");
+        let (hold, queue) = match self.quiz.as_ref() {
+            Some(quiz) => (
+                quiz.hold.map(color),
+                quiz.queue.iter().cloned().map(color).collect(),
+            ),
+            None => (None, self.piece.iter().map(|pc| color(pc.kind)).collect()),
+        };
+        Snapshot {
+            hold,
+            queue,
+            matrix: self.field.to_matrix(),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        }
+    }
+
+    /// Constructs a game state from this page, with the same hold and queue as
+    /// `snapshot()`.
+    pub fn stacker(
+        &self,
+        rules: std::rc::Rc<block_stacker::Ruleset>,
+        cfg: block_stacker::Config,
+    ) -> block_stacker::Stacker {
+        let ss = self.snapshot();
+        let cells = (0..HEIGHT as i16).flat_map(|y| {
+            (0..WIDTH).filter_map(move |x| Some(((y as u16, x as u16), self.cell(x, y)?)))
+        });
+        let hold = ss.hold.map(|c| c.as_char());
+        let queue = ss.queue.iter().map(|c| c.as_char()).collect::<Vec<_>>();
+        block_stacker::Stacker::with_position(rules, cfg, cells, hold, &queue)
+    }
+}
+
+/// Decodes every page of fumen string `s`.
+pub fn decode(s: &str) -> Result<Vec<Page>, FumenError> {
+    let data = match s.find("115@") {
+        Some(idx) if idx > 0 => &s[idx + 4..],
+        _ => return Err(FumenError::Version),
+    };
+    let mut values = data
+        .trim()
+        .chars()
+        .filter(|&ch| ch != '?')
+        .map(|ch| match DATA_CHARS.iter().position(|&d| d as char == ch) {
+            Some(v) => Ok(v as u32),
+            None => Err(FumenError::Char(ch)),
+        })
+        .collect::<Result<VecDeque<_>, _>>()?;
+
+    let mut pages = vec![];
+    let mut prev = Field::empty();
+    let mut repeat = 0;
+    let mut comment = None;
+    let mut quiz = None;
+    while !values.is_empty() {
+        // field
+        let mut field = prev.clone();
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut idx = 0;
+            while idx < BLOCKS {
+                let run = poll(&mut values, 2)? as usize;
+                let diff = (run / BLOCKS) as i16 - 8;
+                let len = run % BLOCKS + 1;
+                if idx == 0 && diff == 0 && len == BLOCKS {
+                    repeat = poll(&mut values, 1)?;
+                }
+                if idx + len > BLOCKS {
+                    return Err(FumenError::Malformed);
+                }
+                for cell in field.cells[idx..idx + len].iter_mut() {
+                    *cell = u8::try_from(*cell as i16 + diff)
+                        .ok()
+                        .filter(|&v| (v as usize) < PIECE_CHARS.len())
+                        .ok_or(FumenError::Malformed)?;
+                }
+                idx += len;
+            }
+        }
+
+        // action
+        let mut action = poll(&mut values, 3)?;
+        let mut take = |n| {
+            let v = action % n;
+            action /= n;
+            v
+        };
+        let kind = take(8) as usize;
+        let rotation = from_fumen_rotation(take(4));
+        let coord = take(BLOCKS as u32) as usize;
+        let rise = take(2) != 0;
+        let mirror = take(2) != 0;
+        let _colorize = take(2) != 0;
+        let has_comment = take(2) != 0;
+        let lock = take(2) == 0;
+
+        let piece = match kind {
+            0 => None,
+            1..=7 => {
+                let kind = PIECE_CHARS[kind] as char;
+                let (dx, dy) = center_correction(kind, rotation);
+                let x = (coord % WIDTH) as i16 + dx;
+                let y = (HEIGHT - 1 - coord / WIDTH) as i16 + dy;
+                Some(Piece {
+                    kind,
+                    rotation,
+                    x,
+                    y,
+                })
+            }
+            _ => return Err(FumenError::Malformed),
+        };
+
+        if has_comment {
+            let text = decode_comment(&mut values)?;
+            quiz = Quiz::parse(&text);
+            comment = Some(text);
+        }
+
+        pages.push(Page {
+            field: field.clone(),
+            piece,
+            comment: comment.clone(),
+            lock,
+            rise,
+            mirror,
+            quiz: quiz.clone(),
+        });
+
+        prev = field;
+        if lock {
+            if let Some(pc) = piece {
+                prev.fill(&pc)?;
+                quiz = quiz.and_then(|q| q.after(pc.kind));
+            }
+            prev.clear_lines();
+            if rise {
+                prev.rise();
+            }
+            if mirror {
+                prev.mirror();
+            }
+        }
+    }
+    Ok(pages)
+}
+
+/// Encodes `pages` into a fumen string. Fails if any page has a piece that does not fit in
+/// the field.
+pub fn encode(pages: &[Page]) -> Result<String, FumenError> {
+    let mut values = vec![];
+    let mut prev = Field::empty();
+    let mut prev_comment = None;
+    // index in `values` of the repeat count of the last unchanged field, if any
+    let mut repeat_idx = None;
+    for (page_idx, page) in pages.iter().enumerate() {
+        // field
+        let unchanged = page.field == prev;
+        match repeat_idx {
+            Some(idx) if unchanged && values[idx] < 63 => values[idx] += 1,
+            _ => {
+                let mut idx = 0;
+                while idx < BLOCKS {
+                    let diff = page.field.cells[idx] as i16 - prev.cells[idx] as i16;
+                    let len = page.field.cells[idx..]
+                        .iter()
+                        .zip(&prev.cells[idx..])
+                        .take_while(|&(&c, &p)| c as i16 - p as i16 == diff)
+                        .count();
+                    push(&mut values, (diff + 8) as u32 * BLOCKS as u32 + len as u32 - 1, 2);
+                    idx += len;
+                }
+                repeat_idx = if unchanged {
+                    values.push(0);
+                    Some(values.len() - 1)
+                } else {
+                    None
+                };
+            }
+        }
+
+        // action
+        let (kind, rotation, coord) = match page.piece {
+            Some(pc) => {
+                if !Field::fits(&pc) {
+                    return Err(FumenError::Piece(pc));
+                }
+                let (dx, dy) = center_correction(pc.kind, pc.rotation);
+                let coord = Field::index((pc.x - dx) as usize, pc.y - dy);
+                let kind = PIECE_CHARS.iter().position(|&c| c as char == pc.kind);
+                (kind.unwrap_or(0), to_fumen_rotation(pc.rotation), coord)
+            }
+            None => (0, 0, 0),
+        };
+        let comment = match (page.comment.as_ref(), prev_comment) {
+            (Some(c), Some(p)) => Some(c).filter(|&c| c != p),
+            (Some(c), None) => Some(c).filter(|c| !c.is_empty()),
+            (None, _) => None,
+        };
+        let mut action = (!page.lock) as u32;
+        for &flag in &[comment.is_some(), page_idx == 0, page.mirror, page.rise] {
+            action = action * 2 + flag as u32;
+        }
+        action = action * BLOCKS as u32 + coord as u32;
+        action = action * 4 + rotation;
+        action = action * 8 + kind as u32;
+        push(&mut values, action, 3);
+        if let Some(text) = comment {
+            encode_comment(&mut values, text);
+        }
+        prev_comment = page.comment.as_ref();
+
+        prev = page.field.clone();
+        if page.lock {
+            if let Some(pc) = page.piece.as_ref() {
+                prev.fill(pc)?;
+            }
+            prev.clear_lines();
+            if page.rise {
+                prev.rise();
+            }
+            if page.mirror {
+                prev.mirror();
+            }
+        }
+    }
+
+    let data = values
+        .into_iter()
+        .map(|v| DATA_CHARS[v as usize] as char)
+        .collect::<Vec<_>>();
+    let mut out = String::from("v115@");
+    // a '?' is inserted after every 47 characters (counting the prefix)
+    for (i, chunk) in std::iter::once(&data[..data.len().min(42)])
+        .chain(data.get(42..).unwrap_or(&[]).chunks(47))
+        .enumerate()
+    {
+        if i > 0 {
+            out.push('?');
+        }
+        out.extend(chunk);
+    }
+    Ok(out)
+}
+
+/// Encodes the placements of suggestion `sugg` as a multi-page fumen, starting from
+/// `snapshot`. Each page shows one placement.
+pub fn encode_suggestion(snapshot: &Snapshot, sugg: &Suggestion) -> Result<String, FumenError> {
+    let shtb = srs();
+    let mut first = Page::from_snapshot(snapshot)?;
+    let mut field = first.field.clone();
+    let mut hold = snapshot.hold;
+    let mut queue = snapshot.queue.iter().cloned().collect::<VecDeque<_>>();
+    let mut pages = vec![];
+    let mut inputs = sugg.inputs.iter();
+    while let Some(piece) = place_next(&shtb, &field, &mut hold, &mut queue, &mut inputs)? {
+        let mut page = match pages.len() {
+            0 => first.clone(),
+            _ => Page {
+                comment: first.comment.clone(),
+                quiz: None,
+                ..first.clone()
+            },
+        };
+        page.field = field.clone();
+        page.piece = Some(piece);
+        field.fill(&piece)?;
+        field.clear_lines();
+        pages.push(page);
+    }
+    if pages.is_empty() {
+        first.comment = None;
+        pages.push(first);
+    }
+    encode(&pages)
+}
+
+/// Simulates `inputs` up to the next hard drop, returning the placed piece. Returns
+/// `None` if there are no inputs left.
+fn place_next<'a>(
+    shtb: &ShapeTable,
+    field: &Field,
+    hold: &mut Option<Color>,
+    queue: &mut VecDeque<Color>,
+    inputs: &mut impl Iterator<Item = &'a Input>,
+) -> Result<Option<Piece>, FumenError> {
+    let matrix = field.to_matrix();
+    let mut color = match queue.pop_front() {
+        Some(c) => c,
+        None => return inputs.next().map_or(Ok(None), |_| Err(FumenError::Suggestion)),
+    };
+    let mut shape = shtb.shape(color).ok_or(FumenError::Suggestion)?;
+    let mut tf = (matrix.rows() as i16, shape.spawn_col(), Orientation::R0);
+    let mut any_inputs = false;
+    for &input in inputs {
+        any_inputs = true;
+        match input {
+            Input::Hold => {
+                let prev = hold.replace(color);
+                color = match prev.or_else(|| queue.pop_front()) {
+                    Some(c) => c,
+                    None => return Err(FumenError::Suggestion),
+                };
+                shape = shtb.shape(color).ok_or(FumenError::Suggestion)?;
+                tf = (matrix.rows() as i16, shape.spawn_col(), Orientation::R0);
+            }
+            Input::SD => tf = shape.sonic_drop(&matrix, tf),
+            Input::HD => {
+                tf = shape.sonic_drop(&matrix, tf);
+                let mut cells = BasicMatrix::with_cols(WIDTH as u16);
+                shape.blit_to(&mut cells, tf);
+                return to_fumen_piece(color.as_char(), tf.2, &cells).map(Some);
+            }
+            _ => tf = shape.try_input(&matrix, tf, input).unwrap_or(tf),
+        }
+    }
+    if any_inputs {
+        Err(FumenError::Suggestion)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Finds the fumen piece of type `kind` occupying exactly the cells in `cells`.
+fn to_fumen_piece(kind: char, r: Orientation, cells: &BasicMatrix) -> Result<Piece, FumenError> {
+    let coords = (0..cells.rows())
+        .flat_map(|i| (0..cells.cols()).map(move |j| (i, j)))
+        .filter(|&c| cells.get(c))
+        .map(|(i, j)| (j as i16, i as i16))
+        .collect::<Vec<_>>();
+    let min_x = coords.iter().map(|c| c.0).min().ok_or(FumenError::Suggestion)?;
+    let min_y = coords.iter().map(|c| c.1).min().ok_or(FumenError::Suggestion)?;
+    // try the corresponding orientation first, since only symmetric pieces can match
+    // other orientations
+    let rotations = std::iter::successors(Some(r), |r| Some(r.cw())).take(4);
+    for rotation in rotations {
+        let offs = piece_offsets(kind, rotation).ok_or(FumenError::Suggestion)?;
+        let x = min_x - offs.iter().map(|o| o.0).min().unwrap();
+        let y = min_y - offs.iter().map(|o| o.1).min().unwrap();
+        let mut blocks = offs.iter().map(|&(dx, dy)| (x + dx, y + dy)).collect::<Vec<_>>();
+        blocks.sort_unstable();
+        let mut expected = coords.clone();
+        expected.sort_unstable();
+        if blocks == expected {
+            return Ok(Piece {
+                kind,
+                rotation,
+                x,
+                y,
+            });
+        }
+    }
+    Err(FumenError::Suggestion)
+}
+
+// Fields
+
+#[derive(Clone, Eq, PartialEq)]
+struct Field {
+    // cells in fumen order: rows from top to bottom, ending with the garbage row
+    cells: [u8; BLOCKS],
+}
+
+impl Field {
+    fn empty() -> Self {
+        Self { cells: [0; BLOCKS] }
+    }
+
+    fn from_matrix(matrix: &BasicMatrix) -> Result<Self, FumenError> {
+        if matrix.cols() as usize != WIDTH {
+            return Err(FumenError::Cols);
+        }
+        if matrix.rows() as usize > HEIGHT {
+            return Err(FumenError::Height);
+        }
+        let mut field = Self::empty();
+        for i in 0..matrix.rows() {
+            for j in 0..matrix.cols() {
+                if matrix.get((i, j)) {
+                    field.set(j as usize, i as i16, 8);
+                }
+            }
+        }
+        Ok(field)
+    }
+
+    fn to_matrix(&self) -> BasicMatrix {
+        let mut matrix = BasicMatrix::with_cols(WIDTH as u16);
+        for y in 0..HEIGHT as i16 {
+            for x in 0..WIDTH {
+                if self.get(x, y) != 0 {
+                    matrix.set((y as u16, x as u16));
+                }
+            }
+        }
+        matrix
+    }
+
+    // `y = -1` is the garbage row, which is stored last
+    fn index(x: usize, y: i16) -> usize {
+        (HEIGHT as i16 - 1 - y) as usize * WIDTH + x
+    }
+
+    fn get(&self, x: usize, y: i16) -> u8 {
+        if x < WIDTH && y >= -1 && y < HEIGHT as i16 {
+            self.cells[Self::index(x, y)]
+        } else {
+            0
+        }
+    }
+
+    fn set(&mut self, x: usize, y: i16, v: u8) {
+        self.cells[Self::index(x, y)] = v;
+    }
+
+    /// Returns `true` if `pc` is a known piece and every block of it is within the field,
+    /// not including the garbage row.
+    fn fits(pc: &Piece) -> bool {
+        let offs = match piece_offsets(pc.kind, pc.rotation) {
+            Some(offs) => offs,
+            None => return false,
+        };
+        offs.iter().all(|&(dx, dy)| {
+            let (x, y) = (pc.x + dx, pc.y + dy);
+            x >= 0 && (x as usize) < WIDTH && y >= 0 && (y as usize) < HEIGHT
+        })
+    }
+
+    /// Fills in the cells of piece `pc`.
+    fn fill(&mut self, pc: &Piece) -> Result<(), FumenError> {
+        if !Field::fits(pc) {
+            return Err(FumenError::Malformed);
+        }
+        let v = PIECE_CHARS.iter().position(|&c| c as char == pc.kind).unwrap() as u8;
+        for &(dx, dy) in piece_offsets(pc.kind, pc.rotation).iter().flatten() {
+            self.set((pc.x + dx) as usize, pc.y + dy, v);
+        }
+        Ok(())
+    }
+
+    /// Removes full rows, not including the garbage row.
+    fn clear_lines(&mut self) {
+        let rows = (0..HEIGHT as i16)
+            .filter(|&y| (0..WIDTH).any(|x| self.get(x, y) == 0))
+            .map(|y| (0..WIDTH).map(|x| self.get(x, y)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        for y in 0..HEIGHT as i16 {
+            for x in 0..WIDTH {
+                let v = rows.get(y as usize).map_or(0, |row| row[x]);
+                self.set(x, y, v);
+            }
+        }
+    }
+
+    /// Shifts the field up by one row, moving the garbage row into the field.
+    fn rise(&mut self) {
+        self.cells.copy_within(WIDTH.., 0);
+        for x in 0..WIDTH {
+            self.set(x, -1, 0);
+        }
+    }
+
+    /// Flips the field horizontally, not including the garbage row.
+    fn mirror(&mut self) {
+        for row in self.cells[..WIDTH * HEIGHT].chunks_mut(WIDTH) {
+            row.reverse();
+        }
+    }
+}
+
+impl std::fmt::Debug for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.to_matrix().fmt(f)
+    }
+}
+
+// Pieces
+
+/// Returns the offsets of each block of piece `kind` from its rotation center, or `None`
+/// if `kind` is not a piece.
+fn piece_offsets(kind: char, r: Orientation) -> Option<[(i16, i16); 4]> {
+    let spawn = match kind {
+        'I' => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        'L' => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        'O' => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        'Z' => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+        'T' => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        'J' => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        'S' => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+        _ => return None,
+    };
+    let mut offs = spawn;
+    for (x, y) in offs.iter_mut() {
+        let (x0, y0) = (*x, *y);
+        let (x1, y1) = match r {
+            Orientation::R0 => (x0, y0),
+            Orientation::R1 => (y0, -x0),
+            Orientation::R2 => (-x0, -y0),
+            Orientation::R3 => (-y0, x0),
+        };
+        *x = x1;
+        *y = y1;
+    }
+    Some(offs)
+}
+
+/// Returns the offset between the location stored in fumen data and the rotation center
+/// of the piece. The stored location uses an older convention for some pieces.
+fn center_correction(kind: char, r: Orientation) -> (i16, i16) {
+    use Orientation::*;
+    match (kind, r) {
+        ('O', R3) => (1, -1),
+        ('O', R2) => (1, 0),
+        ('O', R0) => (0, -1),
+        ('I', R2) => (1, 0),
+        ('I', R3) => (0, -1),
+        ('S', R0) => (0, -1),
+        ('S', R1) => (-1, 0),
+        ('Z', R0) => (0, -1),
+        ('Z', R3) => (1, 0),
+        _ => (0, 0),
+    }
+}
+
+fn from_fumen_rotation(v: u32) -> Orientation {
+    match v {
+        0 => Orientation::R2,
+        1 => Orientation::R1,
+        2 => Orientation::R0,
+        _ => Orientation::R3,
+    }
+}
+
+fn to_fumen_rotation(r: Orientation) -> u32 {
+    match r {
+        Orientation::R2 => 0,
+        Orientation::R1 => 1,
+        Orientation::R0 => 2,
+        Orientation::R3 => 3,
+    }
+}
+
+// Quiz comments
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Quiz {
+    hold: Option<char>,
+    queue: Vec<char>,
+}
+
+impl Quiz {
+    /// Parses quiz comment `#Q=[<hold>](<current>)<next>`.
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.strip_prefix("#Q=[")?;
+        let (hold, s) = s.split_at(s.find(']')?);
+        let s = s.strip_prefix("](")?;
+        let (cur, s) = s.split_at(s.find(')')?);
+        let next = s[1..].split(';').next().unwrap_or("");
+        let is_piece = |c: char| "ILOZTJS".contains(c);
+        let hold = match hold.chars().collect::<Vec<_>>().as_slice() {
+            [] => None,
+            &[c] if is_piece(c) => Some(c),
+            _ => return None,
+        };
+        let queue = cur.chars().chain(next.chars()).collect::<Vec<_>>();
+        if cur.chars().count() > 1 || !queue.iter().all(|&c| is_piece(c)) {
+            return None;
+        }
+        Some(Self { hold, queue })
+    }
+
+    /// Returns the quiz after piece `kind` was used, or `None` if it was not available.
+    fn after(mut self, kind: char) -> Option<Self> {
+        let cur = *self.queue.first()?;
+        if cur != kind {
+            match self.hold {
+                Some(h) if h == kind => {}
+                None if self.queue.get(1) == Some(&kind) => {
+                    self.queue.remove(1);
+                }
+                _ => return None,
+            }
+            self.hold = Some(cur);
+        }
+        self.queue.remove(0);
+        Some(self)
+    }
+}
+
+impl std::fmt::Display for Quiz {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("#Q=[")?;
+        if let Some(h) = self.hold {
+            write!(f, "{}", h)?;
+        }
+        f.write_str("](")?;
+        if let Some(&c) = self.queue.first() {
+            write!(f, "{}", c)?;
+        }
+        f.write_str(")")?;
+        for &c in self.queue.iter().skip(1) {
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+// Low level encoding
+
+fn poll(values: &mut VecDeque<u32>, n: usize) -> Result<u32, FumenError> {
+    let mut v = 0;
+    for i in 0..n {
+        v += values.pop_front().ok_or(FumenError::Truncated)? << (6 * i);
+    }
+    Ok(v)
+}
+
+fn push(values: &mut Vec<u32>, mut v: u32, n: usize) {
+    for _ in 0..n {
+        values.push(v % 64);
+        v /= 64;
+    }
+}
+
+fn decode_comment(values: &mut VecDeque<u32>) -> Result<String, FumenError> {
+    let len = poll(values, 2)? as usize;
+    let mut escaped = String::with_capacity(len);
+    while escaped.len() < len {
+        let mut v = poll(values, 5)?;
+        for _ in 0..4 {
+            if escaped.len() < len {
+                let ch = COMMENT_CHARS
+                    .get((v % 96) as usize)
+                    .ok_or(FumenError::Malformed)?;
+                escaped.push(*ch as char);
+                v /= 96;
+            }
+        }
+    }
+    Ok(unescape(&escaped))
+}
+
+fn encode_comment(values: &mut Vec<u32>, text: &str) {
+    let escaped = escape(text);
+    let escaped = &escaped.as_bytes()[..escaped.len().min(4095)];
+    push(values, escaped.len() as u32, 2);
+    for chunk in escaped.chunks(4) {
+        let v = chunk.iter().rev().fold(0, |v, &ch| {
+            let idx = COMMENT_CHARS.iter().position(|&c| c == ch).unwrap_or(0);
+            v * 96 + idx as u32
+        });
+        push(values, v, 5);
+    }
+}
+
+/// Escapes `s` in the manner of JavaScript's `escape()`.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut buf = [0u16; 2];
+    for ch in s.chars() {
+        if ch.is_ascii_alphanumeric() || "@*_+-./".contains(ch) {
+            out.push(ch);
+        } else {
+            for &u in ch.encode_utf16(&mut buf).iter() {
+                if u < 0x100 {
+                    out.push_str(&format!("%{:02X}", u));
+                } else {
+                    out.push_str(&format!("%u{:04X}", u));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Unescapes `s` in the manner of JavaScript's `unescape()`.
+fn unescape(s: &str) -> String {
+    let hex = |s: &str| u16::from_str_radix(s, 16).ok();
+    let mut units = vec![];
+    let mut buf = [0u16; 2];
+    let mut rest = s;
+    while let Some(ch) = rest.chars().next() {
+        let escaped = match ch {
+            '%' if rest.starts_with("%u") => rest.get(2..6).and_then(hex).map(|u| (u, 6)),
+            '%' => rest.get(1..3).and_then(hex).map(|u| (u, 3)),
+            _ => None,
+        };
+        let len = match escaped {
+            Some((u, len)) => {
+                units.push(u);
+                len
+            }
+            None => {
+                units.extend_from_slice(ch.encode_utf16(&mut buf));
+                ch.len_utf8()
+            }
+        };
+        rest = &rest[len..];
+    }
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::basic_matrix;
+
+    #[test]
+    fn test_decode_empty() {
+        let pages = decode("v115@vhAAgH").unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].piece, None);
+        assert_eq!(pages[0].comment, None);
+        assert!(pages[0].lock);
+        assert_eq!(pages[0].field, Field::empty());
+        assert_eq!(decode("vhAAgH"), Err(FumenError::Version));
+        assert_eq!(decode("v115@vhAA"), Err(FumenError::Truncated));
+        assert_eq!(decode("v115@vh!AgH"), Err(FumenError::Char('!')));
+    }
+
+    #[test]
+    fn test_decode_piece() {
+        let pages = decode("v115@vhAVQJ").unwrap();
+        assert_eq!(
+            pages[0].piece,
+            Some(Piece {
+                kind: 'T',
+                rotation: Orientation::R0,
+                x: 4,
+                y: 0,
+            })
+        );
+        assert_eq!(encode(&pages).unwrap(), "v115@vhAVQJ");
+    }
+
+    #[test]
+    fn test_encode_empty() {
+        let page = Page {
+            comment: None,
+            quiz: None,
+            ..Page::from_snapshot(&"10. - -".parse().unwrap()).unwrap()
+        };
+        assert_eq!(encode(std::slice::from_ref(&page)).unwrap(), "v115@vhAAgH");
+        assert_eq!(
+            encode(&[page.clone(), page.clone()]).unwrap(),
+            "v115@vhBAgHAAA"
+        );
+        assert_eq!(decode("v115@vhBAgHAAA").unwrap(), vec![page.clone(), page]);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let ss: Snapshot = "4x.5x/x8.x/9x. S TIOLJ".parse().unwrap();
+        let fumen = encode(&[Page::from_snapshot(&ss).unwrap()]).unwrap();
+        let pages = decode(&fumen).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].comment.as_deref(), Some("#Q=[S](T)IOLJ"));
+        assert_eq!(pages[0].snapshot(), ss);
+        assert_eq!(pages[0].cell(9, 0), None);
+        assert_eq!(pages[0].cell(0, 0), Some('G'));
+    }
+
+    #[test]
+    fn test_rise_round_trip() {
+        let ss: Snapshot = "x8.x/4x.5x - T".parse().unwrap();
+        let mut first = Page::from_snapshot(&ss).unwrap();
+        for x in 1..WIDTH {
+            first.field.set(x, -1, 8);
+        }
+        first.rise = true;
+        assert_eq!(first.cell(0, -1), None);
+        assert_eq!(first.cell(1, -1), Some('G'));
+        let mut second = first.clone();
+        second.field.rise();
+        second.rise = false;
+        assert_eq!(second.cell(1, 0), Some('G'));
+        assert_eq!(second.cell(1, -1), None);
+        assert_eq!(second.snapshot().matrix.rows(), 3);
+
+        let pages = vec![first, second];
+        let fumen = encode(&pages).unwrap();
+        assert_eq!(decode(&fumen).unwrap(), pages);
+    }
+
+    #[test]
+    fn test_encode_piece_out_of_bounds() {
+        let mut page = Page::from_snapshot(&"10. - -".parse().unwrap()).unwrap();
+        let pc = Piece {
+            kind: 'I',
+            rotation: Orientation::R0,
+            x: 0,
+            y: 0,
+        };
+        page.piece = Some(pc);
+        assert_eq!(encode(&[page.clone()]), Err(FumenError::Piece(pc)));
+        page.piece = Some(Piece { y: -1, x: 4, ..pc });
+        assert!(encode(&[page.clone()]).is_err());
+        page.piece = Some(Piece { x: 1, ..pc });
+        assert!(encode(&[page.clone()]).is_ok());
+
+        // not a piece, even though it fits
+        for &kind in &['G', '?'] {
+            let pc = Piece { kind, x: 1, ..pc };
+            page.piece = Some(pc);
+            assert_eq!(encode(&[page.clone()]), Err(FumenError::Piece(pc)));
+        }
+    }
+
+    #[test]
+    fn test_comment_escape() {
+        assert_eq!(escape("#Q=[](T)"), "%23Q%3D%5B%5D%28T%29");
+        assert_eq!(unescape("%23Q%3D%5B%5D%28T%29"), "#Q=[](T)");
+        assert_eq!(unescape(&escape("ピース 100%")), "ピース 100%");
+    }
+
+    #[test]
+    fn test_quiz() {
+        let q = Quiz::parse("#Q=[](T)IOL;#Q=[](I)OL").unwrap();
+        assert_eq!(q.to_string(), "#Q=[](T)IOL");
+        assert_eq!(q.clone().after('T').unwrap().to_string(), "#Q=[](I)OL");
+        assert_eq!(q.clone().after('I').unwrap().to_string(), "#Q=[T](O)L");
+        assert_eq!(q.clone().after('O'), None);
+        let q = q.after('I').unwrap();
+        assert_eq!(q.clone().after('T').unwrap().to_string(), "#Q=[O](L)");
+        assert_eq!(Quiz::parse("hello"), None);
+        assert_eq!(Quiz::parse("#Q=[](TI)OL"), None);
+    }
+
+    #[test]
+    fn test_piece_offsets() {
+        // T facing right: the nub points towards +x
+        let mut offs = piece_offsets('T', Orientation::R1).unwrap();
+        offs.sort_unstable();
+        assert_eq!(offs, [(0, -1), (0, 0), (0, 1), (1, 0)]);
+    }
+
+    #[test]
+    fn test_srs_orientations_match() {
+        // every orientation in the SRS shape table corresponds to the same orientation in
+        // fumen
+        let shtb = srs();
+        for kind in "ILOZTJS".chars() {
+            let shape = shtb.shape(Color::n(kind)).unwrap();
+            for r in Orientation::iter_all() {
+                let mut cells = BasicMatrix::with_cols(10);
+                shape.blit_to(&mut cells, (2, 3, r));
+                let pc = to_fumen_piece(kind, r, &cells).unwrap();
+                if !"OSZI".contains(kind) {
+                    assert_eq!(pc.rotation, r, "{}", kind);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_suggestion() {
+        use Input::*;
+        let (xx, __) = (true, false);
+        let ss = Snapshot {
+            hold: None,
+            queue: "IOT".chars().map(Color::n).collect(),
+            matrix: basic_matrix![[xx, xx, xx, xx, __, __, __, __, xx, xx]],
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        let sugg = Suggestion {
+            // I flat in the gap, hold O, T on the left
+            inputs: vec![Right, HD, Hold, Left, Left, Left, HD],
+            rating: 0,
+        };
+        let fumen = encode_suggestion(&ss, &sugg).unwrap();
+        let pages = decode(&fumen).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].comment.as_deref(), Some("#Q=[](I)OT"));
+        assert_eq!(pages[0].snapshot(), ss);
+        assert_eq!(
+            pages[0].piece,
+            Some(Piece {
+                kind: 'I',
+                rotation: Orientation::R0,
+                x: 5,
+                y: 0,
+            })
+        );
+        // line was cleared, and the quiz moved on to the next piece
+        assert_eq!(pages[1].snapshot().matrix, BasicMatrix::with_cols(10));
+        assert_eq!(pages[1].snapshot().hold, None);
+        assert_eq!(pages[1].snapshot().queue, [Color::n('O'), Color::n('T')]);
+        assert_eq!(
+            pages[1].piece,
+            Some(Piece {
+                kind: 'T',
+                rotation: Orientation::R0,
+                x: 1,
+                y: 0,
+            })
+        );
+        assert_eq!(
+            encode_suggestion(&ss, &Suggestion { inputs: vec![Left], rating: 0 }),
+            Err(FumenError::Suggestion)
+        );
+    }
+
+    #[test]
+    fn test_long_fumen_splits() {
+        let ss: Snapshot = "x.x.x.x.x./.x.x.x.x.x/x.x.x.x.x./.x.x.x.x.x - T"
+            .parse()
+            .unwrap();
+        let fumen = encode(&[Page::from_snapshot(&ss).unwrap()]).unwrap();
+        assert_eq!(fumen.find('?'), Some(47));
+        assert_eq!(decode(&fumen).unwrap()[0].snapshot(), ss);
+    }
+}
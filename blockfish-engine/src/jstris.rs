@@ -0,0 +1,523 @@
+//! Importing Jstris replays for post-game analysis.
+//!
+//! A saved Jstris replay is a JSON object whose `"c"` field holds the game settings
+//! (including the randomizer seed) and whose `"d"` field holds the base64 encoded list of
+//! inputs. Each input is a big endian 16-bit word, with the action in the low 4 bits and
+//! a timestamp in the remaining bits.
+//!
+//! The replay does not contain the pieces or garbage, so they are regenerated from the
+//! seed using a port of Jstris's randomizer: an Alea generator seeded with the seed
+//! string, drawing pieces uniformly out of a 7-bag, and a second generator with the same
+//! seed drawing cheese holes, never the same column twice in a row. The inputs are then
+//! replayed on a `block_stacker::Stacker` to recover the position before each piece and
+//! the moves actually played. This assumes instant soft drop; a soft drop that only
+//! partially drops the piece is recorded as a sonic drop. 180 degree rotations use the
+//! SRS-X kick table.
+
+use crate::{ai::Snapshot, Color, Input, StackerExt};
+use block_stacker::{Ruleset, Stacker};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+const COLS: u16 = 10;
+const BAG_LEN: usize = 7;
+// Jstris piece ids, in order
+static PIECE_CHARS: &[u8] = b"IOTLJSZ";
+
+#[derive(Debug, Error, Clone, Eq, PartialEq)]
+pub enum JstrisError {
+    #[error("invalid replay JSON: {0}")]
+    Json(String),
+    #[error("invalid base64 in replay data")]
+    Base64,
+    #[error("replay data ended unexpectedly")]
+    Truncated,
+    #[error("unsupported replay action {0}")]
+    Action(u16),
+    #[error("replay continues after the game ended")]
+    GameOver,
+}
+
+/// An input recorded in a replay.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    DasLeft,
+    DasRight,
+    RotateLeft,
+    RotateRight,
+    Rotate180,
+    HardDrop,
+    /// Toggles soft drop on or off.
+    SoftDrop,
+    GravityStep,
+    Hold,
+    GarbageAdd,
+    SolidGarbageAdd,
+    RedbarSet,
+    /// Auto-repeat shift in the direction of the last DAS.
+    ArrMove,
+}
+
+impl TryFrom<u16> for Action {
+    type Error = JstrisError;
+    fn try_from(id: u16) -> Result<Self, JstrisError> {
+        use Action::*;
+        Ok(match id {
+            0 => MoveLeft,
+            1 => MoveRight,
+            2 => DasLeft,
+            3 => DasRight,
+            4 => RotateLeft,
+            5 => RotateRight,
+            6 => Rotate180,
+            7 => HardDrop,
+            8 => SoftDrop,
+            9 => GravityStep,
+            10 => Hold,
+            11 => GarbageAdd,
+            12 => SolidGarbageAdd,
+            13 => RedbarSet,
+            14 => ArrMove,
+            _ => return Err(JstrisError::Action(id)),
+        })
+    }
+}
+
+/// A decoded replay.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Replay {
+    pub seed: String,
+    pub actions: Vec<Action>,
+}
+
+/// A game reconstructed from a replay.
+#[derive(Clone, Debug)]
+pub struct Game {
+    /// Sequence of pieces generated by the randomizer, long enough to cover the game.
+    pub queue: Vec<Color>,
+    /// Sequence of cheese hole columns generated by the randomizer, long enough to cover
+    /// the game.
+    pub holes: Vec<u16>,
+    /// Each piece placed during the game.
+    pub moves: Vec<Move>,
+}
+
+/// A single placement from a reconstructed game.
+#[derive(Clone, Debug)]
+pub struct Move {
+    /// Position when the piece spawned.
+    pub snapshot: Snapshot,
+    /// Inputs played from `snapshot`, ending in a hard drop. Inputs that were obstructed
+    /// are omitted.
+    pub inputs: Vec<Input>,
+}
+
+#[derive(serde::Deserialize)]
+struct RawReplay {
+    c: RawConfig,
+    d: String,
+}
+
+#[derive(serde::Deserialize)]
+struct RawConfig {
+    seed: String,
+}
+
+impl std::str::FromStr for Replay {
+    type Err = JstrisError;
+    fn from_str(s: &str) -> Result<Self, JstrisError> {
+        let raw: RawReplay =
+            serde_json::from_str(s).map_err(|e| JstrisError::Json(e.to_string()))?;
+        let bytes = decode_base64(&raw.d)?;
+        if bytes.len() % 2 != 0 {
+            return Err(JstrisError::Truncated);
+        }
+        let actions = bytes
+            .chunks(2)
+            .map(|w| Action::try_from(u16::from_be_bytes([w[0], w[1]]) & 0xf))
+            .collect::<Result<_, _>>()?;
+        Ok(Replay {
+            seed: raw.c.seed,
+            actions,
+        })
+    }
+}
+
+impl Replay {
+    /// Returns the (infinite) sequence of pieces generated for this replay.
+    pub fn pieces(&self) -> impl Iterator<Item = Color> {
+        let mut rng = Alea::new(&self.seed);
+        let mut bag = vec![];
+        std::iter::repeat_with(move || {
+            if bag.is_empty() {
+                bag.extend_from_slice(PIECE_CHARS);
+            }
+            let idx = (rng.next() * bag.len() as f64) as usize;
+            Color::try_from(bag.remove(idx) as char).unwrap()
+        })
+    }
+
+    /// Returns the (infinite) sequence of cheese hole columns generated for this replay.
+    pub fn holes(&self) -> impl Iterator<Item = u16> {
+        let mut rng = Alea::new(&self.seed);
+        let mut prev = None;
+        std::iter::repeat_with(move || loop {
+            let col = (rng.next() * COLS as f64) as u16;
+            if prev != Some(col) {
+                prev = Some(col);
+                break col;
+            }
+        })
+    }
+
+    /// Replays this game with garbage settings `cfg`, returning the position and inputs
+    /// for each piece placed.
+    pub fn reconstruct(&self, cfg: block_stacker::Config) -> Result<Game, JstrisError> {
+        let drops = self
+            .actions
+            .iter()
+            .filter(|&&a| a == Action::HardDrop)
+            .count();
+        // enough for every placed piece plus hold and previews, and enough garbage for
+        // every placement to clear 4 lines
+        let queue = self.pieces().take(drops + 2 * BAG_LEN).collect::<Vec<_>>();
+        let holes = self
+            .holes()
+            .take(cfg.garbage.max_height + 4 * drops)
+            .collect::<Vec<_>>();
+
+        let pieces = queue.iter().map(|c| c.as_char()).collect::<Vec<_>>();
+        let mut stacker = Stacker::with_sequence(Ruleset::srs_x().into(), cfg, &pieces, &holes);
+        // number of pieces taken from the randomizer so far, not including previews
+        let mut spawned = 1;
+        let mut moves = vec![];
+        let mut snapshot = None;
+        let mut inputs = vec![];
+        let mut das = Input::Left;
+        let mut soft_drop = false;
+
+        for &action in self.actions.iter() {
+            if snapshot.is_none() {
+                let mut ss = stacker.snapshot().ok_or(JstrisError::GameOver)?;
+                let drawn = spawned + stacker.next().len();
+                let bag_end = (drawn + BAG_LEN - 1) / BAG_LEN * BAG_LEN;
+                ss.bag = Some(queue[drawn..bag_end].to_vec());
+                snapshot = Some(ss);
+            }
+            let moved = match action {
+                Action::MoveLeft => try_input(&mut stacker, &mut inputs, Input::Left),
+                Action::MoveRight => try_input(&mut stacker, &mut inputs, Input::Right),
+                Action::DasLeft | Action::DasRight => {
+                    das = if action == Action::DasLeft {
+                        Input::Left
+                    } else {
+                        Input::Right
+                    };
+                    let mut moved = false;
+                    while try_input(&mut stacker, &mut inputs, das) {
+                        moved = true;
+                    }
+                    moved
+                }
+                Action::ArrMove => try_input(&mut stacker, &mut inputs, das),
+                Action::RotateLeft => try_input(&mut stacker, &mut inputs, Input::CCW),
+                Action::RotateRight => try_input(&mut stacker, &mut inputs, Input::CW),
+                Action::Rotate180 => try_input(&mut stacker, &mut inputs, Input::Flip),
+                Action::SoftDrop => {
+                    soft_drop = !soft_drop;
+                    soft_drop
+                }
+                Action::GravityStep => {
+                    stacker.move_down();
+                    let landed = matches!(stacker.current_piece(), Some((_, i, _, _, g)) if i == g);
+                    if landed && inputs.last() != Some(&Input::SD) {
+                        inputs.push(Input::SD);
+                    }
+                    false
+                }
+                Action::Hold => {
+                    // a repeat hold before the piece locks is ignored by the game
+                    let first = stacker.held().is_none();
+                    if stacker.hold() {
+                        if first {
+                            spawned += 1;
+                        }
+                        inputs.clear();
+                        inputs.push(Input::Hold);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                Action::HardDrop => {
+                    stacker.hard_drop();
+                    spawned += 1;
+                    inputs.push(Input::HD);
+                    moves.push(Move {
+                        snapshot: snapshot.take().unwrap(),
+                        inputs: std::mem::take(&mut inputs),
+                    });
+                    false
+                }
+                // only sent in versus games
+                Action::GarbageAdd | Action::SolidGarbageAdd | Action::RedbarSet => false,
+            };
+            if moved && soft_drop {
+                try_input(&mut stacker, &mut inputs, Input::SD);
+            }
+        }
+
+        Ok(Game {
+            queue,
+            holes,
+            moves,
+        })
+    }
+}
+
+/// Simulates `inp` on `stacker`, pushing it onto `inputs` if it was not obstructed.
+fn try_input(stacker: &mut Stacker, inputs: &mut Vec<Input>, inp: Input) -> bool {
+    let ok = match inp {
+        Input::Left => stacker.move_horizontal(-1),
+        Input::Right => stacker.move_horizontal(1),
+        Input::CCW => stacker.rotate(-1),
+        Input::CW => stacker.rotate(1),
+        Input::Flip => stacker.rotate(2),
+        Input::SD => stacker.sonic_drop(),
+        Input::Hold | Input::HD => unreachable!(),
+    };
+    if ok {
+        inputs.push(inp);
+    }
+    ok
+}
+
+fn decode_base64(s: &str) -> Result<Vec<u8>, JstrisError> {
+    let s = s.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for ch in s.bytes() {
+        let v = match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(JstrisError::Base64),
+        };
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Randomizer
+
+/// Johannes Baagøe's Alea generator, as used by Jstris.
+struct Alea {
+    s0: f64,
+    s1: f64,
+    s2: f64,
+    c: f64,
+}
+
+impl Alea {
+    fn new(seed: &str) -> Self {
+        let mut mash = Mash(0xefc8249du32 as f64);
+        let mut s0 = mash.hash(" ");
+        let mut s1 = mash.hash(" ");
+        let mut s2 = mash.hash(" ");
+        for s in [&mut s0, &mut s1, &mut s2].iter_mut() {
+            **s -= mash.hash(seed);
+            if **s < 0.0 {
+                **s += 1.0;
+            }
+        }
+        Self { s0, s1, s2, c: 1.0 }
+    }
+
+    /// Returns a number uniformly distributed in `[0, 1)`.
+    fn next(&mut self) -> f64 {
+        let t = 2091639.0 * self.s0 + self.c * 2.3283064365386963e-10;
+        self.s0 = self.s1;
+        self.s1 = self.s2;
+        self.c = t.trunc();
+        self.s2 = t - self.c;
+        self.s2
+    }
+}
+
+struct Mash(f64);
+
+impl Mash {
+    fn hash(&mut self, data: &str) -> f64 {
+        let n = &mut self.0;
+        for ch in data.encode_utf16() {
+            *n += ch as f64;
+            let mut h = 0.02519603282416938 * *n;
+            *n = to_u32(h);
+            h -= *n;
+            h *= *n;
+            *n = to_u32(h);
+            h -= *n;
+            *n += h * 4294967296.0;
+        }
+        to_u32(*n) * 2.3283064365386963e-10
+    }
+}
+
+/// Equivalent to JavaScript's `x >>> 0` for non-negative `x`.
+fn to_u32(x: f64) -> f64 {
+    x.trunc() % 4294967296.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode_base64(bytes: &[u8]) -> String {
+        let chars = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut s = String::new();
+        for chunk in bytes.chunks(3) {
+            let mut buf = [0u8; 3];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            let n = (buf[0] as u32) << 16 | (buf[1] as u32) << 8 | buf[2] as u32;
+            for i in 0..=chunk.len() {
+                s.push(chars[(n >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        s
+    }
+
+    fn replay_json(seed: &str, actions: &[u16]) -> String {
+        let bytes = actions
+            .iter()
+            .enumerate()
+            .flat_map(|(t, &a)| ((t as u16 * 10) << 4 | a).to_be_bytes().to_vec())
+            .collect::<Vec<_>>();
+        format!(
+            r#"{{"c":{{"v":3.3,"seed":"{}","das":133}},"d":"{}"}}"#,
+            seed,
+            encode_base64(&bytes)
+        )
+    }
+
+    #[test]
+    fn test_alea() {
+        let mut rng = Alea::new("hello.");
+        assert_eq!(rng.next(), 0.4783254903741181);
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(decode_base64("AKAAsQ=="), Ok(vec![0x00, 0xa0, 0x00, 0xb1]));
+        assert_eq!(decode_base64("AK!A"), Err(JstrisError::Base64));
+        assert_eq!(encode_base64(&[0x00, 0xa0, 0x00, 0xb1]), "AKAAsQ");
+    }
+
+    #[test]
+    fn test_parse_replay() {
+        let replay: Replay = replay_json("abc123", &[2, 5, 7, 10, 7]).parse().unwrap();
+        assert_eq!(replay.seed, "abc123");
+        assert_eq!(
+            replay.actions,
+            [
+                Action::DasLeft,
+                Action::RotateRight,
+                Action::HardDrop,
+                Action::Hold,
+                Action::HardDrop,
+            ]
+        );
+        assert_eq!(
+            replay_json("abc123", &[15]).parse::<Replay>().err(),
+            Some(JstrisError::Action(15))
+        );
+        assert!(matches!(
+            "{}".parse::<Replay>().err(),
+            Some(JstrisError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn test_randomizer() {
+        let replay: Replay = replay_json("abc123", &[]).parse().unwrap();
+        let pieces = replay.pieces().take(14).collect::<Vec<_>>();
+        for bag in pieces.chunks(7) {
+            let mut bag = bag.iter().map(|c| c.as_char()).collect::<Vec<_>>();
+            bag.sort_unstable();
+            assert_eq!(bag, ['I', 'J', 'L', 'O', 'S', 'T', 'Z']);
+        }
+        let holes = replay.holes().take(100).collect::<Vec<_>>();
+        assert!(holes.iter().all(|&j| j < COLS));
+        assert!(holes.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn test_reconstruct() {
+        // DAS left, hard drop; hold, DAS right, rotate CW, hard drop
+        let replay: Replay = replay_json("abc123", &[2, 7, 10, 3, 5, 7]).parse().unwrap();
+        let cfg = block_stacker::Config {
+            prng_seed: None,
+            garbage: block_stacker::GarbageConfig {
+                min_height: 2,
+                max_height: 9,
+                total_lines: Some(100),
+            },
+        };
+        let game = replay.reconstruct(cfg).unwrap();
+        assert_eq!(
+            game.queue[..14],
+            replay.pieces().take(14).collect::<Vec<_>>()[..]
+        );
+        assert_eq!(game.moves.len(), 2);
+
+        let (m0, m1) = (&game.moves[0], &game.moves[1]);
+        assert_eq!(m0.snapshot.queue[0], game.queue[0]);
+        assert_eq!(m0.snapshot.hold, None);
+        assert_eq!(m0.snapshot.matrix.rows(), 9);
+        assert_eq!(m0.snapshot.bag.as_ref().unwrap().len(), 1);
+        let (hd, das) = m0.inputs.split_last().unwrap();
+        assert_eq!(*hd, Input::HD);
+        assert!(!das.is_empty() && das.iter().all(|&i| i == Input::Left));
+
+        assert_eq!(m1.snapshot.queue[0], game.queue[1]);
+        assert_eq!(m1.inputs[0], Input::Hold);
+        assert_eq!(*m1.inputs.last().unwrap(), Input::HD);
+        assert!(m1.inputs.contains(&Input::CW));
+
+        // the bottom row of garbage has the first hole
+        let bottom_hole = (0..COLS).find(|&j| !m0.snapshot.matrix.get((0, j)));
+        assert_eq!(bottom_hole, Some(game.holes[8]));
+    }
+
+    #[test]
+    fn test_reconstruct_repeat_hold() {
+        // hold, DAS right, hold again (ignored), rotate CW, hard drop
+        let replay: Replay = replay_json("abc123", &[10, 3, 10, 5, 7]).parse().unwrap();
+        let game = replay.reconstruct(block_stacker::Config::default()).unwrap();
+        assert_eq!(game.moves.len(), 1);
+        let inputs = &game.moves[0].inputs;
+        assert_eq!(inputs[0], Input::Hold);
+        assert_eq!(inputs.iter().filter(|&&i| i == Input::Hold).count(), 1);
+        assert!(inputs.contains(&Input::Right));
+        assert!(inputs.contains(&Input::CW));
+        assert_eq!(game.moves[0].snapshot.hold, None);
+    }
+
+    #[test]
+    fn test_reconstruct_rotate_180() {
+        // rotate 180, hard drop
+        let replay: Replay = replay_json("abc123", &[6, 7]).parse().unwrap();
+        let game = replay.reconstruct(block_stacker::Config::default()).unwrap();
+        assert_eq!(game.moves.len(), 1);
+        assert_eq!(game.moves[0].inputs, [Input::Flip, Input::HD]);
+    }
+}
@@ -6,18 +6,18 @@ mod place;
 mod shape;
 
 pub mod ai;
+pub mod fumen;
+
+pub mod jstris;
 
 pub use common::{Color, Input, Orientation};
 pub use config::{Config, Parameters};
-pub use matrix::BasicMatrix;
+pub use matrix::{BasicMatrix, MAX_COLS};
 
-#[cfg(feature = "block-stacker")]
 mod stacker_util;
 
-#[cfg(feature = "block-stacker")]
 pub use stacker_util::StackerExt;
 
-#[cfg(feature = "gen-shtb")]
 pub use shape::ShapeTable;
 
 #[cfg(feature = "protos")]
@@ -5,6 +5,9 @@ use std::{
 };
 use thiserror::Error;
 
+/// Widest matrix that can be represented by `BasicMatrix`.
+pub const MAX_COLS: u16 = 16;
+
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub struct BasicMatrix {
     /// Number of columns.
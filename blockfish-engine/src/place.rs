@@ -53,6 +53,8 @@ impl<'s> Place<'s> {
 /// algorithm multiple times.
 pub struct PlaceFinder<'s> {
     shtb: &'s ShapeTable,
+    // inputs to try from each placement
+    inputs: &'static [Input],
     matrix: BasicMatrix,
     // next placements to try (depth-first search)
     queue: Vec<Place<'s>>,
@@ -70,6 +72,17 @@ impl<'s> PlaceFinder<'s> {
     pub fn new(shtb: &'s ShapeTable) -> Self {
         PlaceFinder {
             shtb,
+            inputs: if shtb.has_flip() {
+                &[
+                    Input::Left,
+                    Input::Right,
+                    Input::CW,
+                    Input::CCW,
+                    Input::Flip,
+                ]
+            } else {
+                &[Input::Left, Input::Right, Input::CW, Input::CCW]
+            },
             matrix: BasicMatrix::with_cols(0),
             queue: Vec::with_capacity(64),
             places_seen: HashSet::with_capacity(64),
@@ -106,11 +119,8 @@ impl<'s> PlaceFinder<'s> {
 
     fn expand(&mut self, pl: &Place<'s>) {
         let matrix = &self.matrix;
-        self.queue.extend(
-            [Input::Left, Input::Right, Input::CW, Input::CCW]
-                .iter()
-                .filter_map(|&inp| pl.input(matrix, inp)),
-        );
+        self.queue
+            .extend(self.inputs.iter().filter_map(|&inp| pl.input(matrix, inp)));
     }
 
     fn pop(&mut self) -> Option<Place<'s>> {
@@ -152,7 +162,14 @@ impl<'s> Iterator for PlaceFinder<'s> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{ai::Snapshot, basic_matrix, shape::srs, Color, Input::*, Orientation::*};
+    use crate::{
+        ai::Snapshot,
+        basic_matrix,
+        shape::{ars, classic, srs, srs_plus, srs_x},
+        Color,
+        Input::*,
+        Orientation::*,
+    };
 
     /// Returns a `PlaceFinder` iterator already "primed" with node state `st`.
     fn placements<'s>(shtb: &'s ShapeTable, ss: Snapshot) -> PlaceFinder<'s> {
@@ -173,6 +190,10 @@ mod test {
             queue: vec![Color::n('O')],
             hold: Some(Color::n('S')),
             matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         };
         for (idx, pl) in placements(&srs(), snapshot).enumerate() {
             assert_eq!(pl.idx, idx);
@@ -185,6 +206,10 @@ mod test {
             queue: vec![Color::n('O')],
             hold: Some(Color::n('S')),
             matrix: BasicMatrix::with_cols(10),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         };
         let mut o_count = 0;
         let mut s02_count = 0;
@@ -211,6 +236,10 @@ mod test {
             matrix: basic_matrix![[__, __, xx]],
             queue: vec![Color::n('T')],
             hold: Some(Color::n('L')),
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         };
 
         let mut places: Vec<_> = placements(&srs(), snapshot)
@@ -272,13 +301,25 @@ mod test {
         assert!(pl.input(&mat, Left).is_none());
     }
 
-    fn all_places(matrix: BasicMatrix, (color_char, r): (char, Orientation)) -> Vec<(i16, i16)> {
+    fn all_places(matrix: BasicMatrix, piece: (char, Orientation)) -> Vec<(i16, i16)> {
+        all_places_in(&srs(), matrix, piece)
+    }
+
+    fn all_places_in(
+        shtb: &ShapeTable,
+        matrix: BasicMatrix,
+        (color_char, r): (char, Orientation),
+    ) -> Vec<(i16, i16)> {
         let snapshot = Snapshot {
             hold: None,
             queue: vec![Color::n(color_char)],
             matrix,
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         };
-        let mut places: Vec<_> = placements(&srs(), snapshot)
+        let mut places: Vec<_> = placements(shtb, snapshot)
             .filter(|pl| pl.tf.2 == r)
             .map(|pl| (pl.tf.0, pl.tf.1))
             .collect();
@@ -286,11 +327,40 @@ mod test {
         places
     }
 
+    /// Returns the cells covered by every placement of `color_char`, so that placements
+    /// can be compared between shape tables that orient pieces differently.
+    fn all_cells_in(
+        shtb: &ShapeTable,
+        matrix: BasicMatrix,
+        color_char: char,
+    ) -> Vec<Vec<(i16, i16)>> {
+        let snapshot = Snapshot {
+            hold: None,
+            queue: vec![Color::n(color_char)],
+            matrix,
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        };
+        placements(shtb, snapshot)
+            .map(|pl| {
+                let mut cells = pl.shape.cells(pl.tf).collect::<Vec<_>>();
+                cells.sort();
+                cells
+            })
+            .collect()
+    }
+
     fn all_o_places(matrix: BasicMatrix) -> Vec<(i16, i16)> {
         let snapshot = Snapshot {
             hold: None,
             queue: vec![Color::n('O')],
             matrix,
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
         };
         let mut places: Vec<_> = placements(&srs(), snapshot)
             .map(|pl| (pl.tf.0, pl.tf.1))
@@ -485,4 +555,88 @@ mod test {
             r2_places
         );
     }
+
+    #[test]
+    fn test_classic_no_tuck() {
+        let (xx, __) = (true, false);
+        // . . x .      . . x T
+        // . . . .  ->  . . T T
+        // . . . .      . . . T
+        let mat = basic_matrix![[__, __, __, __], [__, __, __, __], [__, __, xx, __]];
+        assert!(all_places(mat.clone(), ('T', R3)).contains(&(0, 2)));
+        // without kicks, the T can't rotate under the overhang
+        let places = all_places_in(&classic(), mat, ('T', R3));
+        assert!(!places.contains(&(0, 2)), "{:?}", places);
+    }
+
+    #[test]
+    fn test_ars_kicks() {
+        let (xx, __) = (true, false);
+        // T x . . .
+        // T T . . .
+        // T . . . .
+        let mat = basic_matrix![
+            [__, __, __, __, __],
+            [__, __, __, __, __],
+            [__, xx, __, __, __],
+        ];
+        let tucked = vec![(0, 0), (1, 0), (1, 1), (2, 0)];
+        assert!(all_cells_in(&srs(), mat.clone(), 'T').contains(&tucked));
+        // ARS only kicks sideways, so the T can't be tucked under the overhang, only rest
+        // on top of it
+        let cells = all_cells_in(&ars(), mat, 'T');
+        assert!(
+            cells.contains(&vec![(2, 0), (3, 0), (3, 1), (4, 0)]),
+            "{:?}",
+            cells
+        );
+        assert!(!cells.contains(&tucked), "{:?}", cells);
+
+        // . . x L .
+        // . . . L x
+        // . . . L L
+        let mat = basic_matrix![
+            [__, __, __, __, __],
+            [__, __, __, __, xx],
+            [__, __, xx, __, __],
+        ];
+        let floor = vec![(0, 3), (0, 4), (1, 3), (2, 3)];
+        assert!(!all_cells_in(&srs(), mat.clone(), 'L').contains(&floor));
+        // rotating from flat side up to the right keeps the L on the floor in ARS
+        let cells = all_cells_in(&ars(), mat, 'L');
+        assert!(cells.contains(&floor), "{:?}", cells);
+    }
+
+    #[test]
+    fn test_srs_plus_flip() {
+        let (xx, __) = (true, false);
+        // . . L .
+        // . . L x
+        // . x L L
+        let mat = basic_matrix![[__, xx, __, __], [__, __, __, xx]];
+        assert!(!all_places(mat.clone(), ('L', R1)).contains(&(0, 1)));
+        let places = all_places_in(&srs_plus(), mat.clone(), ('L', R1));
+        assert!(places.contains(&(0, 1)), "{:?}", places);
+        let places = all_places_in(&srs_x(), mat, ('L', R1));
+        assert!(places.contains(&(0, 1)), "{:?}", places);
+    }
+
+    #[test]
+    fn test_srs_x_flip() {
+        let (xx, __) = (true, false);
+        // . J . . .
+        // x J . . .
+        // J J . . .
+        // x . . x .
+        let mat = basic_matrix![
+            [xx, __, __, xx, __],
+            [__, __, __, __, __],
+            [xx, __, __, __, __],
+        ];
+        // only reachable by kicking two columns to the left while flipping
+        assert!(!all_places(mat.clone(), ('J', R3)).contains(&(1, 0)));
+        assert!(!all_places_in(&srs_plus(), mat.clone(), ('J', R3)).contains(&(1, 0)));
+        let places = all_places_in(&srs_x(), mat, ('J', R3));
+        assert!(places.contains(&(1, 0)), "{:?}", places);
+    }
 }
@@ -17,6 +17,50 @@ impl ShapeTable {
         let data = self.shapes.get(&color)?;
         Some(ShapeRef(&self, data, color))
     }
+
+    /// Returns `true` if any shape in the table can rotate 180 degrees.
+    pub fn has_flip(&self) -> bool {
+        self.kick_tables
+            .iter()
+            .any(|kt| kt.kicks.keys().any(|&Kick(r0, r1)| r1 == r0.flip()))
+    }
+
+    /// Returns every distinct size (number of cells) of the shapes in the table, in sorted
+    /// order.
+    pub fn piece_sizes(&self) -> Vec<usize> {
+        let mut sizes = self
+            .shapes
+            .iter()
+            .map(|(&color, data)| {
+                let shape = ShapeRef(self, data, color);
+                shape.cells((0, 0, Orientation::R0)).count()
+            })
+            .collect::<Vec<_>>();
+        sizes.sort_unstable();
+        sizes.dedup();
+        sizes
+    }
+
+    /// Returns a copy of the precomputed table for the bundled ruleset called `name`, with
+    /// the same names as `block_stacker::Ruleset::builtin`.
+    pub fn builtin(name: &str) -> Option<ShapeTable> {
+        match name {
+            "guideline" | "srs" => Some(srs()),
+            "ars" => Some(ars()),
+            "classic" => Some(classic()),
+            "srs+" | "srs-plus" => Some(srs_plus()),
+            "srs-x" => Some(srs_x()),
+            "pentomino" => Some(pentomino()),
+            _ => None,
+        }
+    }
+
+    /// Returns the color of every shape in the table, in sorted order.
+    pub fn colors(&self) -> Vec<Color> {
+        let mut colors = self.shapes.keys().cloned().collect::<Vec<_>>();
+        colors.sort_unstable();
+        colors
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -76,6 +120,17 @@ impl<'a> ShapeRef<'a> {
         tgt.blit(mat, (i as u16, j as u16));
     }
 
+    /// Returns the `(row, col)` coordinates of each cell of this shape when transformed
+    /// by `tf`.
+    pub fn cells(&self, tf: Transform) -> impl Iterator<Item = (i16, i16)> + 'a {
+        let (i0, j0, mat) = self.transform(tf);
+        (0..mat.rows()).flat_map(move |i| {
+            (0..mat.cols())
+                .filter(move |&j| mat.get((i, j)))
+                .map(move |j| (i0 + i as i16, j0 + j as i16))
+        })
+    }
+
     /// Returns the initial spawn column for this shape.
     pub fn spawn_col(&self) -> i16 {
         self.1.spawn_col
@@ -136,6 +191,7 @@ impl<'a> ShapeRef<'a> {
             Input::Right => (r0, &[(0, 1)]),
             Input::CCW => (r0.ccw(), self.kicks(r0, r0.ccw())),
             Input::CW => (r0.cw(), self.kicks(r0, r0.cw())),
+            Input::Flip => (r0.flip(), self.kicks(r0, r0.flip())),
             _ => {
                 log::error!("invalid input passed to `try_input`");
                 return None;
@@ -230,7 +286,6 @@ impl<'de> Deserialize<'de> for Kick {
 /// Returns `(origin_row, origin_col, mat)` where `mat` is a normalized (no empty rows or
 /// columns on the far sides) view of the coords, with `(origin_row, origin_col)` as the
 /// origin.
-#[cfg(any(test, feature = "gen-shtb"))]
 fn normalize_coords(coords: &[(u16, u16)]) -> (u16, u16, BasicMatrix) {
     assert!(!coords.is_empty());
     let min_row = coords.iter().map(|&(i, _)| i).min().unwrap();
@@ -243,7 +298,6 @@ fn normalize_coords(coords: &[(u16, u16)]) -> (u16, u16, BasicMatrix) {
     (min_row, min_col, mat)
 }
 
-#[cfg(feature = "gen-shtb")]
 impl KickTable {
     /// Generate a kick table for a particular shape given a ruleset.
     fn from_ruleset(rules: &block_stacker::Ruleset, typ: block_stacker::PieceType) -> Self {
@@ -252,6 +306,10 @@ impl KickTable {
             let ori = Orientation::from_u8(r as u8).unwrap();
             kicks.insert(Kick(ori, ori.ccw()), rules.kicks(typ, r, r - 1).collect());
             kicks.insert(Kick(ori, ori.cw()), rules.kicks(typ, r, r + 1).collect());
+            let flips = rules.kicks(typ, r, r + 2).collect::<Vec<_>>();
+            if !flips.is_empty() {
+                kicks.insert(Kick(ori, ori.flip()), flips);
+            }
         }
         Self { kicks }
     }
@@ -265,7 +323,6 @@ impl KickTable {
     }
 }
 
-#[cfg(feature = "gen-shtb")]
 impl ShapeTable {
     /// Generate a shape table from the given game ruleset.
     pub fn from_ruleset(rules: &block_stacker::Ruleset) -> Self {
@@ -287,7 +344,7 @@ impl ShapeTable {
                 coords.clear();
                 coords.extend(rules.coords(typ, r));
                 let (i0, j0, matrix) = normalize_coords(&coords);
-                let mut rs = &mut orientations[r as usize];
+                let rs = &mut orientations[r as usize];
                 rs.offset = (i0 as i16, j0 as i16);
                 // deduplicate identical matrices
                 rs.matrix_index = *matrix_lookup.entry(matrix.clone()).or_insert_with(|| {
@@ -328,12 +385,42 @@ impl ShapeTable {
 }
 
 static SRS_BYTES: &[u8] = include_bytes!("../../support/test/srs-shape-table.json");
+static ARS_BYTES: &[u8] = include_bytes!("../../support/test/ars-shape-table.json");
+static CLASSIC_BYTES: &[u8] = include_bytes!("../../support/test/classic-shape-table.json");
+static SRS_PLUS_BYTES: &[u8] = include_bytes!("../../support/test/srs-plus-shape-table.json");
+static SRS_X_BYTES: &[u8] = include_bytes!("../../support/test/srs-x-shape-table.json");
+static PENTOMINO_BYTES: &[u8] = include_bytes!("../../support/test/pentomino-shape-table.json");
 
 /// Returns a new copy of the SRS shape table.
 pub fn srs() -> ShapeTable {
     serde_json::from_slice(SRS_BYTES).expect("BUG: SRS data is malformed!")
 }
 
+/// Returns a new copy of the ARS shape table.
+pub fn ars() -> ShapeTable {
+    serde_json::from_slice(ARS_BYTES).expect("BUG: ARS data is malformed!")
+}
+
+/// Returns a new copy of the classic (no kicks) shape table.
+pub fn classic() -> ShapeTable {
+    serde_json::from_slice(CLASSIC_BYTES).expect("BUG: classic data is malformed!")
+}
+
+/// Returns a new copy of the SRS+ shape table.
+pub fn srs_plus() -> ShapeTable {
+    serde_json::from_slice(SRS_PLUS_BYTES).expect("BUG: SRS+ data is malformed!")
+}
+
+/// Returns a new copy of the SRS-X shape table.
+pub fn srs_x() -> ShapeTable {
+    serde_json::from_slice(SRS_X_BYTES).expect("BUG: SRS-X data is malformed!")
+}
+
+/// Returns a new copy of the pentomino shape table.
+pub fn pentomino() -> ShapeTable {
+    serde_json::from_slice(PENTOMINO_BYTES).expect("BUG: pentomino data is malformed!")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -487,4 +574,69 @@ mod test {
         assert_eq!(i.normalize((5, 5, R0)), i.normalize((6, 5, R2)));
         assert_eq!(i.normalize((5, 5, R1)), i.normalize((5, 6, R3)));
     }
+
+    #[test]
+    fn test_from_ruleset_matches_tables() {
+        use crate::Orientation::*;
+        use block_stacker::Ruleset;
+        for &name in Ruleset::BUILTIN_NAMES {
+            let table = ShapeTable::builtin(name).unwrap();
+            let rules = Ruleset::builtin(name).unwrap();
+            let shtb = ShapeTable::from_ruleset(&rules);
+            assert_eq!(shtb.colors(), table.colors());
+            for color in table.colors() {
+                let (s0, s1) = (table.shape(color).unwrap(), shtb.shape(color).unwrap());
+                assert_eq!(s0.spawn_col(), s1.spawn_col());
+                for &r in &[R0, R1, R2, R3] {
+                    let tf = (5, 5, r);
+                    assert_eq!(
+                        s0.cells(tf).collect::<Vec<_>>(),
+                        s1.cells(tf).collect::<Vec<_>>()
+                    );
+                    assert_eq!(s0.kicks(r, r.cw()), s1.kicks(r, r.cw()));
+                    assert_eq!(s0.kicks(r, r.ccw()), s1.kicks(r, r.ccw()));
+                    assert_eq!(s0.kicks(r, r.flip()), s1.kicks(r, r.flip()));
+                }
+            }
+        }
+        assert!(ShapeTable::builtin("nes").is_none());
+    }
+
+    #[test]
+    fn test_has_flip() {
+        assert!(!srs().has_flip());
+        assert!(!ars().has_flip());
+        assert!(!classic().has_flip());
+        assert!(srs_plus().has_flip());
+        assert!(srs_x().has_flip());
+    }
+
+    #[test]
+    fn test_piece_sizes() {
+        assert_eq!(srs().piece_sizes(), [4]);
+        assert_eq!(pentomino().piece_sizes(), [5]);
+        assert_eq!(pentomino().colors().len(), 12);
+    }
+
+    #[test]
+    fn test_srs_plus_i_kicks() {
+        use crate::Orientation::*;
+        let mirror = |kicks: &[(i16, i16)]| kicks.iter().map(|&(i, j)| (i, -j)).collect::<Vec<_>>();
+        // rotating left and right kick the same way in opposite directions
+        let srs_plus = srs_plus();
+        let i = srs_plus.shape(Color::n('I')).unwrap();
+        let pairs = [
+            ((R0, R1), (R0, R3)),
+            ((R1, R0), (R3, R0)),
+            ((R1, R2), (R3, R2)),
+            ((R2, R1), (R2, R3)),
+        ];
+        for &((r0, r1), (r2, r3)) in pairs.iter() {
+            assert_eq!(mirror(i.kicks(r0, r1)), i.kicks(r2, r3));
+        }
+        // unlike in SRS
+        let srs = srs();
+        let i = srs.shape(Color::n('I')).unwrap();
+        assert_ne!(mirror(i.kicks(R0, R1)), i.kicks(R0, R3));
+    }
 }
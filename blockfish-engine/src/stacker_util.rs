@@ -1,5 +1,5 @@
 use crate::{
-    ai::Snapshot,
+    ai::{Refill, Snapshot},
     common::{Color, Input},
     matrix::BasicMatrix,
 };
@@ -10,6 +10,11 @@ pub trait StackerExt {
     /// be invalid (e.g., there is no current piece).
     fn snapshot(&self) -> Option<Snapshot>;
 
+    /// Returns the garbage refill rules of the game. These are not included in
+    /// `snapshot()`; set `Snapshot::refill` to this to have the engine simulate garbage
+    /// being added during its search.
+    fn garbage_refill(&self) -> Refill;
+
     /// Simulates input `inp` on the game state.
     fn input(&mut self, inp: Input);
 
@@ -35,13 +40,28 @@ impl StackerExt for block_stacker::Stacker {
         for (coord, _) in self.matrix() {
             matrix.set(coord);
         }
+        let chain = self.chain();
         Some(Snapshot {
             queue,
             hold,
             matrix,
+            bag: None,
+            refill: None,
+            combo: chain.combo,
+            b2b: chain.b2b,
         })
     }
 
+    fn garbage_refill(&self) -> Refill {
+        let garbage = &self.config().garbage;
+        Refill {
+            min_height: garbage.min_height as u16,
+            max_height: garbage.max_height as u16,
+            lines_remaining: self.garbage_remaining(),
+            garbage_rows: self.garbage_rows() as u16,
+        }
+    }
+
     fn input(&mut self, inp: Input) {
         match inp {
             Input::Left => {
@@ -56,6 +76,9 @@ impl StackerExt for block_stacker::Stacker {
             Input::CW => {
                 self.rotate(1);
             }
+            Input::Flip => {
+                self.rotate(2);
+            }
             Input::Hold => {
                 self.hold();
             }
@@ -68,3 +91,31 @@ impl StackerExt for block_stacker::Stacker {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use block_stacker::{Config, Ruleset, Stacker};
+
+    #[test]
+    fn test_snapshot_refill_opt_in() {
+        let mut cfg = Config::default();
+        cfg.prng_seed = Some(1);
+        cfg.garbage.min_height = 2;
+        cfg.garbage.max_height = 9;
+        cfg.garbage.total_lines = Some(100);
+        let stacker = Stacker::new(Ruleset::guideline().into(), cfg);
+        let ss = stacker.snapshot().unwrap();
+        assert_eq!(ss.refill, None);
+        assert_eq!(ss.matrix.rows(), 9);
+        assert_eq!(
+            stacker.garbage_refill(),
+            Refill {
+                min_height: 2,
+                max_height: 9,
+                lines_remaining: Some(91),
+                garbage_rows: 9,
+            }
+        );
+    }
+}
@@ -15,9 +15,13 @@ class AI extends EventEmitter {
         Object.assign(this, {
             ipc: makeIPC(blockfish),
             version: null,
+            protocolVersion: null,
+            capabilities: null,
             _init: false,
             _id: 0,
             _analysisCallbacks: {},
+            _progressCallbacks: {},
+            _batches: {},
         });
         this.ipc.on('recv', this._onRecv.bind(this));
         this.ipc.on('error', e => this.emit('error', e));
@@ -38,6 +42,10 @@ class AI extends EventEmitter {
      * @param {Object} [options] - Analysis options.
      * @param {number} [options.nodeLimit] - Max number of nodes to discover before cutting off search.
      * @param {number} [options.suggestionLimit] - Max number of suggestions to return.
+     * @param {number} [options.progressInterval] - Minimum time between progress updates, in seconds.
+     * @param {AI~Parameters} [options.parameters] - Evaluation scoring parameters.
+     * @param {AI~analyzeCallback} [options.onProgress] - Called with the best suggestions so far
+     * while the analysis is running, if `progressInterval` is set.
      * @param {AI~analyzeCallback} callback - Called when the analysis completes.
      */
     analyze(snapshot, options, callback) {
@@ -66,23 +74,161 @@ class AI extends EventEmitter {
         let id = ++this._id;
         let analyzeCallback = () => {
             this._analysisCallbacks[id] = callback;
+            if (options !== null && options.onProgress !== undefined) {
+                this._progressCallbacks[id] = options.onProgress;
+            }
         };
 
-        let setConfigCallback = () => {
-            let ana = new protos.Request.Analyze;
-            ana.setId(id);
-            ana.setSnapshot(toSnapshotProto(snapshot));
-            let analyzeReq = new protos.Request;
-            analyzeReq.setAnalyze(ana);
-            this.ipc.send(analyzeReq, analyzeCallback);
+        let ana = new protos.Request.Analyze;
+        ana.setId(id);
+        ana.setSnapshot(toSnapshotProto(snapshot));
+        if (options !== null) {
+            // config for just this analysis, so concurrent analyses don't interfere
+            ana.setConfig(toConfigProto(options));
+        }
+        let analyzeReq = new protos.Request;
+        analyzeReq.setAnalyze(ana);
+        this.ipc.send(analyzeReq, analyzeCallback);
+    }
+
+    /**
+     * Analyzes every snapshot in a list. When the whole batch finishes, the callback is
+     * called with the result of each analysis.
+     * @param {Object[]} snapshots - Snapshots to analyze, same as for `analyze`.
+     * @param {Object} [options] - Analysis options, same as for `analyze`.
+     * @param {number} [options.maxRunning] - Max number of analyses running at once.
+     * @param {AI~analyzeCallback} [options.onResult] - Called with the index of each
+     * snapshot and its analysis, as soon as it finishes.
+     * @param {AI~analyzeBatchCallback} callback - Called when every analysis completes.
+     */
+    analyzeBatch(snapshots, options, callback) {
+        if (options instanceof Function) {
+            callback = options;
+            options = {};
+        }
+        options = options || {};
+        if (!this._init) {
+            this.on('init', () => this.analyzeBatch(snapshots, options, callback));
+            return;
+        }
+        let id = ++this._id;
+        let batch = {
+            firstId: id + 1,
+            count: snapshots.length,
+            analyses: [],
+            onResult: options.onResult,
+            callback,
         };
+        this._id += snapshots.length;
 
-        if (options === null) {
-            setConfigCallback();
-        } else {
-            let setConfigReq = new protos.Request;
-            setConfigReq.setSetConfig(toConfigProto(options));
-            this.ipc.send(setConfigReq, setConfigCallback);
+        let req = new protos.Request.AnalyzeBatch;
+        req.setId(id);
+        req.setItemsList(snapshots.map((snapshot, i) => {
+            let item = new protos.Request.AnalyzeBatch.Item;
+            item.setId(batch.firstId + i);
+            item.setSnapshot(toSnapshotProto(snapshot));
+            return item;
+        }));
+        req.setConfig(toConfigProto(options));
+        if (options.maxRunning !== undefined) {
+            req.setMaxRunning(options.maxRunning);
+        }
+        let batchReq = new protos.Request;
+        batchReq.setAnalyzeBatch(req);
+        this.ipc.send(batchReq, () => {
+            this._batches[id] = batch;
+            snapshots.forEach((_, i) => {
+                this._analysisCallbacks[batch.firstId + i] = analysis => {
+                    batch.analyses[i] = analysis;
+                    if (batch.onResult) {
+                        batch.onResult(i, analysis);
+                    }
+                };
+            });
+        });
+    }
+
+    /**
+     * Callback when a batch of analyses completes.
+     *
+     * @callback AI~analyzeBatchCallback
+     * @param {Object} batch - Describes the batch results.
+     * @param {Object[]} batch.analyses - Results of each analysis, in the same order as the
+     * snapshots, as would be passed to {@link AI~analyzeCallback}.
+     * @param {AI~Statistics} batch.statistics - Total statistics of the batch.
+     */
+
+    /**
+     * Evaluates the matrix of a snapshot without searching, calling the callback with
+     * the evaluation.
+     * @param {Object} snapshot - Snapshot to evaluate, same as for `analyze`.
+     * @param {AI~Parameters} [parameters] - Parameters used to compute the score.
+     * @param {AI~staticEvalCallback} callback - Called with the evaluation.
+     */
+    staticEval(snapshot, parameters, callback) {
+        if (parameters instanceof Function) {
+            callback = parameters;
+            parameters = null;
+        }
+        if (!this._init) {
+            this.on('init', () => this.staticEval(snapshot, parameters, callback));
+            return;
+        }
+        let id = ++this._id;
+        let ev = new protos.Request.StaticEval;
+        ev.setId(id);
+        ev.setSnapshot(toSnapshotProto(snapshot));
+        if (parameters) {
+            ev.setParameters(toParametersProto(parameters));
+        }
+        let req = new protos.Request;
+        req.setStaticEval(ev);
+        this.ipc.send(req, () => {
+            this._analysisCallbacks[id] = callback;
+        });
+    }
+
+    /**
+     * Callback when a static evaluation completes.
+     *
+     * @callback AI~staticEvalCallback
+     * @param {Object} eval - The evaluation.
+     * @param {number} eval.rows - Height of the stack.
+     * @param {number} eval.pieceEstimate - Estimated number of pieces to dig out.
+     * @param {number} eval.iDependencies - Number of I dependencies.
+     * @param {number} eval.attack - Attack sent, always 0 for a static evaluation.
+     * @param {number} eval.score - Score of the evaluation. Lower is better.
+     */
+
+    _negotiate(greeting) {
+        let max = greeting.getMaxProtocolVersion();
+        if (max === 0) {
+            // old engine, nothing to negotiate
+            this._finishInit();
+            return;
+        }
+        this.capabilities = fromCapabilitiesProto(greeting.getCapabilities());
+        let min = greeting.getMinProtocolVersion();
+        let version = Math.min(AI.MAX_PROTOCOL_VERSION, max);
+        if (version < Math.max(AI.MIN_PROTOCOL_VERSION, min)) {
+            let e = new Error(`engine speaks protocol versions ${min} to ${max}, expected `
+                              + `${AI.MIN_PROTOCOL_VERSION} to ${AI.MAX_PROTOCOL_VERSION}`);
+            e.code = 'UNSUPPORTED_PROTOCOL';
+            e.id = 0;
+            this.emit('error', e);
+            return;
+        }
+        let hello = new protos.Request.Hello;
+        hello.setProtocolVersion(version);
+        let req = new protos.Request;
+        req.setHello(hello);
+        this.ipc.send(req);
+    }
+
+    _finishInit() {
+        if (!this._init) {
+            this._init = true;
+            this.emit('init');
         }
     }
 
@@ -91,16 +237,62 @@ class AI extends EventEmitter {
             let greeting = res.getGreeting();
             this.version = greeting.getVersion();
             if (!this._init) {
-                this._init = true;
-                this.emit('init');
+                this._negotiate(greeting);
             }
             this.emit('greet', greeting.getMotd());
+        } else if (res.hasHello()) {
+            this.protocolVersion = res.getHello().getProtocolVersion();
+            this._finishInit();
         } else if (res.hasFinished()) {
             let analysis = res.getFinished();
             let id = analysis.getId();
             let cb = this._analysisCallbacks[id];
             this._analysisCallbacks[id] = null;
+            this._progressCallbacks[id] = null;
             cb(fromAnalysisProto(analysis));
+        } else if (res.hasStaticEval()) {
+            let ev = res.getStaticEval();
+            let id = ev.getId();
+            let cb = this._analysisCallbacks[id];
+            this._analysisCallbacks[id] = null;
+            cb({
+                rows: ev.getRows(),
+                pieceEstimate: ev.getPieceEstimate(),
+                iDependencies: ev.getIDependencies(),
+                attack: ev.getAttack(),
+                score: ev.getScore(),
+            });
+        } else if (res.hasBatchFinished()) {
+            let fin = res.getBatchFinished();
+            let batch = this._batches[fin.getId()];
+            this._batches[fin.getId()] = null;
+            batch.callback({
+                analyses: batch.analyses,
+                statistics: fromStatsProto(fin.getStats()),
+            });
+        } else if (res.hasError()) {
+            let error = res.getError();
+            let id = error.getId();
+            // the rejected request won't get a response, so forget its callbacks
+            let batch = this._batches[id];
+            if (batch) {
+                this._batches[id] = null;
+                for (let i = 0; i < batch.count; i++) {
+                    this._analysisCallbacks[batch.firstId + i] = null;
+                }
+            }
+            this._analysisCallbacks[id] = null;
+            this._progressCallbacks[id] = null;
+            let e = new Error(error.getMessage());
+            e.code = errorCodeName(error.getCode());
+            e.id = id;
+            this.emit('error', e);
+        } else if (res.hasProgress()) {
+            let analysis = res.getProgress();
+            let cb = this._progressCallbacks[analysis.getId()];
+            if (cb) {
+                cb(fromAnalysisProto(analysis));
+            }
         }
     }
 }
@@ -112,6 +304,16 @@ class AI extends EventEmitter {
  */
 AI.DEFAULT_BLOCKFISH_PATH = 'blockfish';
 
+/**
+ * Range of protocol versions this module can speak. Once connected, the version picked is
+ * stored in `protocolVersion` and the engine's features in `capabilities`, or both are
+ * `null` if the engine predates protocol versions.
+ * @constant
+ * @type {number}
+ */
+AI.MIN_PROTOCOL_VERSION = 1;
+AI.MAX_PROTOCOL_VERSION = 1;
+
 /**
  * Initialization event, fired once after the {@link AI} is created.
  *
@@ -119,12 +321,20 @@ AI.DEFAULT_BLOCKFISH_PATH = 'blockfish';
  */
 
 /**
- * Fired if an error occurs with the blockfish process.
+ * Fired if an error occurs with the blockfish process, or if it rejects a request. Errors
+ * for rejected requests have a `code` property naming the reason (e.g.
+ * `'INVALID_SNAPSHOT'`) and an `id` property with the id of the request, whose callback
+ * will not be called.
  *
  * @event AI#error
  * @type {Error}
  */
 
+function errorCodeName(code) {
+    let codes = protos.Response.Error.Code;
+    return Object.keys(codes).find(name => codes[name] === code);
+}
+
 function makeIPC(arg) {
     if (arg === undefined) {
         arg = AI.DEFAULT_BLOCKFISH_PATH;
@@ -144,9 +354,44 @@ function toConfigProto(options) {
     if (options.suggestionLimit !== undefined) {
         proto.setMaxResults(options.suggestionLimit);
     }
+    if (options.progressInterval !== undefined) {
+        proto.setProgressIntervalMillis(Math.round(options.progressInterval * 1000));
+    }
+    if (options.parameters !== undefined) {
+        proto.setParameters(toParametersProto(options.parameters));
+    }
     return proto;
 }
 
+function toParametersProto(arg) {
+    /**
+     * Evaluation scoring parameters. Missing parameters use the engine's defaults.
+     *
+     * @typedef {Object} AI~Parameters
+     * @property {number} [rowFactor]
+     * @property {number} [pieceEstimateFactor]
+     * @property {number} [iDependencyFactor]
+     * @property {number} [piecePenalty]
+     * @property {number} [attackFactor]
+     */
+    let params = Object.assign({}, DEFAULT_PARAMETERS, arg);
+    let proto = new protos.Parameters;
+    proto.setRowFactor(params.rowFactor);
+    proto.setPieceEstimateFactor(params.pieceEstimateFactor);
+    proto.setIDependencyFactor(params.iDependencyFactor);
+    proto.setPiecePenalty(params.piecePenalty);
+    proto.setAttackFactor(params.attackFactor);
+    return proto;
+}
+
+const DEFAULT_PARAMETERS = {
+    rowFactor: 5,
+    pieceEstimateFactor: 10,
+    iDependencyFactor: 10,
+    piecePenalty: 10,
+    attackFactor: 0,
+};
+
 function toSnapshotProto(arg) {
     let ss = new protos.Snapshot;
     ss.setHold(arg.hold || "");
@@ -162,6 +407,18 @@ function fromAnalysisProto(arg) {
     };
 }
 
+function fromCapabilitiesProto(caps) {
+    return {
+        rulesets: caps.getRulesetsList(),
+        goals: caps.getGoalsList(),
+        progress: caps.getProgress(),
+        cancellation: caps.getCancellation(),
+        batches: caps.getBatches(),
+        perfectClears: caps.getPerfectClears(),
+        maxNodeLimit: caps.getMaxNodeLimit(),
+    };
+}
+
 function fromStatsProto(arg) {
     /**
      * Statistics on an analysis.
@@ -185,7 +442,7 @@ function fromSuggestionProto(arg) {
      * @typedef {Object} AI~Suggestion
      * @property {number} rating - Suggestion's rating. Lower is better.
      * @property {string[]} inputs - List of inputs to press. Each string is one of
-     * 'left', 'right', 'cw', 'ccw', 'hold', 'sd', or 'hd'.
+     * 'left', 'right', 'cw', 'ccw', 'hold', 'sd', 'hd', or 'flip'.
      */
     return {
         rating: arg.getRating(),
@@ -202,6 +459,7 @@ function fromInputProto(arg) {
     case protos.Input.HOLD: return 'hold';
     case protos.Input.SD: return 'sd';
     case protos.Input.HD: return 'hd';
+    case protos.Input.FLIP: return 'flip';
     }
 }
 
@@ -0,0 +1,552 @@
+//! Python extension module `blockfish_native`, which runs the engine in-process instead
+//! of spawning the `blockfish` service.
+//!
+//! Analyses run on background threads. `Analysis.poll()` checks on one without blocking,
+//! and `Analysis.wait()` blocks without holding the GIL, so it can also be awaited from
+//! asyncio with `loop.run_in_executor(None, analysis.wait)`. The other methods of the
+//! analysis may still be called while it is being waited on, e.g. to stop it.
+
+// triggered by the code that `#[pymethods]` generates for methods returning `PyResult`
+#![allow(clippy::useless_conversion)]
+
+use blockfish::{ai, Color, Config, Input, StackerExt};
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::{
+    convert::TryFrom,
+    rc::Rc,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Names of inputs, as in `blockfish.ai.INPUT_NAMES`.
+const INPUT_NAMES: &[(Input, &str)] = &[
+    (Input::Left, "left"),
+    (Input::Right, "right"),
+    (Input::CW, "cw"),
+    (Input::CCW, "ccw"),
+    (Input::Hold, "hold"),
+    (Input::SD, "sd"),
+    (Input::HD, "hd"),
+    (Input::Flip, "flip"),
+];
+
+fn input_name(input: Input) -> &'static str {
+    let (_, name) = INPUT_NAMES.iter().find(|&&(i, _)| i == input).unwrap();
+    name
+}
+
+fn parse_input(name: &str) -> PyResult<Input> {
+    match INPUT_NAMES.iter().find(|&&(_, n)| n == name) {
+        Some(&(input, _)) => Ok(input),
+        None => Err(PyValueError::new_err(format!("invalid input {:?}", name))),
+    }
+}
+
+fn parse_piece(ch: char) -> PyResult<Color> {
+    Color::try_from(ch).map_err(|_| PyValueError::new_err(format!("invalid piece {:?}", ch)))
+}
+
+fn parse_ruleset(name: &str) -> PyResult<block_stacker::Ruleset> {
+    block_stacker::Ruleset::builtin(name)
+        .ok_or_else(|| PyValueError::new_err(format!("unknown ruleset {:?}", name)))
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Snapshots
+
+/// A game state to analyze. `matrix` lists the rows of the matrix from bottom to top,
+/// where letters are filled cells and spaces and dots are empty cells, as in the service.
+#[pyclass(module = "blockfish_native", name = "Snapshot")]
+#[derive(Clone)]
+struct PySnapshot(ai::Snapshot);
+
+#[pymethods]
+impl PySnapshot {
+    #[new]
+    #[pyo3(signature = (queue, hold = None, matrix = Vec::new(), cols = 10))]
+    fn new(queue: &str, hold: Option<&str>, matrix: Vec<String>, cols: u16) -> PyResult<Self> {
+        let hold = match hold.map(|s| s.chars().collect::<Vec<_>>()).as_deref() {
+            None | Some([]) => None,
+            Some(&[ch]) => Some(parse_piece(ch)?),
+            Some(_) => return Err(PyValueError::new_err("hold must be a single piece")),
+        };
+        if cols == 0 || cols > blockfish::MAX_COLS {
+            let msg = format!("cols must be between 1 and {}", blockfish::MAX_COLS);
+            return Err(PyValueError::new_err(msg));
+        }
+        let mut mat = blockfish::BasicMatrix::with_cols(cols);
+        for (i, row) in matrix.iter().enumerate() {
+            if row.chars().count() != cols as usize {
+                let msg = format!("row {} is not {} cells wide", i, cols);
+                return Err(PyValueError::new_err(msg));
+            }
+            for (j, ch) in row.chars().enumerate() {
+                match ch {
+                    ' ' | '.' => {}
+                    _ if Color::try_from(ch).is_ok() => mat.set((i as u16, j as u16)),
+                    _ => {
+                        let msg = format!("invalid cell {:?} in row {}", ch, i);
+                        return Err(PyValueError::new_err(msg));
+                    }
+                }
+            }
+        }
+        Ok(Self(ai::Snapshot {
+            hold,
+            queue: queue.chars().map(parse_piece).collect::<PyResult<_>>()?,
+            matrix: mat,
+            bag: None,
+            refill: None,
+            combo: None,
+            b2b: false,
+        }))
+    }
+
+    /// Parses a snapshot written in Blockfish's text notation, e.g. `"4x.5x/x9. - TIOLJ"`.
+    #[staticmethod]
+    fn parse(notation: &str) -> PyResult<Self> {
+        let ss = notation
+            .parse()
+            .map_err(|e: ai::ParseSnapshotError| PyValueError::new_err(e.to_string()))?;
+        Ok(Self(ss))
+    }
+
+    #[getter]
+    fn queue(&self) -> String {
+        self.0.queue.iter().map(Color::as_char).collect()
+    }
+
+    #[getter]
+    fn hold(&self) -> Option<String> {
+        self.0.hold.map(|c| c.as_char().to_string())
+    }
+
+    #[getter]
+    fn matrix(&self) -> Vec<String> {
+        let mat = &self.0.matrix;
+        let row = |i| (0..mat.cols()).map(move |j| if mat.get((i, j)) { 'G' } else { ' ' });
+        (0..mat.rows()).map(|i| row(i).collect()).collect()
+    }
+
+    #[getter]
+    fn cols(&self) -> u16 {
+        self.0.matrix.cols()
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Snapshot.parse({:?})", self.0.to_string())
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Results
+
+/// A suggested sequence of inputs. Lower ratings are better.
+#[pyclass(module = "blockfish_native", name = "Suggestion", get_all)]
+#[derive(Clone)]
+struct PySuggestion {
+    rating: i64,
+    inputs: Vec<&'static str>,
+}
+
+#[pymethods]
+impl PySuggestion {
+    fn __repr__(&self) -> String {
+        let inputs = self.inputs.iter().map(|i| format!("'{}'", i));
+        let inputs = inputs.collect::<Vec<_>>().join(", ");
+        format!("Suggestion(rating={}, inputs=[{}])", self.rating, inputs)
+    }
+}
+
+/// Statistics about an analysis. `time_taken` is in seconds.
+#[pyclass(module = "blockfish_native", name = "Statistics", get_all)]
+#[derive(Clone)]
+struct PyStatistics {
+    nodes: usize,
+    iterations: usize,
+    time_taken: f64,
+}
+
+impl From<ai::Stats> for PyStatistics {
+    fn from(stats: ai::Stats) -> Self {
+        Self {
+            nodes: stats.nodes,
+            iterations: stats.iterations,
+            time_taken: stats.time_taken.as_secs_f64(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyStatistics {
+    fn __repr__(&self) -> String {
+        format!(
+            "Statistics(nodes={}, iterations={}, time_taken={})",
+            self.nodes, self.iterations, self.time_taken
+        )
+    }
+}
+
+/// Static evaluation of a matrix. Lower scores are better.
+#[pyclass(module = "blockfish_native", name = "Eval", get_all)]
+#[derive(Clone)]
+struct PyEval {
+    rows: u16,
+    piece_estimate: u16,
+    i_dependencies: u16,
+    attack: u16,
+    score: i64,
+}
+
+#[pymethods]
+impl PyEval {
+    fn __repr__(&self) -> String {
+        format!(
+            "Eval(rows={}, piece_estimate={}, i_dependencies={}, attack={}, score={})",
+            self.rows, self.piece_estimate, self.i_dependencies, self.attack, self.score
+        )
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// AI
+
+/// An instance of the engine. `config` is written as
+/// `<search-limit>[/<parameters>[/<goal>]]` with the search limit in thousands of nodes,
+/// e.g. `"50/5,10,10,10,0/cheese"`.
+#[pyclass(module = "blockfish_native", name = "AI")]
+struct PyAI(ai::AI);
+
+#[pymethods]
+impl PyAI {
+    #[new]
+    #[pyo3(signature = (config = None))]
+    fn new(config: Option<&str>) -> PyResult<Self> {
+        let mut ai = Self(ai::AI::new(Config::default()));
+        if let Some(config) = config {
+            ai.set_config(config)?;
+        }
+        Ok(ai)
+    }
+
+    fn set_config(&mut self, config: &str) -> PyResult<()> {
+        *self.0.config_mut() = config
+            .parse::<Config>()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Maximum number of nodes searched by each analysis.
+    #[getter]
+    fn search_limit(&self) -> usize {
+        self.0.config().search_limit
+    }
+
+    #[setter]
+    fn set_search_limit(&mut self, nodes: usize) {
+        self.0.config_mut().search_limit = nodes;
+    }
+
+    /// Switches to the bundled ruleset called `name`, e.g. `"guideline"` or `"srs-x"`.
+    fn set_ruleset(&mut self, name: &str) -> PyResult<()> {
+        let rules = parse_ruleset(name)?;
+        self.0
+            .set_ruleset(&rules)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Begins an analysis of `snapshot` on a background thread.
+    fn analyze(&mut self, snapshot: &PySnapshot) -> PyResult<PyAnalysis> {
+        if snapshot.0.queue.is_empty() {
+            return Err(PyValueError::new_err("the queue is empty"));
+        }
+        if snapshot.0.matrix.cols() != self.0.cols() {
+            let msg = format!("matrix must be {} cells wide", self.0.cols());
+            return Err(PyValueError::new_err(msg));
+        }
+        Ok(PyAnalysis::new(self.0.analyze(snapshot.0.clone())))
+    }
+
+    /// Evaluates the matrix of `snapshot` without searching.
+    fn static_eval(&self, snapshot: &PySnapshot) -> PyEval {
+        let eval = self.0.static_eval(&snapshot.0);
+        PyEval {
+            rows: eval.rows,
+            piece_estimate: eval.piece_estimate,
+            i_dependencies: eval.i_dependencies,
+            attack: eval.attack,
+            score: eval.score(&self.0.config().parameters),
+        }
+    }
+}
+
+/// Longest time `Analysis.wait()` holds on to the analysis at once.
+const WAIT_SLICE: Duration = Duration::from_millis(10);
+
+/// A running or finished analysis.
+#[pyclass(module = "blockfish_native", name = "Analysis")]
+struct PyAnalysis {
+    analysis: Mutex<ai::Analysis>,
+    stop: ai::StopHandle,
+}
+
+impl PyAnalysis {
+    fn new(analysis: ai::Analysis) -> Self {
+        Self {
+            stop: analysis.stop_handle(),
+            analysis: Mutex::new(analysis),
+        }
+    }
+
+    fn analysis(&self) -> MutexGuard<'_, ai::Analysis> {
+        self.analysis.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[pymethods]
+impl PyAnalysis {
+    /// Collects the analysis's progress without blocking. Returns `True` once it has
+    /// finished.
+    fn poll(&self) -> bool {
+        let mut analysis = self.analysis();
+        loop {
+            match analysis.poll() {
+                Ok(Some(_)) => continue,
+                Ok(None) => return false,
+                Err(ai::AnalysisDone) => return true,
+            }
+        }
+    }
+
+    /// Blocks until the analysis finishes, or until `timeout` seconds have passed.
+    /// Returns `True` if it finished. The GIL is released while waiting.
+    #[pyo3(signature = (timeout = None))]
+    fn wait(&self, py: Python<'_>, timeout: Option<f64>) -> bool {
+        let deadline = timeout.map(|secs| Instant::now() + Duration::from_secs_f64(secs.max(0.0)));
+        py.allow_threads(|| loop {
+            // wait in short slices, so that other threads can use the analysis in between
+            let slice = match deadline {
+                Some(t) => std::cmp::min(t.saturating_duration_since(Instant::now()), WAIT_SLICE),
+                None => WAIT_SLICE,
+            };
+            if self.analysis().wait_timeout(slice) {
+                return true;
+            }
+            if matches!(deadline, Some(t) if Instant::now() >= t) {
+                return false;
+            }
+        })
+    }
+
+    /// Stops the analysis early; it finishes with the suggestions found so far.
+    fn stop(&self) {
+        self.stop.stop();
+    }
+
+    /// Statistics so far, or the final statistics once the analysis has finished.
+    fn statistics(&self) -> PyStatistics {
+        self.analysis().progress().into()
+    }
+
+    /// Suggestions found as of the last `poll` or `wait`, best first. At most
+    /// `max_results` suggestions are returned, each with the inputs for at most
+    /// `max_placements` placements.
+    #[pyo3(signature = (max_results = None, max_placements = None))]
+    fn suggestions(
+        &self,
+        max_results: Option<usize>,
+        max_placements: Option<usize>,
+    ) -> Vec<PySuggestion> {
+        let analysis = self.analysis();
+        let mut moves = analysis.all_moves().collect::<Vec<_>>();
+        moves.sort_by(|&a, &b| analysis.cmp(a, b));
+        moves.truncate(max_results.unwrap_or(moves.len()));
+        let len = max_placements.unwrap_or(std::usize::MAX);
+        moves
+            .into_iter()
+            .map(|m| {
+                let sugg = analysis.suggestion(m, len);
+                PySuggestion {
+                    rating: sugg.rating,
+                    inputs: sugg.inputs.into_iter().map(input_name).collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Stacker
+
+/// A simulated game, for playing out suggestions. `Stacker()` begins a cheese race using
+/// `ruleset`, with the randomizer seeded by `seed` if given.
+#[pyclass(module = "blockfish_native", name = "Stacker", unsendable)]
+struct PyStacker(block_stacker::Stacker);
+
+#[pymethods]
+impl PyStacker {
+    #[new]
+    #[pyo3(signature = (ruleset = "guideline", seed = None))]
+    fn new(ruleset: &str, seed: Option<u64>) -> PyResult<Self> {
+        let cfg = block_stacker::Config {
+            prng_seed: seed,
+            ..block_stacker::Config::default()
+        };
+        let rules = Rc::new(parse_ruleset(ruleset)?);
+        Ok(Self(block_stacker::Stacker::new(rules, cfg)))
+    }
+
+    /// Begins a game from the position of `snapshot` instead of a cheese race. Pieces
+    /// after the snapshot's queue are drawn from the randomizer.
+    #[staticmethod]
+    #[pyo3(signature = (snapshot, ruleset = "guideline", seed = None))]
+    fn from_snapshot(snapshot: &PySnapshot, ruleset: &str, seed: Option<u64>) -> PyResult<Self> {
+        let rules = Rc::new(parse_ruleset(ruleset)?);
+        let mat = &snapshot.0.matrix;
+        if mat.cols() as usize != rules.cols {
+            let msg = format!("matrix must be {} cells wide", rules.cols);
+            return Err(PyValueError::new_err(msg));
+        }
+        let cells = (0..mat.rows())
+            .flat_map(|i| (0..mat.cols()).map(move |j| (i, j)))
+            .filter(|&ij| mat.get(ij))
+            .map(|ij| (ij, 'G'))
+            .collect::<Vec<_>>();
+        let hold = snapshot.0.hold.map(|c| c.as_char());
+        let queue = snapshot
+            .0
+            .queue
+            .iter()
+            .map(Color::as_char)
+            .collect::<Vec<_>>();
+        let cfg = block_stacker::Config {
+            prng_seed: seed,
+            ..block_stacker::Config::default()
+        };
+        let stacker = block_stacker::Stacker::with_position(rules, cfg, cells, hold, &queue);
+        Ok(Self(stacker))
+    }
+
+    /// Returns the current game state, or `None` if there is no current piece (e.g. after
+    /// topping out).
+    fn snapshot(&self) -> Option<PySnapshot> {
+        self.0.snapshot().map(PySnapshot)
+    }
+
+    /// Performs each of `inputs`, e.g. the inputs of a suggestion.
+    fn run(&mut self, inputs: Vec<String>) -> PyResult<()> {
+        let inputs = inputs
+            .iter()
+            .map(|name| parse_input(name))
+            .collect::<PyResult<Vec<_>>>()?;
+        self.0.run(inputs);
+        Ok(())
+    }
+
+    /// Number of cheese lines left to be added, or `None` if unlimited.
+    #[getter]
+    fn garbage_remaining(&self) -> Option<usize> {
+        self.0.garbage_remaining()
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+//////////////////////////////////////////////////////////////////////////////////////////
+// Module
+
+#[pymodule]
+fn blockfish_native(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", blockfish::version())?;
+    m.add_class::<PySnapshot>()?;
+    m.add_class::<PySuggestion>()?;
+    m.add_class::<PyStatistics>()?;
+    m.add_class::<PyEval>()?;
+    m.add_class::<PyAI>()?;
+    m.add_class::<PyAnalysis>()?;
+    m.add_class::<PyStacker>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let ss = PySnapshot::new("LTJ", Some("I"), vec!["GGGG GGGGG".into()], 10).unwrap();
+        assert_eq!(ss.__str__(), "4x.5x I LTJ");
+        assert_eq!(ss.matrix(), ["GGGG GGGGG"]);
+        assert_eq!(ss.hold().as_deref(), Some("I"));
+        assert!(PySnapshot::new("LT", Some("IO"), vec![], 10).is_err());
+        assert!(PySnapshot::new("L?", None, vec![], 10).is_err());
+        assert!(PySnapshot::new("LT", None, vec!["GG".into()], 10).is_err());
+        assert!(PySnapshot::new("LT", None, vec![], 0).is_err());
+        assert!(PySnapshot::new("LT", None, vec![], 17).is_err());
+        assert_eq!(PySnapshot::new("LT", None, vec![], 16).unwrap().cols(), 16);
+        assert_eq!(PySnapshot::parse("10. - LT").unwrap().queue(), "LT");
+    }
+
+    #[test]
+    fn test_analyze_and_play() {
+        let mut ai = PyAI::new(Some("5")).unwrap();
+        let ss = PySnapshot::parse("x9./x9. - IOL").unwrap();
+        let analysis = ai.analyze(&ss).unwrap();
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| assert!(analysis.wait(py, None)));
+        assert!(analysis.poll());
+        assert!(analysis.statistics().nodes > 0);
+        let suggs = analysis.suggestions(Some(3), Some(1));
+        assert_eq!(suggs.len(), 3);
+        assert!(suggs[0].rating <= suggs[1].rating);
+
+        let mut stacker = PyStacker::from_snapshot(&ss, "guideline", Some(0)).unwrap();
+        let inputs = suggs[0].inputs.iter().map(|s| s.to_string()).collect();
+        stacker.run(inputs).unwrap();
+        let next = stacker.snapshot().unwrap();
+        assert_ne!(next.matrix(), ss.matrix());
+        assert!(stacker.run(vec!["jump".into()]).is_err());
+
+        // the matrix must be as wide as the ruleset's
+        let narrow = PySnapshot::new("TIO", None, vec!["G ".into()], 2).unwrap();
+        assert!(ai.analyze(&narrow).is_err());
+    }
+
+    #[test]
+    fn test_static_eval() {
+        let ai = PyAI::new(None).unwrap();
+        let ss = PySnapshot::parse("x9./x9. - -").unwrap();
+        let eval = ai.static_eval(&ss);
+        assert_eq!(eval.rows, 2);
+        assert_eq!(eval.attack, 0);
+    }
+
+    #[test]
+    fn test_stop_while_waiting() {
+        let mut ai = PyAI::new(None).unwrap();
+        ai.set_search_limit(std::usize::MAX);
+        let ss = PySnapshot::parse("10. - LJSZOTILJSZOTI").unwrap();
+        pyo3::prepare_freethreaded_python();
+        let analysis = Python::with_gil(|py| Py::new(py, ai.analyze(&ss).unwrap()).unwrap());
+        let stopper = {
+            let analysis = Python::with_gil(|py| analysis.clone_ref(py));
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                Python::with_gil(|py| {
+                    let analysis = analysis.borrow(py);
+                    analysis.statistics();
+                    analysis.stop();
+                })
+            })
+        };
+        // would never finish unless `stop` gets through while waiting
+        Python::with_gil(|py| assert!(analysis.borrow(py).wait(py, None)));
+        stopper.join().unwrap();
+    }
+}
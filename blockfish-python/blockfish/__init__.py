@@ -1,2 +1,7 @@
-from blockfish.ai import AI, Snapshot, Statistics, Suggestion
-__all__ = ['AI', 'Snapshot', 'Statistics', 'Suggestion']
+from blockfish.ai import (
+    AI, BlockfishError, Capabilities, Snapshot, Statistics, Suggestion, Parameters, Eval,
+)
+__all__ = [
+    'AI', 'BlockfishError', 'Capabilities', 'Snapshot', 'Statistics', 'Suggestion',
+    'Parameters', 'Eval',
+]
@@ -5,7 +5,11 @@ from blockfish.ipc import IPC, create_subprocess_ipc
 import blockfish.blockfish_pb2 as protos
 
 
-INPUT_NAMES = ('left', 'right', 'cw', 'ccw', 'hold', 'sd', 'hd')
+INPUT_NAMES = ('left', 'right', 'cw', 'ccw', 'hold', 'sd', 'hd', 'flip')
+
+# range of protocol versions this module can speak
+MIN_PROTOCOL_VERSION = 1
+MAX_PROTOCOL_VERSION = 1
 
 Snapshot = namedtuple('Snapshot', [
     'queue',
@@ -24,12 +28,50 @@ Suggestion = namedtuple('Suggestion', [
     'inputs',
 ])
 
+# defaults match the engine's default parameters
+Parameters = namedtuple('Parameters', [
+    'row_factor',
+    'piece_estimate_factor',
+    'i_dependency_factor',
+    'piece_penalty',
+    'attack_factor',
+], defaults = (5, 10, 10, 10, 0))
+
+Capabilities = namedtuple('Capabilities', [
+    'rulesets',
+    'goals',
+    'progress',
+    'cancellation',
+    'batches',
+    'perfect_clears',
+    'max_node_limit',
+])
+
+Eval = namedtuple('Eval', [
+    'rows',
+    'piece_estimate',
+    'i_dependencies',
+    'attack',
+    'score',
+])
+
+
+class BlockfishError(Exception):
+    """Raised when the engine rejects a request. `code` is the name of the error code,
+    e.g. 'INVALID_SNAPSHOT'."""
+    def __init__(self, code, message):
+        super().__init__(message)
+        self.code = code
+        self.message = message
+
 
 class AI:
     def __init__(self):
         self._task = None
-        self._next_id = 0
+        # id 0 is reserved for requests without an id
+        self._next_id = 1
         self._analysis = dict()
+        self._progress = dict()
         self._deinit()
 
     async def start(self):
@@ -38,6 +80,8 @@ class AI:
             self._ipc = None
             self._task = asyncio.create_task(self._go())
         await self._init.wait()
+        if self._init_error is not None:
+            raise self._init_error
         return self.version
 
     def shutdown(self):
@@ -45,7 +89,7 @@ class AI:
             self._task.cancel()
         self._deinit()
 
-    async def analyze(self, snapshot, **cfg):
+    async def analyze(self, snapshot, on_progress = None, **cfg):
         # ensure IPC is connected
         await self.start()
         ipc = self._ipc
@@ -53,22 +97,71 @@ class AI:
         id = self._next_id
         self._next_id += 1
         self._analysis[id] = asyncio.Queue(maxsize = 1)
-        # build and send request(s)
-        await ipc.send(
-            to_set_config_proto(cfg),
-            to_analyze_proto(id, snapshot)
-        )
+        if on_progress is not None:
+            self._progress[id] = on_progress
+        # build and send request, with the config for just this analysis
+        await ipc.send(to_analyze_proto(id, snapshot, cfg))
         # wait for callback
         fin = await self._analysis[id].get()
         del self._analysis[id]
+        self._progress.pop(id, None)
+        if isinstance(fin, BlockfishError):
+            raise fin
         # parse response data
         stats = from_stats_proto(fin.stats)
         suggs = [from_suggestion_proto(s) for s in fin.suggestions]
         return suggs, stats
 
+    async def analyze_batch(self, snapshots, max_running = 0, **cfg):
+        await self.start()
+        # one id for the batch, followed by one for each snapshot
+        batch_id = self._next_id
+        self._next_id += len(snapshots) + 1
+        ids = range(batch_id, self._next_id)
+        # all of the batch's responses go to the same queue, as each analysis finishes
+        queue = asyncio.Queue()
+        for id in ids:
+            self._analysis[id] = queue
+        await self._ipc.send(
+            to_analyze_batch_proto(batch_id, snapshots, max_running, cfg))
+        results = [None] * len(snapshots)
+        while True:
+            res = await queue.get()
+            if isinstance(res, BlockfishError) or res.id == batch_id:
+                break
+            suggs = [from_suggestion_proto(s) for s in res.suggestions]
+            results[res.id - batch_id - 1] = (suggs, from_stats_proto(res.stats))
+        for id in ids:
+            del self._analysis[id]
+        if isinstance(res, BlockfishError):
+            raise res
+        return results, from_stats_proto(res.stats)
+
+    async def static_eval(self, snapshot, parameters = None):
+        await self.start()
+        id = self._next_id
+        self._next_id += 1
+        self._analysis[id] = asyncio.Queue(maxsize = 1)
+        await self._ipc.send(to_static_eval_proto(id, snapshot, parameters))
+        res = await self._analysis[id].get()
+        del self._analysis[id]
+        if isinstance(res, BlockfishError):
+            raise res
+        return Eval(
+            rows = res.rows,
+            piece_estimate = res.piece_estimate,
+            i_dependencies = res.i_dependencies,
+            attack = res.attack,
+            score = res.score,
+        )
+
     def _deinit(self):
         self.version = None
+        # both None if the engine predates protocol versions
+        self.protocol_version = None
+        self.capabilities = None
         self._init = None
+        self._init_error = None
         self._ipc = None
         self._task = None
 
@@ -82,29 +175,114 @@ class AI:
                 break
             tag = res.WhichOneof('res')
             if tag == 'greeting':
-                self.version = res.greeting.version
+                grt = res.greeting
+                self.version = grt.version
+                if grt.max_protocol_version == 0:
+                    # old engine, nothing to negotiate
+                    self._init.set()
+                    continue
+                self.capabilities = from_capabilities_proto(grt.capabilities)
+                version = min(MAX_PROTOCOL_VERSION, grt.max_protocol_version)
+                if version < max(MIN_PROTOCOL_VERSION, grt.min_protocol_version):
+                    self._init_error = BlockfishError(
+                        'UNSUPPORTED_PROTOCOL',
+                        'engine speaks protocol versions {} to {}, expected {} to {}'
+                        .format(grt.min_protocol_version, grt.max_protocol_version,
+                                MIN_PROTOCOL_VERSION, MAX_PROTOCOL_VERSION))
+                    self._init.set()
+                    continue
+                await ipc.send(to_hello_proto(version))
+            elif tag == 'hello':
+                self.protocol_version = res.hello.protocol_version
                 self._init.set()
             elif tag == 'finished':
                 fin = res.finished
                 self._analysis[fin.id].put_nowait(fin)
+            elif tag == 'static_eval':
+                ev = res.static_eval
+                self._analysis[ev.id].put_nowait(ev)
+            elif tag == 'batch_finished':
+                fin = res.batch_finished
+                self._analysis[fin.id].put_nowait(fin)
+            elif tag == 'error':
+                err = res.error
+                code = protos.Response.Error.Code.Name(err.code)
+                queue = self._analysis.get(err.id)
+                if queue is not None:
+                    queue.put_nowait(BlockfishError(code, err.message))
+                elif not self._init.is_set():
+                    # rejected "hello"
+                    self._init_error = BlockfishError(code, err.message)
+                    self._init.set()
+            elif tag == 'progress':
+                prog = res.progress
+                callback = self._progress.get(prog.id)
+                if callback is not None:
+                    suggs = [from_suggestion_proto(s) for s in prog.suggestions]
+                    callback(suggs, from_stats_proto(prog.stats))
         await ipc.kill()
 
-def to_set_config_proto(cfg):
+def to_hello_proto(version):
     req = protos.Request()
+    req.hello.protocol_version = version
+    return req
+
+def from_capabilities_proto(caps):
+    return Capabilities(
+        rulesets = list(caps.rulesets),
+        goals = list(caps.goals),
+        progress = caps.progress,
+        cancellation = caps.cancellation,
+        batches = caps.batches,
+        perfect_clears = caps.perfect_clears,
+        max_node_limit = caps.max_node_limit,
+    )
+
+def to_config_proto(cfg, proto):
     if 'node_limit' in cfg:
-        req.set_config.node_limit = cfg['node_limit']
+        proto.node_limit = cfg['node_limit']
     if 'suggestion_limit' in cfg:
-        req.set_config.max_results = cfg['suggestion_limit']
-    return req
+        proto.max_results = cfg['suggestion_limit']
+    if 'progress_interval' in cfg:
+        proto.progress_interval_millis = int(cfg['progress_interval'] * 1000)
+    if 'parameters' in cfg:
+        to_parameters_proto(cfg['parameters'], proto.parameters)
 
-def to_analyze_proto(id, ss):
+def to_parameters_proto(params, proto):
+    for name, value in params._asdict().items():
+        setattr(proto, name, value)
+
+def to_analyze_proto(id, ss, cfg):
     req = protos.Request()
     req.analyze.id = id
+    to_config_proto(cfg, req.analyze.config)
+    to_snapshot_proto(ss, req.analyze.snapshot)
+    return req
+
+def to_analyze_batch_proto(id, snapshots, max_running, cfg):
+    req = protos.Request()
+    req.analyze_batch.id = id
+    req.analyze_batch.max_running = max_running
+    to_config_proto(cfg, req.analyze_batch.config)
+    for i, ss in enumerate(snapshots):
+        item = req.analyze_batch.items.add()
+        item.id = id + 1 + i
+        to_snapshot_proto(ss, item.snapshot)
+    return req
+
+def to_snapshot_proto(ss, proto):
     if ss.queue is not None:
-        req.analyze.snapshot.queue = ss.queue
+        proto.queue = ss.queue
     if ss.hold is not None:
-        req.analyze.snapshot.hold = ss.hold
-    req.analyze.snapshot.rows.extend(ss.matrix)
+        proto.hold = ss.hold
+    proto.rows.extend(ss.matrix)
+
+def to_static_eval_proto(id, ss, parameters):
+    req = protos.Request()
+    req.static_eval.id = id
+    to_snapshot_proto(ss, req.static_eval.snapshot)
+    if parameters is not None:
+        to_parameters_proto(parameters, req.static_eval.parameters)
     return req
 
 def from_stats_proto(proto):
@@ -1,7 +1,16 @@
 #!/usr/bin/sh
-cargo run \
-      -q --release \
-      --manifest-path ./blockfish-engine/Cargo.toml \
-      --features gen-shtb \
-      --bin blockfish-gen-shtb \
-      > ./support/test/srs-shape-table.json
+gen() {
+    cargo run \
+          -q --release \
+          --manifest-path ./blockfish-engine/Cargo.toml \
+          --features gen-shtb \
+          --bin blockfish-gen-shtb \
+          -- "$1" \
+          > "./support/test/$2-shape-table.json"
+}
+gen guideline srs
+gen ars ars
+gen classic classic
+gen srs+ srs-plus
+gen srs-x srs-x
+gen pentomino pentomino